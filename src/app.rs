@@ -1,50 +1,262 @@
-use chatgpt::config::ChatGPTEngine;
-use chatgpt::config::ModelConfigurationBuilder;
-use chatgpt::prelude::ChatGPT;
-use chatgpt::prelude::Conversation;
-use chatgpt::types::ChatMessage;
-use chatgpt::types::Role;
 use egui::Vec2;
 use egui_notify::Toasts;
+use std::collections::{BTreeSet, HashMap};
 use std::format;
+use std::path::PathBuf;
 use std::println;
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
 use std::time::Duration;
 
 use egui_extras::RetainedImage;
-use tokio::sync::*;
 
-use crate::settings;
-use crate::settings::Settings;
+use oxidized_gpt::appearance::{self, Appearance, Rgb, Theme};
+use oxidized_gpt::attachment::{self, Attachment, Part};
+use oxidized_gpt::audio::{self, Recording, SpeechCache, SpeechFormat};
+use oxidized_gpt::client::Client;
+use oxidized_gpt::commands::{self, Command};
+use oxidized_gpt::documents::{Library, Reindexed};
+use oxidized_gpt::drafts::Drafts;
+use oxidized_gpt::export::{self, Format};
+use oxidized_gpt::fonts::{self, FontFile, Script};
+use oxidized_gpt::i18n::{Catalog, Language};
+use oxidized_gpt::images;
+use oxidized_gpt::import;
+use oxidized_gpt::prompt_history::PromptHistory;
+use oxidized_gpt::search::{self, Filter, Hit, SearchIndex};
+use oxidized_gpt::session::ChatSession;
+use oxidized_gpt::settings;
+use oxidized_gpt::settings::{DocumentOptions, ImageOptions, Settings};
+use oxidized_gpt::shortcuts::{Action, Keymap, SendMode, Shortcut};
+use oxidized_gpt::store::{Citation, Message, Role, Store, StoredConversation, ToolCall};
+use oxidized_gpt::templates::{self, Pack, Template};
+use oxidized_gpt::tools::{self, Approvals, Confirm, Registry};
 
 pub struct App {
-    conversation: Option<Arc<Mutex<Conversation>>>,
+    session: Option<ChatSession>,
     pmt: String,
-    history: Vec<ChatMessage>,
+    history: Vec<Message>,
+    /// version of the session `history` was copied from
+    history_version: u64,
+    store: Store,
+    /// saved conversations listed in the side panel
+    conversations: Vec<StoredConversation>,
     ai_icon: RetainedImage,
     user_icon: RetainedImage,
     system_icon: RetainedImage,
-    send_icon: RetainedImage,
     is_side_panel_expanded: bool,
     settings: Settings,
     toasts: Toasts,
     app_name: String,
     current_role: settings::Role,
+    /// the current profile as edited in the side panel, `None` until the panel is shown
+    profile_form: Option<ProfileForm>,
+    /// indexes of the messages picked for export, `None` when not picking
+    export_selection: Option<BTreeSet<usize>>,
+    /// the fonts found for the scripts egui's own fonts miss, also embedded in PDF exports so
+    /// that non-latin text shows up
+    fallback_fonts: Vec<(Script, FontFile)>,
+    /// the extra font directories of the last search for `fallback_fonts`, finished or not
+    font_scan: Option<Vec<PathBuf>>,
+    /// whether the appearance changed since it was last stored, it is only stored once the
+    /// pointer lets go so that dragging in the colour picker does not write every frame
+    appearance_unsaved: bool,
+    search_index: SearchIndex,
+    /// the search window, `None` when closed
+    search: Option<SearchForm>,
+    /// index of the message to scroll to on the next frame
+    jump_to: Option<usize>,
+    /// index of a message and the words to highlight in it
+    highlight: Option<(usize, String)>,
+    /// the find bar, `None` when closed
+    find_bar: Option<FindBar>,
+    /// files to send with the next prompt
+    attachments: Vec<Attachment>,
+    /// `data:` URLs and thumbnails of the images to send with the next prompt
+    images: Vec<(String, RetainedImage)>,
+    /// thumbnails of the images in the history by message index, image index and URL length,
+    /// which tells an image replaced by a retry apart, `None` when the image could not be decoded
+    thumbnails: HashMap<(usize, usize, usize), Option<RetainedImage>>,
+    /// results of the work done in the background
+    done: mpsc::Receiver<Done>,
+    done_tx: mpsc::Sender<Done>,
+    /// number of transcriptions not received yet
+    transcribing: usize,
+    /// indexes of the messages being read aloud
+    reading: BTreeSet<usize>,
+    /// `None` when its directory could not be created
+    speech_cache: Option<SpeechCache>,
+    /// local tools the model may call while `settings.tools.enabled`
+    tools: Arc<Registry>,
+    /// tool calls waiting for the user to allow or decline them
+    approvals: Approvals,
+    confirm: Confirm,
+    /// indexes of the roles' document folders, `None` when their directory could not be created
+    library: Option<Library>,
+    /// number of document folders being indexed
+    indexing: usize,
+    /// the form asking for the variables of a template, `None` when closed
+    template_form: Option<TemplateForm>,
+    /// the window editing a template, `None` when closed
+    template_editor: Option<TemplateEditor>,
+    /// position of the highlighted suggestion of the slash command popup
+    completion: usize,
+    /// the prompts sent before, recalled with Up/Down and Ctrl+R
+    prompt_history: PromptHistory,
+    /// position in `prompt_history` while going through it with Up/Down, with the prompt
+    /// that was being written before
+    recalling: Option<(usize, String)>,
+    /// the Ctrl+R window searching the prompts sent before, `None` when closed
+    recall_search: Option<RecallSearch>,
+    /// the unsent prompts of the conversations left while writing them
+    drafts: Drafts,
+    /// key in `drafts` of the conversation `pmt` is written for
+    draft_key: String,
+    /// whether the window listing the shortcuts is shown
+    cheat_sheet: bool,
+    /// the action whose new shortcut is the next key pressed
+    capturing: Option<Action>,
+    /// the texts of the window in the language of the settings or the system
+    i18n: Catalog,
+    /// the appearance the style and fonts were set for, with the theme of the desktop then
+    applied_appearance: Option<(Appearance, Option<eframe::Theme>)>,
+    /// zoom and font size of the sliders, kept apart from the settings while being dragged
+    zoom: f32,
+    font_size: f32,
 }
 
-impl App {
-    pub fn new(cc: &eframe::CreationContext<'_>, app_name: &str) -> Self {
-        let config_path = confy::get_configuration_file_path(app_name, None);
-        println!("config_path:{:#?}", config_path);
-        let settings: Settings = confy::load(app_name, None).unwrap();
-        let current_role = settings.role_list.get(0).unwrap().to_owned();
+/// The fields of a profile edited in the side panel, only written to the settings on Save so
+/// that a half-typed name never renames the profile or gets stored along with other settings
+struct ProfileForm {
+    /// name of the profile edited, which stays its name in the settings until saved
+    profile: String,
+    name: String,
+    provider: settings::Provider,
+    api_key: String,
+    api_url: String,
+    model: String,
+}
+
+impl ProfileForm {
+    fn new(profile: &settings::Profile) -> Self {
+        Self {
+            profile: profile.name.clone(),
+            name: profile.name.clone(),
+            provider: profile.provider,
+            api_key: profile.api_key.clone(),
+            api_url: profile.api_url.clone(),
+            model: profile.model.clone(),
+        }
+    }
+}
+
+/// the query of the Ctrl+R window and the highlighted match
+#[derive(Default)]
+struct RecallSearch {
+    query: String,
+    selected: usize,
+    /// whether the query field got the focus after opening
+    focused: bool,
+}
+
+/// models suggested after `/model`, besides the ones of the profile and the conversation
+const MODELS: [&str; 5] = [
+    "gpt-4o",
+    "gpt-4o-mini",
+    "gpt-4-turbo",
+    "gpt-4",
+    "gpt-3.5-turbo",
+];
+
+/// a suggestion of the slash command popup
+struct Completion {
+    /// the prompt after picking it
+    text: String,
+    label: String,
+    detail: String,
+}
+
+/// the values typed in for the variables of template `index`
+struct TemplateForm {
+    index: usize,
+    values: HashMap<String, String>,
+}
+
+/// a template being written, `index` is `None` for a new one
+struct TemplateEditor {
+    index: Option<usize>,
+    template: Template,
+}
 
-        println!("{:#?}", settings);
+/// the inputs of the search window and their results
+#[derive(Default)]
+struct SearchForm {
+    query: String,
+    role: Option<Role>,
+    /// YYYY-MM-DD
+    since: String,
+    /// YYYY-MM-DD
+    until: String,
+    model: Option<String>,
+    conversation: Option<String>,
+    hits: Vec<Hit>,
+    /// whether the query field got the focus after opening
+    focused: bool,
+}
+
+/// work finished in the background, picked up by the next frame
+enum Done {
+    /// the text of a recording, to add to the prompt
+    Transcribed(Result<String, String>),
+    /// the audio of message `index`, to play or to save in the `save` format
+    ReadAloud {
+        index: usize,
+        save: Option<SpeechFormat>,
+        result: Result<PathBuf, String>,
+    },
+    /// a document folder brought up to date
+    Indexed(Result<Reindexed, String>),
+    /// the fonts found for the scripts egui's own fonts miss, searching `dirs` first
+    FontsFound {
+        dirs: Vec<PathBuf>,
+        fonts: Vec<(Script, FontFile)>,
+    },
+}
+
+/// the Ctrl+F bar finding text in the open conversation
+#[derive(Default)]
+struct FindBar {
+    query: String,
+    /// position of the selected match among all matches
+    current: usize,
+    /// message index and byte range of the selected match
+    selected: Option<(usize, std::ops::Range<usize>)>,
+    /// whether the query field got the focus after opening
+    focused: bool,
+}
+
+impl App {
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        app_name: &str,
+        settings: Settings,
+        store: Store,
+    ) -> Self {
+        let current_role = settings.current_profile().role_list[0].to_owned();
+        // without a readable drafts file the drafts are only kept until the app closes
+        let drafts = Drafts::open(app_name).unwrap_or_default();
+        let draft_key = Drafts::new_conversation_key(&settings.current_profile, &current_role.name);
+        let (done_tx, done) = mpsc::channel();
+        let approvals = Approvals::default();
+        let ctx = cc.egui_ctx.clone();
+        let confirm = approvals.confirm(move || ctx.request_repaint());
 
         Self {
-            conversation: None,
-            pmt: "".to_string(),
+            session: None,
+            pmt: drafts.get(&draft_key).to_owned(),
             history: Vec::new(),
+            history_version: 0,
+            store,
+            conversations: Vec::new(),
             ai_icon: RetainedImage::from_image_bytes(
                 "chatgpt_logo.jpeg",
                 include_bytes!("../media/chatgpt_logo.jpeg"),
@@ -60,68 +272,387 @@ impl App {
                 include_bytes!("../media/system.png"),
             )
             .unwrap(),
-            send_icon: RetainedImage::from_image_bytes(
-                "send.png",
-                include_bytes!("../media/Send.png"),
-            )
-            .unwrap(),
             is_side_panel_expanded: false,
-            settings,
             toasts: Toasts::default(),
             app_name: app_name.to_owned(),
             current_role,
+            profile_form: None,
+            export_selection: None,
+            fallback_fonts: Vec::new(),
+            font_scan: None,
+            appearance_unsaved: false,
+            search_index: SearchIndex::default(),
+            search: None,
+            jump_to: None,
+            highlight: None,
+            find_bar: None,
+            attachments: Vec::new(),
+            images: Vec::new(),
+            thumbnails: HashMap::new(),
+            done,
+            done_tx,
+            transcribing: 0,
+            reading: BTreeSet::new(),
+            speech_cache: SpeechCache::open(app_name).ok(),
+            tools: Arc::new(Registry::local(&settings.tools)),
+            approvals,
+            confirm,
+            library: Library::open(app_name).ok(),
+            indexing: 0,
+            template_form: None,
+            template_editor: None,
+            completion: 0,
+            // without a readable history file the prompts are only kept until the app closes
+            prompt_history: PromptHistory::open(app_name).unwrap_or_default(),
+            recalling: None,
+            recall_search: None,
+            drafts,
+            draft_key,
+            cheat_sheet: false,
+            capturing: None,
+            i18n: Catalog::new(settings.language.unwrap_or_else(Language::system)),
+            applied_appearance: None,
+            zoom: settings.appearance.zoom,
+            font_size: settings.appearance.font_size,
+            settings,
         }
     }
 
-    fn create_conversation(&self) -> Conversation {
+    fn create_session(&self) -> chatgpt::Result<ChatSession> {
         println!("new conversation with role {:#?}", self.current_role);
-        let url: &'static str = Box::leak(self.settings.api_url.clone().into_boxed_str());
-        ChatGPT::new_with_config(
-            self.settings.api_key.clone(),
-            ModelConfigurationBuilder::default()
-                .api_url(url)
-                .temperature(1.0)
-                .engine(ChatGPTEngine::Gpt35Turbo)
-                .build()
-                .unwrap(),
-        )
-        .unwrap()
-        .new_conversation_directed(self.current_role.prompt.clone())
+        let session =
+            ChatSession::new(&self.settings, &self.current_role)?.with_store(self.store.clone());
+        Ok(self.equip(session))
+    }
+
+    /// gives the session the role's documents, and the local tools when they are switched on
+    fn equip(&self, session: ChatSession) -> ChatSession {
+        let session = match &self.library {
+            Some(library) => session.with_documents(library.clone()),
+            None => session,
+        };
+        if self.settings.tools.enabled {
+            session.with_tools(self.tools.clone(), self.confirm.clone())
+        } else {
+            session.without_tools()
+        }
+    }
+
+    /// drop the current conversation, the next prompt starts a new one
+    fn reset_session(&mut self) {
+        self.session = None;
+        self.history.clear();
+        self.history_version = 0;
+        self.export_selection = None;
+        self.jump_to = None;
+        self.highlight = None;
+        self.thumbnails.clear();
+    }
+
+    /// the key in `drafts` of the conversation shown
+    fn current_draft_key(&self) -> String {
+        match &self.session {
+            Some(session) => session.id(),
+            None => Drafts::new_conversation_key(
+                &self.settings.current_profile,
+                &self.current_role.name,
+            ),
+        }
+    }
+
+    /// After switching to another conversation, keeps the prompt as the draft of the one left
+    /// and brings back the draft of the one shown.
+    fn swap_draft(&mut self, ctx: &egui::Context) {
+        let key = self.current_draft_key();
+        if key == self.draft_key {
+            return;
+        }
+        self.save_draft();
+        self.pmt = self.drafts.get(&key).to_owned();
+        self.draft_key = key;
+        self.recalling = None;
+        self.completion = 0;
+        move_cursor_to_end(ctx, egui::Id::new("prompt"), &self.pmt);
+    }
+
+    /// keeps the prompt as the draft of the conversation it is written for
+    fn save_draft(&mut self) {
+        if let Err(err) = self.drafts.set(&self.draft_key, &self.pmt) {
+            self.toasts
+                .error(
+                    self.i18n
+                        .format("draft_save_failed", &[("err", err.to_string())]),
+                )
+                .set_duration(None);
+        }
+    }
+
+    /// continue a saved conversation with the profile and role it was created with
+    fn open_conversation(&mut self, conversation: StoredConversation) {
+        if self.settings.profile(&conversation.profile).is_some() {
+            self.settings.current_profile = conversation.profile.clone();
+        }
+        let profile = self.settings.current_profile();
+        self.current_role = profile
+            .role_list
+            .iter()
+            .find(|r| r.name == conversation.role)
+            .unwrap_or(&profile.role_list[0])
+            .clone();
+        self.reset_session();
+        match ChatSession::open(&self.settings, conversation) {
+            Ok(session) => {
+                self.history = session.history();
+                self.history_version = session.version();
+                let session = self.equip(session.with_store(self.store.clone()));
+                self.session = Some(session);
+            }
+            Err(err) => {
+                self.toasts
+                    .error(self.i18n.format("open_failed", &[("err", err.to_string())]))
+                    .set_duration(None);
+            }
+        }
+    }
+
+    fn render_profile_list(&mut self, ui: &mut egui::Ui) {
+        // the running conversation keeps the profile it was created under
+        let hover_text = match self.session.as_ref().map(|s| s.profile()) {
+            Some(profile) => self
+                .i18n
+                .format("switch_profile_running", &[("profile", profile)]),
+            None => self.i18n.text("switch_profile").to_owned(),
+        };
+        let mut selected = None;
+        ui.menu_button(self.settings.current_profile.clone(), |ui| {
+            for profile in self.settings.profiles.iter() {
+                let is_current_profile = profile.name == self.settings.current_profile;
+                if ui
+                    .selectable_label(is_current_profile, profile.name.clone())
+                    .clicked()
+                {
+                    if !is_current_profile {
+                        selected = Some(profile.name.clone());
+                    }
+                    ui.close_menu();
+                }
+            }
+        })
+        .response
+        .on_hover_text(hover_text);
+        if let Some(name) = selected {
+            self.switch_profile(name);
+        }
+    }
+
+    /// make `name` the current profile and start over with its first role
+    fn switch_profile(&mut self, name: String) {
+        self.settings.current_profile = name;
+        self.current_role = self.settings.current_profile().role_list[0].clone();
+        self.reset_session();
+    }
+
+    /// Writes the profile form into the current profile and saves the settings, refusing empty
+    /// names and names of other profiles.
+    ///
+    /// The saved conversations of a renamed profile are moved over to the new name.
+    fn save_profile(&mut self) {
+        let Some(form) = &self.profile_form else {
+            return;
+        };
+        let name = form.name.trim().to_owned();
+        let old = self.settings.current_profile().name.clone();
+        let error = if name.is_empty() {
+            Some("profile_name_empty")
+        } else if name != old && self.settings.profile(&name).is_some() {
+            Some("profile_names_taken")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            self.toasts.error(self.i18n.text(error)).set_duration(None);
+            return;
+        }
+        let profile = self.settings.current_profile_mut();
+        profile.name = name.clone();
+        profile.provider = form.provider;
+        profile.api_key = form.api_key.clone();
+        profile.api_url = form.api_url.clone();
+        profile.model = form.model.clone();
+        self.settings.current_profile = name.clone();
+        self.profile_form = Some(ProfileForm::new(self.settings.current_profile()));
+        if name != old {
+            if let Some(session) = &self.session {
+                session.rename_profile(&old, &name);
+            }
+            if let Err(err) = self.store.rename_profile(&old, &name) {
+                self.toasts
+                    .error(self.i18n.format("save_failed", &[("err", err.to_string())]))
+                    .set_duration(None);
+            }
+            self.conversations = self.store.list();
+            // the prompt being written stays, now under the new name
+            self.draft_key = self.current_draft_key();
+        }
+        if self.store_settings() {
+            self.toasts
+                .success(self.i18n.text("saved"))
+                .set_duration(Some(Duration::from_secs(1)));
+        }
+    }
+
+    /// Saves the settings, telling when that fails.
+    ///
+    /// Every part of the window saves through here. Profile edits only reach the settings
+    /// through [`App::save_profile`], so no save stores a half-typed profile.
+    fn store_settings(&mut self) -> bool {
+        match self.settings.store(&self.app_name) {
+            Ok(()) => true,
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("settings_save_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+                false
+            }
+        }
     }
 
     fn render_role_list(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let mut selected = None;
         ui.menu_image_button(self.ai_icon.texture_id(ctx), Vec2::splat(24.0), |ui| {
-            for role in self.settings.role_list.iter() {
+            for role in self.settings.current_profile().role_list.iter() {
                 let is_current_role = role.eq(&self.current_role);
                 if ui
                     .selectable_label(is_current_role, role.name.clone())
                     .clicked()
                 {
                     if !is_current_role {
-                        self.current_role = role.clone();
-                        self.conversation = None;
-                        self.history.clear();
-                        println!("new role!! {:#?}", role);
+                        selected = Some(role.clone());
                     }
                     println!("current_role={:#?} ", self.current_role);
                     ui.close_menu();
                 }
             }
         });
+        if let Some(role) = selected {
+            println!("new role!! {:#?}", role);
+            self.current_role = role;
+            self.reset_session();
+        }
     }
 
-    ///to avoid the historical messages disappear before the response be received from openai
-    fn sync_new_message(&mut self) -> bool {
-        match self.conversation.clone() {
-            Some(conversation) => {
-                if let Ok(conversation) = conversation.try_lock_owned() {
-                    if self.history.len() <= conversation.history.len()
-                        && self.history.last() != conversation.history.last()
+    fn render_export_menu(&mut self, ui: &mut egui::Ui) {
+        let mut exported = None;
+        ui.add_enabled_ui(self.session.is_some(), |ui| {
+            ui.menu_button("📤", |ui| {
+                for format in Format::ALL {
+                    if ui
+                        .button(
+                            self.i18n
+                                .format("export_as", &[("format", format.name().to_string())]),
+                        )
+                        .clicked()
                     {
-                        self.history = conversation.history.clone();
-                        return true;
+                        exported = Some(format);
+                        ui.close_menu();
                     }
                 }
+                ui.separator();
+                let picking = self.export_selection.is_some();
+                let label = if picking {
+                    self.i18n.text("export_all")
+                } else {
+                    self.i18n.text("pick_messages")
+                };
+                if ui.button(label).clicked() {
+                    self.export_selection = if picking { None } else { Some(BTreeSet::new()) };
+                    ui.close_menu();
+                }
+            })
+            .response
+            .on_hover_text(match &self.export_selection {
+                Some(selection) => self
+                    .i18n
+                    .format("export_picked", &[("count", selection.len().to_string())]),
+                None => self.i18n.text("export").to_owned(),
+            });
+        });
+        if let Some(format) = exported {
+            self.export_conversation(format);
+        }
+    }
+
+    /// write the current conversation, or the picked messages, to the downloads folder
+    fn export_conversation(&mut self, format: Format) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        let conversation = session.conversation();
+        let selection: Vec<usize> = self.export_selection.iter().flatten().copied().collect();
+        let font = match format {
+            Format::Pdf => self.pdf_font(),
+            _ => None,
+        };
+        let result =
+            export::export(&conversation, &selection, format, font.as_deref()).and_then(|bytes| {
+                let dir = match directories::UserDirs::new()
+                    .and_then(|dirs| dirs.download_dir().map(PathBuf::from))
+                {
+                    Some(dir) => dir,
+                    None => settings::config_dir(&self.app_name)?.join("exports"),
+                };
+                std::fs::create_dir_all(&dir)?;
+                let path = dir.join(export::file_name(&conversation, format));
+                std::fs::write(&path, bytes)?;
+                Ok(path)
+            });
+        match result {
+            Ok(path) => {
+                self.toasts
+                    .success(
+                        self.i18n
+                            .format("exported_to", &[("path", path.display().to_string())]),
+                    )
+                    .set_duration(Some(Duration::from_secs(3)));
+            }
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("export_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+        }
+    }
+
+    /// the check box picking the message at `index` for export, while picking
+    fn render_export_checkbox(&mut self, ui: &mut egui::Ui, index: usize) {
+        if let Some(selection) = self.export_selection.as_mut() {
+            let mut checked = selection.contains(&index);
+            if ui.checkbox(&mut checked, "").changed() {
+                if checked {
+                    selection.insert(index);
+                } else {
+                    selection.remove(&index);
+                }
+            }
+        }
+    }
+
+    ///copy the session's messages when they changed, returns whether there is something new to scroll to
+    fn sync_new_message(&mut self) -> bool {
+        match &self.session {
+            Some(session) => {
+                let version = session.version();
+                if version != self.history_version {
+                    self.history_version = version;
+                    self.history = session.history();
+                    return true;
+                }
                 false
             }
             None => false,
@@ -133,8 +664,23 @@ impl App {
         if self.is_side_panel_expanded {
             panel_handle = "《";
         }
-        if ui.small_button(panel_handle).clicked() {
-            self.is_side_panel_expanded = !self.is_side_panel_expanded;
+        if ui
+            .small_button(panel_handle)
+            .on_hover_text(format!(
+                "{} ({})",
+                action_label(&self.i18n, Action::ToggleSettings),
+                self.settings.keymap.toggle_settings
+            ))
+            .clicked()
+        {
+            self.toggle_side_panel();
+        }
+    }
+
+    fn toggle_side_panel(&mut self) {
+        self.is_side_panel_expanded = !self.is_side_panel_expanded;
+        if self.is_side_panel_expanded {
+            self.conversations = self.store.list();
         }
     }
 
@@ -144,34 +690,37 @@ impl App {
                 //wether or not scroll to new message
                 let need_scroll = self.sync_new_message();
 
-                for msg in self.history.clone().iter() {
+                // the first message is the role setting message
+                for (index, msg) in self.history.clone().iter().enumerate().skip(1) {
                     match msg.role {
                         Role::System => {
-                            // ignore role setting message
-                            if msg.content == self.current_role.prompt {
-                                continue;
-                            }
-
                             ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
+                                self.render_export_checkbox(ui, index);
                                 self.system_icon.show_size(ui, Vec2::splat(24.0));
 
                                 let resp = ui
                                     .add(
-                                        egui::Label::new(msg.content.clone())
-                                            .wrap(true)
-                                            .sense(egui::Sense::click()),
+                                        egui::Label::new(self.message_text(
+                                            ui,
+                                            index,
+                                            &msg.content,
+                                        ))
+                                        .wrap(true)
+                                        .sense(egui::Sense::click()),
                                     )
-                                    .on_hover_text_at_pointer("📋 点击复制");
+                                    .on_hover_text_at_pointer(self.i18n.text("click_to_copy"));
 
                                 if need_scroll {
                                     resp.scroll_to_me(None);
+                                } else if self.jump_to == Some(index) {
+                                    resp.scroll_to_me(Some(egui::Align::Center));
                                 }
 
                                 if resp.clicked() {
                                     ui.output_mut(|o| {
                                         o.copied_text = msg.content.clone();
                                         self.toasts
-                                            .success("复制成功")
+                                            .success(self.i18n.text("copied"))
                                             .set_duration(Some(Duration::from_secs(1)));
                                     });
                                 }
@@ -179,90 +728,110 @@ impl App {
                         }
                         Role::Assistant => {
                             ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
+                                self.render_export_checkbox(ui, index);
                                 if ui
                                     .add(egui::widgets::ImageButton::new(
                                         self.ai_icon.texture_id(ui.ctx()),
                                         Vec2::splat(24.0),
                                     ))
-                                    .on_hover_text("❌点击删除")
+                                    .on_hover_text(self.i18n.text("click_to_delete"))
                                     .clicked()
                                 {
                                     println!("current history message have benn cleaned!");
-                                    self.history.clear();
-                                    self.conversation = None;
+                                    self.reset_session();
                                     println!("history size: {}", self.history.len());
                                     self.toasts
-                                        .success("当前会话已重置！")
+                                        .success(self.i18n.text("conversation_reset"))
                                         .set_duration(Some(Duration::from_secs(1)));
                                 }
-                                let mut content = egui::text::LayoutJob::default();
-
-                                content.append(
-                                    &msg.content,
-                                    0.0,
-                                    egui::TextFormat {
-                                        ..Default::default()
-                                    },
-                                );
-
+                                self.render_read_aloud_button(ui, index, &msg.content);
                                 let resp = ui
                                     .add(
-                                        egui::Label::new(content)
-                                            .wrap(true)
-                                            .sense(egui::Sense::click()),
+                                        egui::Label::new(self.message_text(
+                                            ui,
+                                            index,
+                                            &msg.content,
+                                        ))
+                                        .wrap(true)
+                                        .sense(egui::Sense::click()),
                                     )
-                                    .on_hover_text_at_pointer("📋 点击复制");
+                                    .on_hover_text_at_pointer(self.i18n.text("click_to_copy"));
 
                                 if need_scroll {
                                     resp.scroll_to_me(None);
+                                } else if self.jump_to == Some(index) {
+                                    resp.scroll_to_me(Some(egui::Align::Center));
                                 }
 
                                 if resp.clicked() {
                                     ui.output_mut(|o| {
                                         o.copied_text = msg.content.clone();
                                         self.toasts
-                                            .success("复制成功")
+                                            .success(self.i18n.text("copied"))
                                             .set_duration(Some(Duration::from_secs(1)));
                                     });
                                 }
                             });
+                            self.render_thumbnails(ui, index, &msg.images);
+                            render_tool_calls(ui, &self.i18n, index, &msg.tool_calls);
+                            self.render_citations(ui, &msg.citations);
                         }
                         Role::User => {
                             ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+                                self.render_export_checkbox(ui, index);
                                 if ui
                                     .add(egui::widgets::ImageButton::new(
                                         self.user_icon.texture_id(ui.ctx()),
                                         Vec2::splat(24.0),
                                     ))
-                                    .on_hover_text("❌点击删除")
+                                    .on_hover_text(self.i18n.text("click_to_delete"))
                                     .clicked()
                                 {
                                     println!("current history message have benn cleaned!");
-                                    self.history.clear();
-                                    self.conversation = None;
+                                    self.reset_session();
                                     println!("history size: {}", self.history.len());
                                     self.toasts
-                                        .success("当前会话已重置！")
+                                        .success(self.i18n.text("conversation_reset"))
                                         .set_duration(Some(Duration::from_secs(1)));
                                 }
 
+                                // attachments show up as chips below the text
+                                let text = shown_text(msg);
                                 let resp = ui
-                                    .add(
-                                        egui::Label::new(msg.content.clone())
-                                            .wrap(true)
-                                            .sense(egui::Sense::click()),
-                                    )
-                                    .on_hover_text_at_pointer("📋 点击复制");
+                                    .with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                                        let resp = ui
+                                            .add(
+                                                egui::Label::new(
+                                                    self.message_text(ui, index, &text),
+                                                )
+                                                .wrap(true)
+                                                .sense(egui::Sense::click()),
+                                            )
+                                            .on_hover_text_at_pointer(
+                                                self.i18n.text("click_to_copy"),
+                                            );
+                                        render_attachment_chips(
+                                            ui,
+                                            &self.i18n,
+                                            index,
+                                            &msg.content,
+                                        );
+                                        self.render_thumbnails(ui, index, &msg.images);
+                                        resp
+                                    })
+                                    .inner;
 
                                 if need_scroll {
                                     resp.scroll_to_me(None);
+                                } else if self.jump_to == Some(index) {
+                                    resp.scroll_to_me(Some(egui::Align::Center));
                                 }
 
                                 if resp.clicked() {
                                     ui.output_mut(|o| {
                                         o.copied_text = msg.content.clone();
                                         self.toasts
-                                            .success("复制成功")
+                                            .success(self.i18n.text("copied"))
                                             .set_duration(Some(Duration::from_secs(1)));
                                     });
                                 }
@@ -272,10 +841,35 @@ impl App {
                     ui.separator();
                     ui.add_space(22_f32);
                 }
+                self.jump_to = None;
             });
         });
     }
 
+    /// the content of the message at `index`, with the searched words highlighted
+    fn message_text(&self, ui: &egui::Ui, index: usize, content: &str) -> egui::WidgetText {
+        if let Some(bar) = self
+            .find_bar
+            .as_ref()
+            .filter(|b| !b.query.trim().is_empty())
+        {
+            let ranges = search::find(content, &bar.query);
+            let current = match &bar.selected {
+                Some((selected, range)) if *selected == index => {
+                    ranges.iter().position(|r| r == range)
+                }
+                _ => None,
+            };
+            return highlight_job(ui, content, &ranges, current).into();
+        }
+        match &self.highlight {
+            Some((highlighted, query)) if *highlighted == index => {
+                highlight_job(ui, content, &search::find(content, query), None).into()
+            }
+            _ => content.into(),
+        }
+    }
+
     fn render_history_panel(&mut self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.horizontal_centered(|ui| {
@@ -287,148 +881,2895 @@ impl App {
     }
 
     fn render_spinner_if_necessary(&mut self, ui: &mut egui::Ui) {
-        // If we can acquire the lock successfully, means there's no prompt submitting thread exist currently
-        // namely: the App is waiting for user's inputing
-        let is_waiting_for_ai = match self.conversation.clone() {
-            Some(conversation) => !conversation.try_lock().is_ok(),
+        if self.transcribing > 0 {
+            ui.spinner().on_hover_text(self.i18n.text("transcribing"));
+        }
+        if self.indexing > 0 {
+            ui.spinner().on_hover_text(self.i18n.text("indexing"));
+        }
+        let is_waiting_for_ai = match &self.session {
+            Some(session) => session.is_busy(),
             None => false,
         };
 
         if is_waiting_for_ai {
+            if ui
+                .small_button("⏹")
+                .on_hover_text(format!(
+                    "{} ({})",
+                    action_label(&self.i18n, Action::Stop),
+                    self.settings.keymap.stop
+                ))
+                .clicked()
+            {
+                if let Some(session) = &self.session {
+                    session.cancel();
+                }
+            }
             ui.with_layout(
                 egui::Layout::centered_and_justified(egui::Direction::TopDown),
                 |ui| {
                     ui.spinner();
                 },
             );
-            return;
         }
     }
 
     fn render_input_box(&mut self, ctx: &egui::Context) {
         egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
             //ui.add_space(2_f32);
+            self.render_pending_attachments(ui);
 
             ui.horizontal(|ui| {
+                self.render_profile_list(ui);
                 self.render_role_list(ctx, ui);
+                self.render_export_menu(ui);
+                if ui
+                    .button("🔍")
+                    .on_hover_text(format!(
+                        "{} ({})",
+                        action_label(&self.i18n, Action::Search),
+                        self.settings.keymap.search
+                    ))
+                    .clicked()
+                {
+                    self.toggle_search();
+                }
+                if ui
+                    .button("⌨")
+                    .on_hover_text(format!(
+                        "{} ({})",
+                        action_label(&self.i18n, Action::CheatSheet),
+                        self.settings.keymap.cheat_sheet
+                    ))
+                    .clicked()
+                {
+                    self.cheat_sheet = !self.cheat_sheet;
+                }
+                if ui
+                    .button("📎")
+                    .on_hover_text(self.i18n.text("attach_hint"))
+                    .clicked()
+                {
+                    for path in rfd::FileDialog::new().pick_files().unwrap_or_default() {
+                        self.add_file(ctx, &path);
+                    }
+                }
+
+                self.render_image_options(ui);
+                self.render_documents_menu(ui);
+                self.render_templates_menu(ui);
+                self.render_tools_toggle(ui);
 
                 self.render_spinner_if_necessary(ui);
+                let prompt_id = egui::Id::new("prompt");
+                let completions = self.completions();
+                let focused = ui.memory(|m| m.has_focus(prompt_id));
+                if focused {
+                    self.handle_command_keys(ui, &completions);
+                    if completions.is_empty() {
+                        self.handle_history_keys(ui, prompt_id);
+                    }
+                }
+                // taken before the text edit sees it, which would start a new line
+                let send = focused && self.take_send_key(ui);
                 let prompt_text_edit = egui::TextEdit::multiline(&mut self.pmt)
+                    .id(prompt_id)
                     .desired_width(f32::INFINITY)
                     .desired_rows(1)
                     .margin(egui::Vec2::splat(24_f32))
-                    .hint_text("回车键发送");
+                    .hint_text(self.i18n.format(
+                        "prompt_hint",
+                        &[(
+                            "key",
+                            send_mode_label(&self.i18n, self.settings.keymap.send_mode).to_string(),
+                        )],
+                    ));
 
                 let resp = ui.add(prompt_text_edit);
-                if !self.is_side_panel_expanded {
+                if resp.has_focus() {
+                    self.render_completions(ui, resp.rect, &completions);
+                }
+                // leave the focus to the search window or find bar while one is in use
+                if !self.is_side_panel_expanded && ui.memory(|m| m.focus().is_none()) {
                     resp.request_focus();
                 }
-
-                // register the event of pressing Enter to send the message or pressing
-                // Shift + Enter to create a new line
+                // the text edit handles pasted text, an image on the clipboard comes
+                // without a paste event
                 if resp.has_focus()
-                    && ui.input(|i| i.key_pressed(egui::Key::Enter))
-                    && self.pmt.trim().len() > 1
+                    && ui.input(|i| {
+                        i.modifiers.command
+                            && i.key_pressed(egui::Key::V)
+                            && !i.events.iter().any(|e| matches!(e, egui::Event::Paste(_)))
+                    })
                 {
-                    //press Shift + Enter to create a new line
-                    if ui.input(|i| i.modifiers.matches(egui::Modifiers::SHIFT)) {
-                        self.pmt.push('\n');
-                    } else {
-                        if self.conversation.is_none() {
-                            self.conversation =
-                                Some(Arc::new(Mutex::new(self.create_conversation())));
-                        }
+                    self.paste_image();
+                }
 
-                        self.history.push(ChatMessage {
-                            role: Role::User,
-                            content: self.pmt.trim().to_owned(),
-                        });
+                if send {
+                    self.submit_prompt(ctx);
+                }
+            });
+        });
+    }
 
-                        tokio::spawn(App::submit_prompt(
-                            ctx.clone(),
-                            self.conversation.clone().unwrap().clone(),
-                            self.pmt.trim().to_owned(),
-                        ));
-                        self.pmt.clear();
-                    }
+    /// Takes the Enter press sending the prompt out of the input, the others stay for the text
+    /// edit to start a new line.
+    ///
+    /// Returns whether there is something to send.
+    fn take_send_key(&self, ui: &mut egui::Ui) -> bool {
+        let mode = self.settings.keymap.send_mode;
+        let pressed = ui.input_mut(|i| {
+            let mut pressed = false;
+            i.events.retain(|event| match event {
+                egui::Event::Key {
+                    key: egui::Key::Enter,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } if mode.sends(modifiers.command, modifiers.shift) => {
+                    pressed = true;
+                    false
                 }
+                _ => true,
             });
+            pressed
         });
+        pressed
+            && (commands::has_text(&self.pmt)
+                || !self.attachments.is_empty()
+                || !self.images.is_empty())
     }
 
-    fn render_side_panel(&mut self, ctx: &egui::Context) {
-        let side_panel_width = ctx.screen_rect().max.x / 4.0;
-        egui::SidePanel::left("side_panel")
-            .show_separator_line(true)
-            .exact_width(side_panel_width)
-            .show_animated(ctx, self.is_side_panel_expanded, |ui| {
-                egui::ScrollArea::vertical().show(ui, |ui| {
-                    ui.horizontal(|ui| {
-                        ui.label("API_KEY ");
-                        ui.add(
-                            egui::TextEdit::singleline(&mut self.settings.api_key)
-                                .desired_width(side_panel_width * 0.9),
-                        );
-                    });
-                    ui.horizontal(|ui| {
-                        ui.label("API_URL ");
-                        ui.add(
-                            egui::TextEdit::singleline(&mut self.settings.api_url)
-                                .desired_width(side_panel_width * 0.9),
-                        );
-                    });
+    /// sends the prompt with the attachments, starting a conversation when there is none
+    fn send_prompt(&mut self, ctx: &egui::Context) {
+        // the prompt stays until the reply is in
+        if self.session_or_create().is_none_or(|s| s.is_busy()) {
+            return;
+        }
+        let ctx = ctx.clone();
+        let mut msg = Message::new(
+            Role::User,
+            attachment::compose(commands::unescape(&self.pmt).trim(), &self.attachments),
+        );
+        msg.images = self.images.drain(..).map(|(url, _)| url).collect();
+        let session = self.session.as_ref().unwrap();
+        session.send_message(msg, move || ctx.request_repaint());
+        self.remember_prompt();
+        self.pmt.clear();
+        self.attachments.clear();
+    }
 
-                    ui.add_space(22.0);
-                    if ui.button("保存").clicked() {
-                        println!("ready to save settings:{:#?}", self.settings);
-                        match confy::store(self.app_name.as_str(), None, self.settings.clone()) {
-                            Err(err) => {
-                                self.toasts
-                                    .error(format!("保存失败！（{err}）"))
-                                    .set_duration(None);
-                            }
-                            _ => {
-                                self.toasts
-                                    .warning("为了使新设置生效，请手动重启本应用！")
-                                    .set_duration(None);
-                            }
-                        };
+    /// the suggestions for the command being typed, empty when the prompt is not a command or
+    /// the command is complete
+    fn completions(&self) -> Vec<Completion> {
+        let Some(typed) = self.pmt.strip_prefix('/') else {
+            return Vec::new();
+        };
+        if typed.starts_with('/') || typed.contains('\n') {
+            return Vec::new();
+        }
+        let mut completions = Vec::new();
+        match typed.split_once(' ') {
+            None => {
+                for name in Command::NAMES {
+                    if !name.starts_with(typed) {
+                        continue;
                     }
-                });
-            });
+                    let (usage, detail) = match name {
+                        "new" => ("/new".to_owned(), self.i18n.text("command_new")),
+                        "role" => (
+                            format!("/role <{}>", self.i18n.text("argument_role")),
+                            self.i18n.text("command_role"),
+                        ),
+                        "model" => (
+                            format!("/model <{}>", self.i18n.text("argument_model")),
+                            self.i18n.text("command_model"),
+                        ),
+                        "temp" => ("/temp <0–2>".to_owned(), self.i18n.text("command_temp")),
+                        "export" => (
+                            "/export <md|html|json|pdf>".to_owned(),
+                            self.i18n.text("command_export"),
+                        ),
+                        "clear" => ("/clear".to_owned(), self.i18n.text("command_clear")),
+                        _ => ("/retry".to_owned(), self.i18n.text("command_retry")),
+                    };
+                    let text = if commands::takes_argument(name) {
+                        format!("/{name} ")
+                    } else {
+                        format!("/{name}")
+                    };
+                    completions.push(Completion {
+                        text,
+                        label: usage,
+                        detail: detail.to_owned(),
+                    });
+                }
+            }
+            Some((name, argument)) => {
+                let argument = argument.trim_start().to_lowercase();
+                let options: Vec<String> = match name {
+                    "role" => self
+                        .settings
+                        .current_profile()
+                        .role_list
+                        .iter()
+                        .map(|r| r.name.clone())
+                        .collect(),
+                    "model" => {
+                        let mut models = vec![self.settings.current_profile().model.clone()];
+                        if let Some(session) = &self.session {
+                            models.push(session.conversation().model);
+                        }
+                        models.extend(MODELS.map(String::from));
+                        let mut seen = BTreeSet::new();
+                        models.retain(|m| !m.is_empty() && seen.insert(m.clone()));
+                        models
+                    }
+                    "export" => Format::ALL
+                        .iter()
+                        .map(|f| f.extension().to_owned())
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                for option in options {
+                    if option.to_lowercase().starts_with(&argument) {
+                        completions.push(Completion {
+                            text: format!("/{name} {option}"),
+                            label: option,
+                            detail: String::new(),
+                        });
+                    }
+                }
+            }
+        }
+        completions.retain(|c| c.text.trim_end() != self.pmt.trim_end());
+        completions
     }
 
-    fn render_notification(&mut self, ctx: &egui::Context) {
-        self.toasts.show(ctx);
+    /// Up and Down pick a suggestion and Tab takes it, Enter runs a complete command or takes
+    /// the suggestion; these keys never reach the text edit then.
+    fn handle_command_keys(&mut self, ui: &mut egui::Ui, completions: &[Completion]) {
+        if completions.is_empty() {
+            return;
+        }
+        let none = egui::Modifiers::NONE;
+        self.completion = self.completion.min(completions.len() - 1);
+        let accept = ui.input_mut(|i| {
+            if i.consume_key(none, egui::Key::ArrowUp) {
+                self.completion = self.completion.saturating_sub(1);
+            }
+            if i.consume_key(none, egui::Key::ArrowDown) {
+                self.completion = (self.completion + 1).min(completions.len() - 1);
+            }
+            i.consume_key(none, egui::Key::Tab)
+        });
+        if accept {
+            self.accept_completion(ui.ctx(), completions);
+        }
     }
 
-    async fn submit_prompt(
-        ctx: egui::Context,
-        conversation: Arc<Mutex<Conversation>>,
-        pmt: String,
-    ) {
-        println!("====[send message:{:#?}]=====", pmt);
+    /// puts the highlighted suggestion of the slash command popup into the prompt
+    fn accept_completion(&mut self, ctx: &egui::Context, completions: &[Completion]) {
+        if let Some(completion) = completions.get(self.completion) {
+            self.pmt = completion.text.clone();
+            self.completion = 0;
+            move_cursor_to_end(ctx, egui::Id::new("prompt"), &self.pmt);
+        }
+    }
 
-        let mut conversation = conversation.lock().await;
-        if let Err(e) = conversation.send_message(pmt).await {
-            conversation.history.push(ChatMessage {
-                role: Role::System,
-                content: format!("{:#?}", e),
-            });
-            println!("{:#?}", e);
+    /// Runs the prompt when it is a command and sends it otherwise.
+    ///
+    /// A command that cannot run yet, like `/role` with part of a name, takes the highlighted
+    /// suggestion instead.
+    fn submit_prompt(&mut self, ctx: &egui::Context) {
+        let Some(parsed) = Command::parse(&self.pmt) else {
+            self.send_prompt(ctx);
+            return;
+        };
+        let completions = self.completions();
+        let runnable = match &parsed {
+            Ok(Command::Role(name)) => self.find_role(name).is_some(),
+            Ok(_) => true,
+            Err(_) => false,
+        };
+        if !runnable && !completions.is_empty() {
+            self.accept_completion(ctx, &completions);
+            return;
+        }
+        match parsed {
+            Ok(command) => {
+                self.remember_prompt();
+                self.pmt.clear();
+                self.run_command(ctx, command);
+            }
+            // kept for fixing
+            Err(err) => {
+                self.toasts
+                    .error(err)
+                    .set_duration(Some(Duration::from_secs(3)));
+            }
         }
-        ctx.request_repaint();
     }
-}
 
-//main loop running for ever
-impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        self.render_side_panel(ctx);
-        self.render_input_box(ctx);
+    /// Up on the first line of the prompt brings back the prompt sent before the one shown,
+    /// Down on the last line the one sent after it, and after the newest the prompt that was
+    /// being written.
+    fn handle_history_keys(&mut self, ui: &mut egui::Ui, prompt_id: egui::Id) {
+        let cursor = egui::TextEdit::load_state(ui.ctx(), prompt_id)
+            .and_then(|state| state.ccursor_range())
+            .map_or(self.pmt.chars().count(), |range| range.primary.index);
+        let split = self
+            .pmt
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.pmt.len(), |(i, _)| i);
+        let none = egui::Modifiers::NONE;
+        let recalled = if !self.pmt[..split].contains('\n')
+            && ui.input_mut(|i| i.consume_key(none, egui::Key::ArrowUp))
+        {
+            match &self.recalling {
+                _ if self.prompt_history.is_empty() => None,
+                None => Some((self.prompt_history.len() - 1, self.pmt.clone())),
+                Some((0, _)) => None,
+                Some((i, draft)) => Some((i - 1, draft.clone())),
+            }
+        } else if self.recalling.is_some()
+            && !self.pmt[split..].contains('\n')
+            && ui.input_mut(|i| i.consume_key(none, egui::Key::ArrowDown))
+        {
+            let (i, draft) = self.recalling.take().unwrap();
+            if i + 1 < self.prompt_history.len() {
+                Some((i + 1, draft))
+            } else {
+                self.pmt = draft;
+                move_cursor_to_end(ui.ctx(), prompt_id, &self.pmt);
+                None
+            }
+        } else {
+            None
+        };
+        if let Some((i, draft)) = recalled {
+            self.pmt = self.prompt_history.entries()[i].clone();
+            self.recalling = Some((i, draft));
+            move_cursor_to_end(ui.ctx(), prompt_id, &self.pmt);
+        }
+    }
 
-        self.render_history_panel(ctx);
+    /// keeps the prompt being sent for recalling it later and drops it as a draft
+    fn remember_prompt(&mut self) {
+        self.recalling = None;
+        if let Err(err) = self.prompt_history.push(&self.pmt) {
+            self.toasts
+                .error(
+                    self.i18n
+                        .format("history_save_failed", &[("err", err.to_string())]),
+                )
+                .set_duration(None);
+        }
+        if let Err(err) = self.drafts.set(&self.draft_key, "") {
+            self.toasts
+                .error(
+                    self.i18n
+                        .format("draft_save_failed", &[("err", err.to_string())]),
+                )
+                .set_duration(None);
+        }
+    }
 
-        self.render_notification(ctx);
+    /// the Ctrl+R window finding a prompt sent before, picking one puts it into the prompt
+    fn render_recall_search(&mut self, ctx: &egui::Context) {
+        let Some(search) = self.recall_search.as_mut() else {
+            return;
+        };
+        let matches = self.prompt_history.search(&search.query);
+        let none = egui::Modifiers::NONE;
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(none, egui::Key::ArrowUp),
+                i.consume_key(none, egui::Key::ArrowDown),
+                i.consume_key(none, egui::Key::Enter),
+                i.consume_key(none, egui::Key::Escape),
+            )
+        });
+        if up {
+            search.selected = search.selected.saturating_sub(1);
+        }
+        if down {
+            search.selected += 1;
+        }
+        search.selected = search.selected.min(matches.len().saturating_sub(1));
+        let mut picked = enter.then_some(search.selected);
+        let mut open = !escape;
+        egui::Window::new(self.i18n.text("prompt_history"))
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(480.0)
+            .anchor(egui::Align2::CENTER_BOTTOM, Vec2::new(0.0, -120.0))
+            .show(ctx, |ui| {
+                let resp = ui.add(
+                    egui::TextEdit::singleline(&mut search.query)
+                        .desired_width(f32::INFINITY)
+                        .hint_text(self.i18n.text("search_sent")),
+                );
+                if !search.focused {
+                    resp.request_focus();
+                    search.focused = true;
+                }
+                if resp.changed() {
+                    search.selected = 0;
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(320.0)
+                    .show(ui, |ui| {
+                        for (i, entry) in matches.iter().enumerate().take(100) {
+                            let line = entry.lines().next().unwrap_or_default();
+                            let mut label: String = line.chars().take(80).collect();
+                            if label.len() < entry.len() {
+                                label.push('…');
+                            }
+                            let resp = ui
+                                .selectable_label(i == search.selected, label)
+                                .on_hover_text(*entry);
+                            if i == search.selected && (up || down) {
+                                resp.scroll_to_me(None);
+                            }
+                            if resp.clicked() {
+                                picked = Some(i);
+                            }
+                        }
+                    });
+                if matches.is_empty() {
+                    ui.weak(self.i18n.text("no_matching_entries"));
+                }
+                ui.weak(self.i18n.text("recall_keys"));
+            });
+        let picked = picked.and_then(|i| matches.get(i)).map(|e| e.to_string());
+        if let Some(entry) = picked {
+            self.pmt = entry;
+            self.recalling = None;
+            self.recall_search = None;
+            ctx.memory_mut(|m| m.request_focus(egui::Id::new("prompt")));
+            move_cursor_to_end(ctx, egui::Id::new("prompt"), &self.pmt);
+        } else if !open {
+            self.recall_search = None;
+        }
+    }
+
+    /// the suggestions above the prompt, clicking one takes it
+    fn render_completions(
+        &mut self,
+        ui: &mut egui::Ui,
+        prompt: egui::Rect,
+        completions: &[Completion],
+    ) {
+        if completions.is_empty() {
+            return;
+        }
+        let mut clicked = None;
+        egui::Area::new("slash_commands")
+            .order(egui::Order::Foreground)
+            .fixed_pos(prompt.left_top())
+            .pivot(egui::Align2::LEFT_BOTTOM)
+            .show(ui.ctx(), |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    for (i, completion) in completions.iter().enumerate() {
+                        let text = if completion.detail.is_empty() {
+                            completion.label.clone()
+                        } else {
+                            format!("{}    {}", completion.label, completion.detail)
+                        };
+                        if ui.selectable_label(i == self.completion, text).clicked() {
+                            clicked = Some(i);
+                        }
+                    }
+                    ui.weak(self.i18n.text("completion_keys"));
+                });
+            });
+        if let Some(i) = clicked {
+            self.pmt = completions[i].text.clone();
+            self.completion = 0;
+            ui.memory_mut(|m| m.request_focus(egui::Id::new("prompt")));
+            move_cursor_to_end(ui.ctx(), egui::Id::new("prompt"), &self.pmt);
+        }
+    }
+
+    /// the role of the current profile called `name`, in any case
+    fn find_role(&self, name: &str) -> Option<settings::Role> {
+        self.settings
+            .current_profile()
+            .role_list
+            .iter()
+            .find(|r| r.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    fn run_command(&mut self, ctx: &egui::Context, command: Command) {
+        match command {
+            Command::New => {
+                self.reset_session();
+                self.toasts
+                    .success(self.i18n.text("new_conversation_started"))
+                    .set_duration(Some(Duration::from_secs(1)));
+            }
+            Command::Role(name) => {
+                match self.find_role(&name) {
+                    Some(role) => {
+                        self.current_role = role;
+                        self.reset_session();
+                        self.toasts
+                            .success(self.i18n.format(
+                                "switched_role",
+                                &[("role", self.current_role.name.clone())],
+                            ))
+                            .set_duration(Some(Duration::from_secs(1)));
+                    }
+                    None => {
+                        self.toasts
+                            .error(self.i18n.format("no_such_role", &[("name", name.clone())]))
+                            .set_duration(Some(Duration::from_secs(3)));
+                    }
+                }
+            }
+            Command::Model(model) => {
+                if let Some(session) = self.session_or_create() {
+                    session.set_model(model.clone());
+                    self.toasts
+                        .success(
+                            self.i18n
+                                .format("model_switched", &[("model", model.clone())]),
+                        )
+                        .set_duration(Some(Duration::from_secs(1)));
+                }
+            }
+            Command::Temperature(temperature) => {
+                if let Some(session) = self.session_or_create() {
+                    session.set_temperature(temperature);
+                    self.toasts
+                        .success(self.i18n.format(
+                            "temperature_set",
+                            &[("temperature", temperature.to_string())],
+                        ))
+                        .set_duration(Some(Duration::from_secs(1)));
+                }
+            }
+            Command::Export(format) => {
+                if self.session.is_some() {
+                    self.export_conversation(format);
+                } else {
+                    self.toasts
+                        .warning(self.i18n.text("nothing_to_export"))
+                        .set_duration(Some(Duration::from_secs(3)));
+                }
+            }
+            Command::Clear => {
+                if let Some(session) = &self.session {
+                    if let Err(err) = session.clear() {
+                        self.toasts
+                            .error(
+                                self.i18n.format(
+                                    "conversation_save_failed",
+                                    &[("err", err.to_string())],
+                                ),
+                            )
+                            .set_duration(None);
+                    }
+                    self.thumbnails.clear();
+                    self.export_selection = None;
+                }
+            }
+            Command::Retry => {
+                if let Some(session) = &self.session {
+                    let ctx = ctx.clone();
+                    session.retry(move || ctx.request_repaint());
+                }
+            }
+        }
+    }
+
+    /// the running conversation, started now when there is none
+    fn session_or_create(&mut self) -> Option<&mut ChatSession> {
+        if self.session.is_none() {
+            match self.create_session() {
+                Ok(session) => self.session = Some(session),
+                Err(err) => {
+                    self.toasts
+                        .error(
+                            self.i18n
+                                .format("create_failed", &[("err", err.to_string())]),
+                        )
+                        .set_duration(None);
+                    return None;
+                }
+            }
+        }
+        self.session.as_mut()
+    }
+
+    /// attach an image or a text file to the next prompt, or transcribe a recording into it
+    fn add_file(&mut self, ctx: &egui::Context, path: &std::path::Path) {
+        if audio::is_audio(path) {
+            self.transcribe(ctx, path);
+            return;
+        }
+        if !images::is_image(path) {
+            self.add_attachment(path);
+            return;
+        }
+        match images::read(path) {
+            Ok(url) => self.add_image(url),
+            Err(err) => {
+                self.toasts
+                    .error(self.i18n.format(
+                        "add_image_failed",
+                        &[
+                            ("path", path.display().to_string()),
+                            ("err", err.to_string()),
+                        ],
+                    ))
+                    .set_duration(None);
+            }
+        }
+    }
+
+    fn add_image(&mut self, url: String) {
+        let thumbnail = images::decode(&url)
+            .ok_or_else(|| "invalid data URL".to_owned())
+            .and_then(|bytes| RetainedImage::from_image_bytes("pending image", &bytes));
+        match thumbnail {
+            Ok(thumbnail) => self.images.push((url, thumbnail)),
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("add_pasted_image_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+        }
+    }
+
+    /// sends a recording to the transcription endpoint of the current profile in the background
+    fn transcribe(&mut self, ctx: &egui::Context, path: &std::path::Path) {
+        let client = Client::new(self.settings.current_profile()).map_err(|e| e.to_string());
+        let recording = Recording::read(path).map_err(|e| e.to_string());
+        let (client, recording) = match client.and_then(|c| recording.map(|r| (c, r))) {
+            Ok(ready) => ready,
+            Err(err) => {
+                self.toasts
+                    .error(self.i18n.format(
+                        "transcribe_file_failed",
+                        &[
+                            ("path", path.display().to_string()),
+                            ("err", err.to_string()),
+                        ],
+                    ))
+                    .set_duration(None);
+                return;
+            }
+        };
+        self.transcribing += 1;
+        let tx = self.done_tx.clone();
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let result = client.transcribe(recording).await;
+            let _ = tx.send(Done::Transcribed(result.map_err(|e| e.to_string())));
+            ctx.request_repaint();
+        });
+    }
+
+    /// Reads message `index` aloud in the background, reusing its cached audio, then plays it
+    /// with the system's player or offers to save it as `save`.
+    fn read_aloud(
+        &mut self,
+        ctx: &egui::Context,
+        index: usize,
+        text: String,
+        save: Option<SpeechFormat>,
+    ) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        let conversation = session.id();
+        let profile = self
+            .settings
+            .profile(&session.profile())
+            .unwrap_or_else(|| self.settings.current_profile());
+        let client = match Client::new(profile) {
+            Ok(client) => client,
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("read_aloud_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+                return;
+            }
+        };
+        let Some(cache) = self.speech_cache.clone() else {
+            self.toasts
+                .error(self.i18n.text("speech_cache_failed"))
+                .set_duration(None);
+            return;
+        };
+        self.reading.insert(index);
+        let tx = self.done_tx.clone();
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let format = save.unwrap_or(SpeechFormat::Mp3);
+            let result = cache
+                .read_aloud(&client, &conversation, index, &text, format)
+                .await;
+            let _ = tx.send(Done::ReadAloud {
+                index,
+                save,
+                result: result.map_err(|e| e.to_string()),
+            });
+            ctx.request_repaint();
+        });
+    }
+
+    /// handles the work finished in the background since the last frame
+    fn receive_done(&mut self) {
+        while let Ok(done) = self.done.try_recv() {
+            match done {
+                // finished transcriptions go at the end of the prompt for editing
+                Done::Transcribed(Ok(text)) => {
+                    self.transcribing -= 1;
+                    if !self.pmt.trim().is_empty() {
+                        self.pmt.push('\n');
+                    }
+                    self.pmt.push_str(text.trim());
+                }
+                Done::Transcribed(Err(err)) => {
+                    self.transcribing -= 1;
+                    self.toasts
+                        .error(
+                            self.i18n
+                                .format("transcribe_failed", &[("err", err.to_string())]),
+                        )
+                        .set_duration(None);
+                }
+                // a scan for directories changed since is dropped, the newer one is coming
+                Done::FontsFound { dirs, fonts } => {
+                    if self.font_scan.as_ref() == Some(&dirs) {
+                        self.fallback_fonts = fonts;
+                        // sets the fonts again with the ones found
+                        self.applied_appearance = None;
+                    }
+                }
+                Done::Indexed(Ok(reindexed)) => {
+                    self.indexing -= 1;
+                    self.toasts
+                        .success(self.i18n.format(
+                            "indexed",
+                            &[
+                                ("files", reindexed.files.to_string()),
+                                ("passages", reindexed.passages.to_string()),
+                                ("added", reindexed.added.to_string()),
+                                ("changed", reindexed.changed.to_string()),
+                                ("removed", reindexed.removed.to_string()),
+                            ],
+                        ))
+                        .set_duration(Some(Duration::from_secs(3)));
+                }
+                Done::Indexed(Err(err)) => {
+                    self.indexing -= 1;
+                    self.toasts
+                        .error(
+                            self.i18n
+                                .format("index_failed", &[("err", err.to_string())]),
+                        )
+                        .set_duration(None);
+                }
+                Done::ReadAloud {
+                    index,
+                    save,
+                    result,
+                } => {
+                    self.reading.remove(&index);
+                    match (result, save) {
+                        (Ok(path), None) => {
+                            if let Err(err) = open::that_detached(&path) {
+                                self.toasts
+                                    .error(self.i18n.format(
+                                        "play_failed",
+                                        &[
+                                            ("path", path.display().to_string()),
+                                            ("err", err.to_string()),
+                                        ],
+                                    ))
+                                    .set_duration(None);
+                            }
+                        }
+                        (Ok(path), Some(format)) => self.save_speech(index, &path, format),
+                        (Err(err), _) => {
+                            self.toasts
+                                .error(
+                                    self.i18n
+                                        .format("read_aloud_failed", &[("err", err.to_string())]),
+                                )
+                                .set_duration(None);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn save_speech(&mut self, index: usize, cached: &std::path::Path, format: SpeechFormat) {
+        let conversation = self
+            .session
+            .as_ref()
+            .map(|s| s.id())
+            .unwrap_or_else(|| "speech".to_owned());
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name(format!("{conversation}-{index}.{}", format.extension()))
+            .add_filter(format.extension(), &[format.extension()])
+            .save_file()
+        else {
+            return;
+        };
+        match std::fs::copy(cached, &path) {
+            Ok(_) => {
+                self.toasts
+                    .success(
+                        self.i18n
+                            .format("saved_to", &[("path", path.display().to_string())]),
+                    )
+                    .set_duration(Some(Duration::from_secs(3)));
+            }
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("speech_save_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+        }
+    }
+
+    /// 🔊 plays the reply, its context menu saves the audio, a spinner while it is being read
+    fn render_read_aloud_button(&mut self, ui: &mut egui::Ui, index: usize, content: &str) {
+        if self.reading.contains(&index) {
+            ui.spinner().on_hover_text(self.i18n.text("reading_aloud"));
+            return;
+        }
+        let resp = ui
+            .small_button("🔊")
+            .on_hover_text(self.i18n.text("read_aloud_hint"));
+        let clicked = resp.clicked();
+        let mut save = None;
+        resp.context_menu(|ui| {
+            for format in SpeechFormat::ALL {
+                let label = self.i18n.format(
+                    "save_as",
+                    &[("format", format.extension().to_uppercase().to_string())],
+                );
+                if ui.button(label).clicked() {
+                    save = Some(format);
+                    ui.close_menu();
+                }
+            }
+        });
+        if clicked || save.is_some() {
+            self.read_aloud(ui.ctx(), index, content.to_owned(), save);
+        }
+    }
+
+    /// Ctrl+V with an image instead of text on the clipboard attaches the image
+    fn paste_image(&mut self) {
+        let Ok(image) = arboard::Clipboard::new().and_then(|mut c| c.get_image()) else {
+            return;
+        };
+        match images::from_rgba(
+            image.width as u32,
+            image.height as u32,
+            image.bytes.into_owned(),
+        ) {
+            Ok(url) => self.add_image(url),
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("paste_image_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+        }
+    }
+
+    /// small versions of the images of the message at `index`, bigger on hover
+    fn render_thumbnails(&mut self, ui: &mut egui::Ui, index: usize, urls: &[String]) {
+        if urls.is_empty() {
+            return;
+        }
+        let mut save = None;
+        let mut copy = None;
+        ui.horizontal_wrapped(|ui| {
+            for (i, url) in urls.iter().enumerate() {
+                let thumbnail = self
+                    .thumbnails
+                    .entry((index, i, url.len()))
+                    .or_insert_with(|| {
+                        let bytes = images::decode(url)?;
+                        RetainedImage::from_image_bytes(format!("image {index}/{i}"), &bytes).ok()
+                    });
+                match thumbnail {
+                    Some(image) => {
+                        image
+                            .show_max_size(ui, Vec2::splat(160.0))
+                            .on_hover_ui(|ui| {
+                                image.show_max_size(ui, Vec2::splat(640.0));
+                            })
+                            .context_menu(|ui| {
+                                if ui.button(self.i18n.text("save_image")).clicked() {
+                                    save = Some((i, url));
+                                    ui.close_menu();
+                                }
+                                if ui.button(self.i18n.text("copy_image")).clicked() {
+                                    copy = Some(url);
+                                    ui.close_menu();
+                                }
+                            });
+                    }
+                    None => {
+                        ui.label("🖼 ?");
+                    }
+                }
+            }
+        });
+        if let Some((i, url)) = save {
+            self.save_image(index, i, url);
+        }
+        if let Some(url) = copy {
+            match copy_image(url) {
+                Ok(()) => {
+                    self.toasts
+                        .success(self.i18n.text("copied"))
+                        .set_duration(Some(Duration::from_secs(1)));
+                }
+                Err(err) => {
+                    self.toasts
+                        .error(
+                            self.i18n
+                                .format("copy_image_failed", &[("err", err.to_string())]),
+                        )
+                        .set_duration(None);
+                }
+            }
+        }
+    }
+
+    fn save_image(&mut self, index: usize, i: usize, url: &str) {
+        let conversation = self
+            .session
+            .as_ref()
+            .map(|s| s.id())
+            .unwrap_or_else(|| "image".to_owned());
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name(format!(
+                "{conversation}-{index}-{}.{}",
+                i + 1,
+                images::extension(url)
+            ))
+            .save_file()
+        else {
+            return;
+        };
+        let result = images::decode(url)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidData))
+            .and_then(|bytes| std::fs::write(&path, bytes));
+        match result {
+            Ok(()) => {
+                self.toasts
+                    .success(
+                        self.i18n
+                            .format("saved_to", &[("path", path.display().to_string())]),
+                    )
+                    .set_duration(Some(Duration::from_secs(3)));
+            }
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("image_save_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+        }
+    }
+
+    /// size, count and quality of the images of a drawing role, changes are kept in the settings
+    fn render_image_options(&mut self, ui: &mut egui::Ui) {
+        let Some(mut options) = self.current_role.image.clone() else {
+            return;
+        };
+        ui.menu_button(format!("🖼 {} ×{}", options.size, options.count), |ui| {
+            ui.label(self.i18n.text("image_size"));
+            for size in ImageOptions::SIZES {
+                ui.radio_value(&mut options.size, size.to_owned(), size);
+            }
+            ui.separator();
+            ui.add(
+                egui::Slider::new(&mut options.count, 1..=4).text(self.i18n.text("image_count")),
+            );
+            ui.separator();
+            ui.label(self.i18n.text("image_quality"));
+            for quality in ImageOptions::QUALITIES {
+                ui.radio_value(&mut options.quality, quality.to_owned(), quality);
+            }
+        })
+        .response
+        .on_hover_text(format!("{} · {}", options.model, options.quality));
+        if self.current_role.image.as_ref() == Some(&options) {
+            return;
+        }
+        if let Some(session) = &self.session {
+            session.set_image_options(options.clone());
+        }
+        self.current_role.image = Some(options);
+        self.save_current_role();
+    }
+
+    /// 📚 picks the folder of documents the role answers from, how many passages go along with
+    /// a prompt, and indexes the folder again
+    fn render_documents_menu(&mut self, ui: &mut egui::Ui) {
+        let before = self.current_role.documents.clone();
+        let mut documents = before.clone();
+        let mut reindex = false;
+        let title = match &documents {
+            Some(options) => format!(
+                "📚 {}",
+                options
+                    .folder
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            ),
+            None => "📚".to_owned(),
+        };
+        ui.menu_button(title, |ui| {
+            if let Some(options) = documents.as_mut() {
+                ui.label(options.folder.display().to_string());
+                ui.add(
+                    egui::Slider::new(&mut options.top_k, 1..=10).text(self.i18n.text("passages")),
+                );
+                ui.separator();
+                if ui.button(self.i18n.text("reindex")).clicked() {
+                    reindex = true;
+                    ui.close_menu();
+                }
+            }
+            if ui.button(self.i18n.text("pick_folder")).clicked() {
+                ui.close_menu();
+                if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                    let top_k = documents.as_ref().map(|d| d.top_k);
+                    documents = Some(DocumentOptions {
+                        folder,
+                        top_k: top_k.unwrap_or(DocumentOptions::default().top_k),
+                    });
+                    reindex = true;
+                }
+            }
+            if documents.is_some() && ui.button(self.i18n.text("stop_documents")).clicked() {
+                documents = None;
+                ui.close_menu();
+            }
+        })
+        .response
+        .on_hover_text(self.i18n.text("documents_hint"));
+        if reindex {
+            if let Some(options) = &documents {
+                self.reindex(ui.ctx(), options.folder.clone());
+            }
+        }
+        if documents == before {
+            return;
+        }
+        if let Some(session) = &self.session {
+            session.set_documents(documents.clone());
+        }
+        self.current_role.documents = documents;
+        self.save_current_role();
+    }
+
+    /// embeds the changed files of `folder` in the background with the current profile
+    fn reindex(&mut self, ctx: &egui::Context, folder: PathBuf) {
+        let Some(library) = self.library.clone() else {
+            self.toasts
+                .error(self.i18n.text("index_dir_failed"))
+                .set_duration(None);
+            return;
+        };
+        let client = match Client::new(self.settings.current_profile()) {
+            Ok(client) => client,
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("index_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+                return;
+            }
+        };
+        self.indexing += 1;
+        let tx = self.done_tx.clone();
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let result = library.reindex(&client, &folder).await;
+            let _ = tx.send(Done::Indexed(result.map_err(|e| e.to_string())));
+            ctx.request_repaint();
+        });
+    }
+
+    /// the passages a reply was given with, clicking one opens its file
+    fn render_citations(&mut self, ui: &mut egui::Ui, citations: &[Citation]) {
+        if citations.is_empty() {
+            return;
+        }
+        ui.horizontal_wrapped(|ui| {
+            for (i, citation) in citations.iter().enumerate() {
+                let resp = ui
+                    .small_button(format!("📚 [{}] {}", i + 1, citation.label()))
+                    .on_hover_text(citation.location());
+                if resp.clicked() {
+                    if let Err(err) = open::that_detached(&citation.path) {
+                        self.toasts
+                            .error(self.i18n.format(
+                                "open_file_failed",
+                                &[
+                                    ("path", citation.path.display().to_string()),
+                                    ("err", err.to_string()),
+                                ],
+                            ))
+                            .set_duration(None);
+                    }
+                }
+            }
+        });
+    }
+
+    /// 📝 the saved prompts, picking one fills in its variables and adds it to the prompt
+    fn render_templates_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("📝", |ui| {
+            for (index, template) in self.settings.templates.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui
+                        .small_button("✏")
+                        .on_hover_text(self.i18n.text("edit_template"))
+                        .clicked()
+                    {
+                        self.template_editor = Some(TemplateEditor {
+                            index: Some(index),
+                            template: template.clone(),
+                        });
+                        ui.close_menu();
+                    }
+                    let hover = if template.description.is_empty() {
+                        template.body.clone()
+                    } else {
+                        format!("{}\n\n{}", template.description, template.body)
+                    };
+                    if ui.button(&template.name).on_hover_text(hover).clicked() {
+                        self.template_form = Some(TemplateForm {
+                            index,
+                            values: HashMap::new(),
+                        });
+                        ui.close_menu();
+                    }
+                });
+            }
+            if !self.settings.templates.is_empty() {
+                ui.separator();
+            }
+            if ui
+                .button(self.i18n.text("new_template"))
+                .on_hover_text(self.i18n.text("new_template_hint"))
+                .clicked()
+            {
+                self.template_editor = Some(TemplateEditor {
+                    index: None,
+                    template: Template {
+                        body: self.pmt.clone(),
+                        ..Default::default()
+                    },
+                });
+                ui.close_menu();
+            }
+            if ui.button(self.i18n.text("import_templates")).clicked() {
+                ui.close_menu();
+                self.import_templates();
+            }
+            if ui.button(self.i18n.text("export_templates")).clicked() {
+                ui.close_menu();
+                self.export_templates();
+            }
+        })
+        .response
+        .on_hover_text(self.i18n.text("templates"));
+        // templates without variables need no form
+        if let Some(form) = &self.template_form {
+            match self.settings.templates.get(form.index) {
+                Some(template) if template.variables().is_empty() => {
+                    let body = template.body.clone();
+                    self.insert_prompt(&body);
+                    self.template_form = None;
+                }
+                Some(_) => {}
+                None => self.template_form = None,
+            }
+        }
+    }
+
+    /// the form filling in the variables of a template, with a preview of the prompt
+    fn render_template_form(&mut self, ctx: &egui::Context) {
+        let Some(form) = self.template_form.as_mut() else {
+            return;
+        };
+        let Some(template) = self.settings.templates.get(form.index) else {
+            self.template_form = None;
+            return;
+        };
+        let mut open = true;
+        let mut insert = false;
+        egui::Window::new(format!("📝 {}", template.name))
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                if !template.description.is_empty() {
+                    ui.label(&template.description);
+                }
+                for name in template.variables() {
+                    ui.label(&name);
+                    let value = form.values.entry(name).or_default();
+                    ui.add(
+                        egui::TextEdit::multiline(value)
+                            .desired_rows(1)
+                            .desired_width(f32::INFINITY),
+                    );
+                }
+                ui.collapsing(self.i18n.text("preview"), |ui| {
+                    ui.add(egui::Label::new(template.fill(&form.values)).wrap(true));
+                });
+                ui.horizontal(|ui| {
+                    if ui.button(self.i18n.text("insert")).clicked() {
+                        insert = true;
+                    }
+                });
+            });
+        if insert {
+            let filled = template.fill(&form.values);
+            self.insert_prompt(&filled);
+            self.template_form = None;
+        } else if !open {
+            self.template_form = None;
+        }
+    }
+
+    /// adds `text` to the end of the prompt being written
+    fn insert_prompt(&mut self, text: &str) {
+        if !self.pmt.trim().is_empty() {
+            self.pmt.push_str("\n\n");
+        }
+        self.pmt.push_str(text);
+    }
+
+    /// the window writing a new template or changing or deleting a saved one
+    fn render_template_editor(&mut self, ctx: &egui::Context) {
+        let Some(editor) = self.template_editor.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut save = false;
+        let mut delete = false;
+        let name = editor.template.name.trim().to_owned();
+        let taken = self
+            .settings
+            .templates
+            .iter()
+            .enumerate()
+            .any(|(i, t)| t.name == name && Some(i) != editor.index);
+        egui::Window::new(self.i18n.text("template_editor"))
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                egui::Grid::new("template_editor")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label(self.i18n.text("name"));
+                        ui.text_edit_singleline(&mut editor.template.name);
+                        ui.end_row();
+                        ui.label(self.i18n.text("description"));
+                        ui.text_edit_singleline(&mut editor.template.description);
+                        ui.end_row();
+                    });
+                ui.add(
+                    egui::TextEdit::multiline(&mut editor.template.body)
+                        .code_editor()
+                        .desired_rows(8)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("Review this diff:\n\n{{diff}}"),
+                );
+                let variables = editor.template.variables();
+                if !variables.is_empty() {
+                    ui.label(self.i18n.format(
+                        "variables",
+                        &[(
+                            "names",
+                            variables.join(self.i18n.text("list_separator")).to_string(),
+                        )],
+                    ));
+                }
+                if taken {
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        self.i18n.text("template_name_taken"),
+                    );
+                }
+                ui.horizontal(|ui| {
+                    let valid =
+                        !name.is_empty() && !taken && !editor.template.body.trim().is_empty();
+                    if ui
+                        .add_enabled(valid, egui::Button::new(self.i18n.text("save")))
+                        .clicked()
+                    {
+                        save = true;
+                    }
+                    if editor.index.is_some() && ui.button(self.i18n.text("delete")).clicked() {
+                        delete = true;
+                    }
+                });
+            });
+        if save {
+            let mut template = editor.template.clone();
+            template.name = name;
+            match editor.index {
+                Some(i) => self.settings.templates[i] = template,
+                None => self.settings.templates.push(template),
+            }
+        } else if delete {
+            if let Some(i) = editor.index {
+                self.settings.templates.remove(i);
+                self.template_form = None;
+            }
+        } else {
+            if !open {
+                self.template_editor = None;
+            }
+            return;
+        }
+        self.template_editor = None;
+        self.store_settings();
+    }
+
+    /// adds the templates of a pack file, replacing the ones with the same name
+    fn import_templates(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("json", &["json"])
+            .pick_file()
+        else {
+            return;
+        };
+        let pack = match Pack::read(&path) {
+            Ok(pack) => pack,
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("import_templates_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+                return;
+            }
+        };
+        // indexes of the open form may point elsewhere now
+        self.template_form = None;
+        self.template_editor = None;
+        let (added, replaced) = templates::merge(&mut self.settings.templates, pack);
+        if self.store_settings() {
+            self.toasts
+                .success(self.i18n.format(
+                    "templates_imported",
+                    &[
+                        ("added", added.to_string()),
+                        ("replaced", replaced.to_string()),
+                    ],
+                ))
+                .set_duration(Some(Duration::from_secs(3)));
+        }
+    }
+
+    /// writes all templates to a pack file to share
+    fn export_templates(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name("templates.json")
+            .add_filter("json", &["json"])
+            .save_file()
+        else {
+            return;
+        };
+        let pack = Pack {
+            templates: self.settings.templates.clone(),
+        };
+        match pack.write(&path) {
+            Ok(()) => {
+                self.toasts
+                    .success(
+                        self.i18n
+                            .format("exported_to", &[("path", path.display().to_string())]),
+                    )
+                    .set_duration(Some(Duration::from_secs(3)));
+            }
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("export_templates_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+        }
+    }
+
+    /// writes the edited current role back into the profile and saves the settings
+    fn save_current_role(&mut self) {
+        let role = self.current_role.clone();
+        if let Some(saved) = self
+            .settings
+            .current_profile_mut()
+            .role_list
+            .iter_mut()
+            .find(|r| r.name == role.name)
+        {
+            *saved = role;
+        }
+        self.store_settings();
+    }
+
+    /// read a text file to send with the next prompt, warning when it is big
+    fn add_attachment(&mut self, path: &std::path::Path) {
+        match Attachment::read(path) {
+            Ok(attachment) => {
+                if let Some(warning) = attachment.warning() {
+                    self.toasts
+                        .warning(warning)
+                        .set_duration(Some(Duration::from_secs(5)));
+                }
+                self.attachments.push(attachment);
+                let total: usize = self.attachments.iter().map(|a| a.tokens()).sum();
+                if self.attachments.len() > 1 && total > attachment::WARN_TOKENS {
+                    self.toasts
+                        .warning(
+                            self.i18n
+                                .format("attachments_too_big", &[("total", total.to_string())]),
+                        )
+                        .set_duration(Some(Duration::from_secs(5)));
+                }
+            }
+            Err(err) => {
+                self.toasts
+                    .error(self.i18n.format(
+                        "add_attachment_failed",
+                        &[
+                            ("path", path.display().to_string()),
+                            ("err", err.to_string()),
+                        ],
+                    ))
+                    .set_duration(None);
+            }
+        }
+    }
+
+    /// add the files dropped onto the window as attachments
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("drop_overlay"),
+            ));
+            let screen = ctx.screen_rect();
+            painter.rect_filled(screen, 0.0, egui::Color32::from_black_alpha(160));
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                self.i18n.text("drop_hint"),
+                egui::FontId::proportional(24.0),
+                egui::Color32::WHITE,
+            );
+        }
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        for file in dropped {
+            if let Some(path) = file.path {
+                self.add_file(ctx, &path);
+            }
+        }
+    }
+
+    /// the files waiting to be sent, each with a button to drop it
+    fn render_pending_attachments(&mut self, ui: &mut egui::Ui) {
+        if self.attachments.is_empty() && self.images.is_empty() {
+            return;
+        }
+        let mut removed = None;
+        let mut removed_image = None;
+        ui.horizontal_wrapped(|ui| {
+            for (i, attachment) in self.attachments.iter().enumerate() {
+                ui.group(|ui| {
+                    ui.label(format!("📄 {}", attachment.name))
+                        .on_hover_text(self.i18n.format(
+                            "about_tokens",
+                            &[("tokens", attachment.tokens().to_string())],
+                        ));
+                    if ui
+                        .small_button("✖")
+                        .on_hover_text(self.i18n.text("remove_attachment"))
+                        .clicked()
+                    {
+                        removed = Some(i);
+                    }
+                });
+            }
+            for (i, (_, thumbnail)) in self.images.iter().enumerate() {
+                ui.group(|ui| {
+                    thumbnail.show_max_size(ui, Vec2::splat(48.0));
+                    if ui
+                        .small_button("✖")
+                        .on_hover_text(self.i18n.text("remove_image"))
+                        .clicked()
+                    {
+                        removed_image = Some(i);
+                    }
+                });
+            }
+        });
+        if let Some(i) = removed {
+            self.attachments.remove(i);
+        }
+        if let Some(i) = removed_image {
+            self.images.remove(i);
+        }
+    }
+
+    fn render_side_panel(&mut self, ctx: &egui::Context) {
+        let side_panel_width = ctx.screen_rect().max.x / 4.0;
+        egui::SidePanel::left("side_panel")
+            .show_separator_line(true)
+            .exact_width(side_panel_width)
+            .show_animated(ctx, self.is_side_panel_expanded, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let mut selected = None;
+                    ui.horizontal(|ui| {
+                        ui.label(self.i18n.text("profile"));
+                        egui::ComboBox::from_id_source("profile_combo")
+                            .selected_text(self.settings.current_profile.clone())
+                            .show_ui(ui, |ui| {
+                                for profile in self.settings.profiles.iter() {
+                                    let is_current_profile =
+                                        profile.name == self.settings.current_profile;
+                                    if ui
+                                        .selectable_label(is_current_profile, profile.name.clone())
+                                        .clicked()
+                                        && !is_current_profile
+                                    {
+                                        selected = Some(profile.name.clone());
+                                    }
+                                }
+                            });
+                        if ui
+                            .small_button("➕")
+                            .on_hover_text(self.i18n.text("new_profile"))
+                            .clicked()
+                        {
+                            let mut profile = self.settings.current_profile().clone();
+                            let mut n = self.settings.profiles.len() + 1;
+                            while self.settings.profile(&format!("profile{n}")).is_some() {
+                                n += 1;
+                            }
+                            profile.name = format!("profile{n}");
+                            selected = Some(profile.name.clone());
+                            self.settings.profiles.push(profile);
+                            self.store_settings();
+                        }
+                        if self.settings.profiles.len() > 1
+                            && ui
+                                .small_button("➖")
+                                .on_hover_text(self.i18n.text("delete_profile"))
+                                .clicked()
+                        {
+                            let current = self.settings.current_profile.clone();
+                            self.settings.profiles.retain(|p| p.name != current);
+                            selected = Some(self.settings.profiles[0].name.clone());
+                            self.store_settings();
+                        }
+                    });
+                    if let Some(name) = selected {
+                        self.switch_profile(name);
+                    }
+
+                    // start over from the settings when another profile became the current one
+                    let current = self.settings.current_profile();
+                    if self
+                        .profile_form
+                        .as_ref()
+                        .is_none_or(|form| form.profile != current.name)
+                    {
+                        self.profile_form = Some(ProfileForm::new(current));
+                    }
+                    let Some(profile) = &mut self.profile_form else {
+                        return;
+                    };
+                    ui.horizontal(|ui| {
+                        ui.label(self.i18n.text("profile_name"));
+                        ui.add(
+                            egui::TextEdit::singleline(&mut profile.name)
+                                .desired_width(side_panel_width * 0.9),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(self.i18n.text("provider"));
+                        egui::ComboBox::from_id_source("provider_combo")
+                            .selected_text(profile.provider.name())
+                            .show_ui(ui, |ui| {
+                                for provider in settings::Provider::ALL {
+                                    ui.selectable_value(
+                                        &mut profile.provider,
+                                        provider,
+                                        provider.name(),
+                                    );
+                                }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(self.i18n.text("api_key"));
+                        ui.add(
+                            egui::TextEdit::singleline(&mut profile.api_key)
+                                .password(true)
+                                .desired_width(side_panel_width * 0.9),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(self.i18n.text("api_url"));
+                        ui.add(
+                            egui::TextEdit::singleline(&mut profile.api_url)
+                                .desired_width(side_panel_width * 0.9),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(self.i18n.text("model"));
+                        ui.add(
+                            egui::TextEdit::singleline(&mut profile.model)
+                                .desired_width(side_panel_width * 0.9),
+                        );
+                    });
+                    ui.add_space(22.0);
+                    if ui.button(self.i18n.text("save")).clicked() {
+                        self.save_profile();
+                    }
+
+                    ui.separator();
+                    self.render_language_picker(ui);
+                    self.render_appearance_settings(ui);
+                    self.render_keymap_settings(ui);
+                    ui.separator();
+                    if ui
+                        .button(self.i18n.text("import_chatgpt"))
+                        .on_hover_text(self.i18n.text("import_chatgpt_hint"))
+                        .clicked()
+                    {
+                        self.import_chatgpt_export();
+                    }
+                    self.render_conversation_list(ui);
+                });
+            });
+    }
+
+    /// pick a chat.openai.com data export and save its conversations with the current role
+    fn import_chatgpt_export(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("ChatGPT export", &["zip", "json"])
+            .pick_file()
+        else {
+            return;
+        };
+        let result =
+            import::chatgpt_export(&path, &self.settings.current_profile, &self.current_role)
+                .and_then(|conversations| import::save(&self.store, &conversations));
+        match result {
+            Ok((count, kept)) => {
+                self.toasts
+                    .success(
+                        self.i18n
+                            .format("conversations_imported", &[("count", count.to_string())]),
+                    )
+                    .set_duration(Some(Duration::from_secs(2)));
+                if kept > 0 {
+                    self.toasts
+                        .info(self.i18n.format(
+                            "conversations_kept_on_import",
+                            &[("count", kept.to_string())],
+                        ))
+                        .set_duration(Some(Duration::from_secs(4)));
+                }
+            }
+            Err(err) => {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("import_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+        }
+        self.conversations = self.store.list();
+    }
+
+    fn render_conversation_list(&mut self, ui: &mut egui::Ui) {
+        let current_id = self.session.as_ref().map(|s| s.id());
+        let mut opened = None;
+        let mut deleted = None;
+        for conversation in self.conversations.iter() {
+            ui.horizontal(|ui| {
+                if ui
+                    .small_button("🗑")
+                    .on_hover_text(self.i18n.text("delete_conversation"))
+                    .clicked()
+                {
+                    deleted = Some(conversation.id.clone());
+                }
+                let is_current = current_id.as_ref() == Some(&conversation.id);
+                if ui
+                    .selectable_label(is_current, conversation.title.clone())
+                    .on_hover_text(format!("{} / {}", conversation.profile, conversation.role))
+                    .clicked()
+                    && !is_current
+                {
+                    opened = Some(conversation.clone());
+                }
+            });
+        }
+        if let Some(id) = deleted {
+            if let Err(err) = self.store.delete(&id) {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("delete_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+            if current_id.as_ref() == Some(&id) {
+                // the draft goes with the conversation
+                self.pmt.clear();
+                self.reset_session();
+            } else if let Err(err) = self.drafts.set(&id, "") {
+                self.toasts
+                    .error(
+                        self.i18n
+                            .format("draft_save_failed", &[("err", err.to_string())]),
+                    )
+                    .set_duration(None);
+            }
+            self.conversations = self.store.list();
+        }
+        if let Some(conversation) = opened {
+            self.open_conversation(conversation);
+        }
+    }
+
+    fn toggle_search(&mut self) {
+        if self.search.take().is_none() {
+            self.search_index.refresh(&self.store);
+            self.search = Some(SearchForm::default());
+        }
+    }
+
+    fn render_search_window(&mut self, ctx: &egui::Context) {
+        let Some(form) = self.search.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut changed = false;
+        let mut picked = None;
+        egui::Window::new(self.i18n.text("search"))
+            .open(&mut open)
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                let resp = ui.add(
+                    egui::TextEdit::singleline(&mut form.query)
+                        .desired_width(f32::INFINITY)
+                        .hint_text(self.i18n.text("search_all")),
+                );
+                if !form.focused {
+                    resp.request_focus();
+                    form.focused = true;
+                }
+                changed |= resp.changed();
+
+                ui.horizontal_wrapped(|ui| {
+                    let role_name = |role: Option<Role>| match role {
+                        None => self.i18n.text("all_roles"),
+                        Some(Role::User) => self.i18n.text("role_user"),
+                        Some(Role::Assistant) => self.i18n.text("role_assistant"),
+                        Some(Role::System) => self.i18n.text("role_system"),
+                    };
+                    egui::ComboBox::from_id_source("search_role")
+                        .selected_text(role_name(form.role))
+                        .show_ui(ui, |ui| {
+                            for role in [
+                                None,
+                                Some(Role::User),
+                                Some(Role::Assistant),
+                                Some(Role::System),
+                            ] {
+                                changed |= ui
+                                    .selectable_value(&mut form.role, role, role_name(role))
+                                    .changed();
+                            }
+                        });
+                    egui::ComboBox::from_id_source("search_model")
+                        .selected_text(
+                            form.model
+                                .clone()
+                                .unwrap_or(self.i18n.text("all_models").to_owned()),
+                        )
+                        .show_ui(ui, |ui| {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut form.model,
+                                    None,
+                                    self.i18n.text("all_models"),
+                                )
+                                .changed();
+                            for model in self.search_index.models() {
+                                changed |= ui
+                                    .selectable_value(&mut form.model, Some(model.clone()), model)
+                                    .changed();
+                            }
+                        });
+                    let conversations = self.search_index.conversations();
+                    let selected_title = form
+                        .conversation
+                        .as_ref()
+                        .and_then(|id| conversations.iter().find(|c| &c.id == id))
+                        .map_or(self.i18n.text("all_conversations").to_owned(), |c| {
+                            c.title.clone()
+                        });
+                    egui::ComboBox::from_id_source("search_conversation")
+                        .selected_text(selected_title)
+                        .width(160.0)
+                        .show_ui(ui, |ui| {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut form.conversation,
+                                    None,
+                                    self.i18n.text("all_conversations"),
+                                )
+                                .changed();
+                            for conversation in conversations.iter() {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut form.conversation,
+                                        Some(conversation.id.clone()),
+                                        conversation.title.clone(),
+                                    )
+                                    .changed();
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label(self.i18n.text("since"));
+                    changed |= ui
+                        .add(
+                            egui::TextEdit::singleline(&mut form.since)
+                                .desired_width(90.0)
+                                .hint_text("YYYY-MM-DD"),
+                        )
+                        .changed();
+                    ui.label(self.i18n.text("until"));
+                    changed |= ui
+                        .add(
+                            egui::TextEdit::singleline(&mut form.until)
+                                .desired_width(90.0)
+                                .hint_text("YYYY-MM-DD"),
+                        )
+                        .changed();
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical()
+                    .max_height(360.0)
+                    .show(ui, |ui| {
+                        if form.hits.is_empty() && !form.query.trim().is_empty() {
+                            ui.label(self.i18n.text("no_matching_messages"));
+                        }
+                        for hit in form.hits.iter() {
+                            let heading =
+                                format!("{} · {}", hit.title, export::format_time(hit.timestamp));
+                            ui.label(egui::RichText::new(heading).small().weak());
+                            let resp = ui
+                                .add(
+                                    egui::Label::new(highlight_job(
+                                        ui,
+                                        &hit.snippet,
+                                        &hit.highlights,
+                                        None,
+                                    ))
+                                    .wrap(true)
+                                    .sense(egui::Sense::click()),
+                                )
+                                .on_hover_text_at_pointer(self.i18n.text("jump_to_message"));
+                            if resp.clicked() {
+                                picked = Some(hit.clone());
+                            }
+                            ui.separator();
+                        }
+                    });
+            });
+
+        if changed {
+            self.search_index.refresh(&self.store);
+            // a half typed date does not filter yet
+            let filter = Filter {
+                role: form.role,
+                since: search::parse_day(&form.since, false),
+                until: search::parse_day(&form.until, true),
+                model: form.model.clone(),
+                conversation: form.conversation.clone(),
+            };
+            form.hits = self.search_index.search(&form.query, &filter, 100);
+        }
+        let query = form.query.clone();
+        if !open {
+            self.search = None;
+        }
+        if let Some(hit) = picked {
+            self.jump_to_hit(hit, query);
+        }
+    }
+
+    /// show the message of a search result, opening its conversation if needed
+    fn jump_to_hit(&mut self, hit: Hit, query: String) {
+        let is_current = self.session.as_ref().map(|s| s.id()) == Some(hit.conversation.clone());
+        if !is_current {
+            match self.store.load(&hit.conversation) {
+                Ok(conversation) => self.open_conversation(conversation),
+                Err(err) => {
+                    self.toasts
+                        .error(self.i18n.format("open_failed", &[("err", err.to_string())]))
+                        .set_duration(None);
+                    return;
+                }
+            }
+        }
+        self.jump_to = Some(hit.index);
+        self.highlight = Some((hit.index, query));
+    }
+
+    fn open_find_bar(&mut self) {
+        match self.find_bar.as_mut() {
+            // focus the bar again
+            Some(bar) => bar.focused = false,
+            None => self.find_bar = Some(FindBar::default()),
+        }
+    }
+
+    fn render_find_bar(&mut self, ctx: &egui::Context) {
+        let Some(bar) = self.find_bar.as_mut() else {
+            return;
+        };
+
+        // every match in the conversation, in the order they are shown, the text of attachments
+        // is not shown and not searched
+        let matches: Vec<(usize, std::ops::Range<usize>)> = self
+            .history
+            .iter()
+            .enumerate()
+            .skip(1)
+            .flat_map(|(index, msg)| {
+                search::find(&shown_text(msg), &bar.query)
+                    .into_iter()
+                    .map(move |range| (index, range))
+            })
+            .collect();
+        let mut step: Option<isize> = None;
+        let mut close = false;
+        egui::TopBottomPanel::top("find_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let resp = ui.add(
+                    egui::TextEdit::singleline(&mut bar.query)
+                        .desired_width(240.0)
+                        .hint_text(self.i18n.text("find_in_conversation")),
+                );
+                if !bar.focused {
+                    resp.request_focus();
+                    bar.focused = true;
+                }
+                if resp.changed() {
+                    // start over from the first match
+                    bar.current = 0;
+                    step = Some(0);
+                }
+                // a single line edit gives up the focus on Enter, take it back to keep going
+                if resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    step = Some(if ui.input(|i| i.modifiers.shift) {
+                        -1
+                    } else {
+                        1
+                    });
+                    resp.request_focus();
+                }
+                if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    close = true;
+                }
+
+                if bar.query.trim().is_empty() {
+                    ui.label("");
+                } else if matches.is_empty() {
+                    ui.label(self.i18n.text("no_matches"));
+                } else {
+                    ui.label(format!(
+                        "{}/{}",
+                        bar.current.min(matches.len() - 1) + 1,
+                        matches.len()
+                    ));
+                }
+                if ui
+                    .small_button("⬆")
+                    .on_hover_text(self.i18n.text("previous_match"))
+                    .clicked()
+                {
+                    step = Some(-1);
+                }
+                if ui
+                    .small_button("⬇")
+                    .on_hover_text(self.i18n.text("next_match"))
+                    .clicked()
+                {
+                    step = Some(1);
+                }
+                if ui
+                    .small_button("✖")
+                    .on_hover_text(self.i18n.text("close"))
+                    .clicked()
+                {
+                    close = true;
+                }
+            });
+        });
+
+        if close {
+            self.find_bar = None;
+            return;
+        }
+        if matches.is_empty() {
+            bar.selected = None;
+            return;
+        }
+        bar.current = bar.current.min(matches.len() - 1);
+        if let Some(step) = step {
+            let len = matches.len() as isize;
+            bar.current = (bar.current as isize + step).rem_euclid(len) as usize;
+            self.jump_to = Some(matches[bar.current].0);
+        }
+        bar.selected = Some(matches[bar.current].clone());
+    }
+
+    /// 🛠 switches the local tools on or off, also for the running conversation
+    fn render_tools_toggle(&mut self, ui: &mut egui::Ui) {
+        let mut enabled = self.settings.tools.enabled;
+        ui.toggle_value(&mut enabled, "🛠").on_hover_text(
+            self.i18n.format(
+                "tools_hint",
+                &[(
+                    "tools",
+                    self.tools
+                        .names()
+                        .join(self.i18n.text("list_separator"))
+                        .to_string(),
+                )],
+            ),
+        );
+        if enabled == self.settings.tools.enabled {
+            return;
+        }
+        self.settings.tools.enabled = enabled;
+        if let Some(session) = self.session.take() {
+            self.session = Some(self.equip(session));
+        }
+        self.store_settings();
+    }
+
+    /// asks whether the tool call the model is waiting for may run
+    fn render_tool_approval(&mut self, ctx: &egui::Context) {
+        let Some(call) = self.approvals.current() else {
+            return;
+        };
+        let mut answer = None;
+        egui::Window::new(self.i18n.text("run_tool"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(
+                    self.i18n
+                        .format("tool_requested", &[("tool", call.name.clone())]),
+                );
+                ui.add(
+                    egui::Label::new(egui::RichText::new(&call.arguments).monospace()).wrap(true),
+                );
+                ui.horizontal(|ui| {
+                    if ui.button(self.i18n.text("run")).clicked() {
+                        answer = Some(true);
+                    }
+                    if ui.button(self.i18n.text("decline")).clicked() {
+                        answer = Some(false);
+                    }
+                });
+            });
+        if let Some(run) = answer {
+            self.approvals.answer(run);
+        }
+    }
+
+    /// runs the actions whose shortcuts were pressed, or binds the one pressed while capturing
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if let Some(action) = self.capturing {
+            self.capture_shortcut(ctx, action);
+            return;
+        }
+        let prompt_id = egui::Id::new("prompt");
+        for action in Action::ALL {
+            let Some(shortcut) = keyboard_shortcut(self.settings.keymap.get(action)) else {
+                continue;
+            };
+            // keys like Escape only stop a reply from the prompt, the other windows close with it
+            if action == Action::Stop
+                && (!self.session.as_ref().is_some_and(|s| s.is_busy())
+                    || ctx.memory(|m| m.focus().is_some_and(|id| id != prompt_id)))
+            {
+                continue;
+            }
+            if ctx.input_mut(|i| i.consume_shortcut(&shortcut)) {
+                self.run_action(ctx, action);
+            }
+        }
+    }
+
+    /// binds the next key pressed, with its modifiers, to `action`
+    fn capture_shortcut(&mut self, ctx: &egui::Context, action: Action) {
+        let pressed = ctx.input_mut(|i| {
+            let mut pressed = None;
+            // the key must not reach the prompt or the other shortcuts
+            i.events.retain(|event| match event {
+                egui::Event::Key {
+                    key,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } if pressed.is_none() => {
+                    pressed = Some((*key, *modifiers));
+                    false
+                }
+                egui::Event::Key { .. } | egui::Event::Text(_) => false,
+                _ => true,
+            });
+            pressed
+        });
+        let Some((key, modifiers)) = pressed else {
+            return;
+        };
+        self.capturing = None;
+        let shortcut = Shortcut {
+            ctrl: modifiers.command,
+            alt: modifiers.alt,
+            shift: modifiers.shift,
+            key: key.name().to_owned(),
+        };
+        if let Err(other) = self.settings.keymap.set(action, shortcut.clone()) {
+            self.toasts
+                .warning(self.i18n.format(
+                    "shortcut_taken",
+                    &[
+                        ("shortcut", shortcut.to_string()),
+                        ("action", action_label(&self.i18n, other).to_string()),
+                    ],
+                ))
+                .set_duration(Some(Duration::from_secs(3)));
+            return;
+        }
+        self.store_settings();
+    }
+
+    fn run_action(&mut self, ctx: &egui::Context, action: Action) {
+        match action {
+            Action::NewChat => self.reset_session(),
+            Action::NextChat => self.switch_conversation(1),
+            Action::PreviousChat => self.switch_conversation(-1),
+            Action::FocusInput => ctx.memory_mut(|m| m.request_focus(egui::Id::new("prompt"))),
+            Action::Stop => {
+                if let Some(session) = &self.session {
+                    session.cancel();
+                }
+            }
+            Action::Regenerate => self.run_command(ctx, Command::Retry),
+            Action::CopyLastAnswer => {
+                let answer = self
+                    .history
+                    .iter()
+                    .rev()
+                    .find(|m| m.role == Role::Assistant && !m.content.is_empty());
+                match answer {
+                    Some(answer) => {
+                        ctx.output_mut(|o| o.copied_text = answer.content.clone());
+                        self.toasts
+                            .success(self.i18n.text("copied"))
+                            .set_duration(Some(Duration::from_secs(1)));
+                    }
+                    None => {
+                        self.toasts
+                            .warning(self.i18n.text("no_answer_yet"))
+                            .set_duration(Some(Duration::from_secs(1)));
+                    }
+                }
+            }
+            Action::ToggleSettings => self.toggle_side_panel(),
+            Action::Search => self.toggle_search(),
+            Action::Find => self.open_find_bar(),
+            Action::RecallPrompt => {
+                self.recall_search = match self.recall_search {
+                    Some(_) => None,
+                    None => Some(RecallSearch::default()),
+                };
+            }
+            Action::CheatSheet => self.cheat_sheet = !self.cheat_sheet,
+        }
+    }
+
+    /// opens the conversation `step` places further down the list, the first one from a new chat
+    fn switch_conversation(&mut self, step: isize) {
+        self.conversations = self.store.list();
+        let current = self.session.as_ref().map(|s| s.id());
+        let position = self
+            .conversations
+            .iter()
+            .position(|c| Some(&c.id) == current.as_ref());
+        let next = match position {
+            Some(i) => i.checked_add_signed(step),
+            None if step > 0 => Some(0),
+            None => None,
+        };
+        if let Some(conversation) = next.and_then(|i| self.conversations.get(i)) {
+            self.open_conversation(conversation.clone());
+        }
+    }
+
+    /// the shortcuts of the window, with the fixed keys of the prompt
+    fn render_cheat_sheet(&mut self, ctx: &egui::Context) {
+        if !self.cheat_sheet {
+            return;
+        }
+        let keymap = &self.settings.keymap;
+        let i18n = &self.i18n;
+        let newline = match keymap.send_mode {
+            SendMode::Enter => "Shift+Enter",
+            SendMode::CtrlEnter => "Enter",
+        };
+        let mut open = true;
+        egui::Window::new(self.i18n.text("shortcuts"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                egui::Grid::new("cheat_sheet")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        let fixed = [
+                            (i18n.text("send"), send_mode_label(i18n, keymap.send_mode)),
+                            (i18n.text("newline"), newline),
+                            (i18n.text("recall_prompts"), "Up / Down"),
+                            (i18n.text("complete_command"), "Tab"),
+                        ];
+                        for (label, keys) in fixed {
+                            ui.label(label);
+                            ui.monospace(keys);
+                            ui.end_row();
+                        }
+                        for action in Action::ALL {
+                            ui.label(action_label(i18n, action));
+                            ui.monospace(keymap.get(action).to_string());
+                            ui.end_row();
+                        }
+                    });
+                ui.add_space(6.0);
+                ui.weak(i18n.text("shortcuts_hint"));
+            });
+        if !open || ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
+            self.cheat_sheet = false;
+        }
+    }
+
+    /// picks the language of the window, the system's by default
+    fn render_language_picker(&mut self, ui: &mut egui::Ui) {
+        let before = self.settings.language;
+        let name = |language: Option<Language>| match language {
+            Some(language) => language.name().to_owned(),
+            None => format!(
+                "{} ({})",
+                self.i18n.text("follow_system"),
+                Language::system().name()
+            ),
+        };
+        ui.horizontal(|ui| {
+            ui.label("🌐");
+            egui::ComboBox::from_id_source("language_combo")
+                .selected_text(name(self.settings.language))
+                .show_ui(ui, |ui| {
+                    for language in [None].into_iter().chain(Language::ALL.map(Some)) {
+                        ui.selectable_value(&mut self.settings.language, language, name(language));
+                    }
+                });
+        });
+        if self.settings.language == before {
+            return;
+        }
+        self.i18n = Catalog::new(self.settings.language.unwrap_or_else(Language::system));
+        self.store_settings();
+    }
+
+    /// the send mode and a button per action, clicking one binds the next key pressed
+    fn render_keymap_settings(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(self.i18n.text("shortcuts")).show(ui, |ui| {
+            let mut changed = false;
+            ui.horizontal(|ui| {
+                ui.label(self.i18n.text("send"));
+                let keymap = &mut self.settings.keymap;
+                egui::ComboBox::from_id_source("send_mode_combo")
+                    .selected_text(send_mode_label(&self.i18n, keymap.send_mode))
+                    .show_ui(ui, |ui| {
+                        for mode in [SendMode::Enter, SendMode::CtrlEnter] {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut keymap.send_mode,
+                                    mode,
+                                    send_mode_label(&self.i18n, mode),
+                                )
+                                .changed();
+                        }
+                    });
+            });
+            egui::Grid::new("keymap").num_columns(2).show(ui, |ui| {
+                for action in Action::ALL {
+                    ui.label(action_label(&self.i18n, action));
+                    if self.capturing == Some(action) {
+                        if ui
+                            .button(self.i18n.text("press_shortcut"))
+                            .on_hover_text(self.i18n.text("click_to_cancel"))
+                            .clicked()
+                        {
+                            self.capturing = None;
+                        }
+                    } else if ui
+                        .button(self.settings.keymap.get(action).to_string())
+                        .on_hover_text(self.i18n.text("click_to_rebind"))
+                        .clicked()
+                    {
+                        self.capturing = Some(action);
+                    }
+                    ui.end_row();
+                }
+            });
+            if ui.button(self.i18n.text("restore_defaults")).clicked() {
+                self.settings.keymap = Keymap::default();
+                self.capturing = None;
+                changed = true;
+            }
+            if changed {
+                self.store_settings();
+            }
+        });
+    }
+
+    /// Sets the style, fonts and scale of the window when the appearance settings, the theme of
+    /// the desktop or the scale of the display changed.
+    fn apply_appearance(&mut self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let appearance = self.settings.appearance.clone().clamped();
+        // the display's scale changes when the window moves to another screen
+        let native_pixels_per_point = frame.info().native_pixels_per_point.unwrap_or(1.0);
+        let pixels_per_point = native_pixels_per_point * appearance.zoom;
+        if (ctx.pixels_per_point() - pixels_per_point).abs() > 0.001 {
+            ctx.set_pixels_per_point(pixels_per_point);
+        }
+
+        let system_theme = frame.info().system_theme;
+        let applied = (appearance, system_theme);
+        if self.applied_appearance.as_ref() == Some(&applied) {
+            return;
+        }
+        let (appearance, _) = &applied;
+        if self.font_scan.as_ref() != Some(&appearance.font_dirs) {
+            self.scan_fonts(ctx, appearance.font_dirs.clone());
+        }
+        let fonts_changed = self.applied_appearance.as_ref().is_none_or(|(before, _)| {
+            before.proportional_font != appearance.proportional_font
+                || before.monospace_font != appearance.monospace_font
+        });
+        if fonts_changed {
+            ctx.set_fonts(self.font_definitions(appearance));
+        }
+
+        let dark = match appearance.theme {
+            Theme::System => system_theme != Some(eframe::Theme::Light),
+            Theme::Light => false,
+            Theme::Dark => true,
+        };
+        let mut visuals = if dark {
+            egui::Visuals::dark()
+        } else {
+            egui::Visuals::light()
+        };
+        if let Some(Rgb([r, g, b])) = appearance.accent {
+            let accent = egui::Color32::from_rgb(r, g, b);
+            visuals.selection.bg_fill = accent;
+            visuals.hyperlink_color = accent;
+        }
+        let mut style = egui::Style {
+            visuals,
+            ..egui::Style::default()
+        };
+        let scale = appearance.font_size / appearance::DEFAULT_FONT_SIZE;
+        for font in style.text_styles.values_mut() {
+            font.size *= scale;
+        }
+        ctx.set_style(style);
+        self.applied_appearance = Some(applied);
+    }
+
+    /// Looks for CJK, Cyrillic and emoji fonts in the background, as reading the font
+    /// directories takes a moment, the window uses them once they are found.
+    fn scan_fonts(&mut self, ctx: &egui::Context, dirs: Vec<PathBuf>) {
+        self.font_scan = Some(dirs.clone());
+        let tx = self.done_tx.clone();
+        let ctx = ctx.clone();
+        tokio::task::spawn_blocking(move || {
+            let fonts = fonts::discover(&fonts::font_dirs(&dirs));
+            let _ = tx.send(Done::FontsFound { dirs, fonts });
+            ctx.request_repaint();
+        });
+    }
+
+    /// egui's fonts with the font files of the settings first and the fonts found for CJK,
+    /// Cyrillic and emoji between egui's text fonts and its emoji fonts
+    fn font_definitions(&mut self, appearance: &Appearance) -> egui::FontDefinitions {
+        let mut fonts = egui::FontDefinitions::default();
+        let mut failed = Vec::new();
+        // egui panics on a file it cannot parse, so it only gets the ones that load
+        let mut add = |fonts: &mut egui::FontDefinitions, font: &FontFile| match font.load() {
+            Ok(bytes) => {
+                let name = format!("{}#{}", font.path.display(), font.index);
+                let data = egui::FontData {
+                    index: font.index,
+                    ..egui::FontData::from_owned(bytes)
+                };
+                fonts.font_data.insert(name.clone(), data);
+                Some(name)
+            }
+            Err(err) => {
+                failed.push((font.path.clone(), err));
+                None
+            }
+        };
+
+        let fallbacks: Vec<String> = self
+            .fallback_fonts
+            .iter()
+            .filter_map(|(_, font)| add(&mut fonts, font))
+            .collect();
+        for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+            let names = fonts.families.entry(family).or_default();
+            let at = names
+                .iter()
+                .position(|name| name == "NotoEmoji-Regular")
+                .unwrap_or(names.len());
+            names.splice(at..at, fallbacks.iter().cloned());
+        }
+
+        for (family, path) in [
+            (
+                egui::FontFamily::Proportional,
+                &appearance.proportional_font,
+            ),
+            (egui::FontFamily::Monospace, &appearance.monospace_font),
+        ] {
+            if let Some(name) = path
+                .as_ref()
+                .and_then(|path| add(&mut fonts, &FontFile::new(path)))
+            {
+                fonts.families.entry(family).or_default().insert(0, name);
+            }
+        }
+
+        for (path, err) in failed {
+            self.toasts
+                .error(self.i18n.format(
+                    "font_load_failed",
+                    &[
+                        ("path", path.display().to_string()),
+                        ("err", err.to_string()),
+                    ],
+                ))
+                .set_duration(None);
+        }
+        fonts
+    }
+
+    /// The font embedded in PDF exports: the text font of the settings, else one found for CJK
+    /// or Cyrillic, which cover Latin too. `None` leaves PDFs to the built-in Latin font.
+    fn pdf_font(&self) -> Option<Vec<u8>> {
+        let text_font = self
+            .settings
+            .appearance
+            .proportional_font
+            .as_ref()
+            .map(FontFile::new);
+        let found = [Script::Cjk, Script::Cyrillic]
+            .into_iter()
+            .flat_map(|script| {
+                self.fallback_fonts
+                    .iter()
+                    .filter(move |(s, _)| *s == script)
+                    .map(|(_, font)| font.clone())
+            });
+        let candidates: Vec<FontFile> = text_font.into_iter().chain(found).collect();
+        fonts::pdf_font(&candidates)
+    }
+
+    /// theme, accent colour, zoom, text size and font files, saved as soon as they change
+    fn render_appearance_settings(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(self.i18n.text("appearance")).show(ui, |ui| {
+            let before = self.settings.appearance.clone();
+            let appearance = &mut self.settings.appearance;
+            egui::Grid::new("appearance").num_columns(2).show(ui, |ui| {
+                ui.label(self.i18n.text("theme"));
+                egui::ComboBox::from_id_source("theme_combo")
+                    .selected_text(theme_label(&self.i18n, appearance.theme))
+                    .show_ui(ui, |ui| {
+                        for theme in Theme::ALL {
+                            ui.selectable_value(
+                                &mut appearance.theme,
+                                theme,
+                                theme_label(&self.i18n, theme),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label(self.i18n.text("accent_color"));
+                ui.horizontal(|ui| {
+                    let mut custom = appearance.accent.is_some();
+                    if ui.checkbox(&mut custom, "").changed() {
+                        // start from the selection colour of the theme shown
+                        let [r, g, b, _] = ui.visuals().selection.bg_fill.to_array();
+                        appearance.accent = custom.then_some(Rgb([r, g, b]));
+                    }
+                    if let Some(Rgb(rgb)) = &mut appearance.accent {
+                        egui::color_picker::color_edit_button_srgb(ui, rgb);
+                    }
+                });
+                ui.end_row();
+
+                // the sliders rescale themselves, so the settings only follow once let go
+                ui.label(self.i18n.text("zoom"));
+                let response = ui.add(
+                    egui::Slider::new(&mut self.zoom, appearance::ZOOM_RANGE)
+                        .custom_formatter(|zoom, _| format!("{:.0}%", zoom * 100.0))
+                        .custom_parser(|text| {
+                            let percent = text.trim().trim_end_matches('%').trim();
+                            percent.parse::<f64>().ok().map(|percent| percent / 100.0)
+                        }),
+                );
+                if !response.dragged() {
+                    appearance.zoom = self.zoom;
+                }
+                ui.end_row();
+
+                ui.label(self.i18n.text("font_size"));
+                let response = ui.add(
+                    egui::Slider::new(&mut self.font_size, appearance::FONT_SIZE_RANGE)
+                        .step_by(0.5),
+                );
+                if !response.dragged() {
+                    appearance.font_size = self.font_size;
+                }
+                ui.end_row();
+
+                for (label, font) in [
+                    ("text_font", &mut appearance.proportional_font),
+                    ("code_font", &mut appearance.monospace_font),
+                ] {
+                    ui.label(self.i18n.text(label));
+                    ui.horizontal(|ui| {
+                        let name = match font {
+                            Some(path) => path
+                                .file_name()
+                                .unwrap_or(path.as_os_str())
+                                .to_string_lossy()
+                                .into_owned(),
+                            None => self.i18n.text("default_font").to_owned(),
+                        };
+                        let mut button = ui.button(name);
+                        if let Some(path) = font {
+                            button = button.on_hover_text(path.display().to_string());
+                        }
+                        if button.clicked() {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter(self.i18n.text("fonts"), &["ttf", "otf", "ttc"])
+                                .pick_file()
+                            {
+                                *font = Some(path);
+                            }
+                        }
+                        if font.is_some()
+                            && ui
+                                .small_button("✖")
+                                .on_hover_text(self.i18n.text("use_default_font"))
+                                .clicked()
+                        {
+                            *font = None;
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+            if ui.button(self.i18n.text("restore_defaults")).clicked() {
+                self.settings.appearance = Appearance::default();
+                self.zoom = self.settings.appearance.zoom;
+                self.font_size = self.settings.appearance.font_size;
+            }
+            if self.settings.appearance != before {
+                self.appearance_unsaved = true;
+            }
+        });
+        if self.appearance_unsaved && !ui.ctx().is_using_pointer() {
+            self.appearance_unsaved = false;
+            self.store_settings();
+        }
+    }
+
+    fn render_notification(&mut self, ctx: &egui::Context) {
+        self.toasts.show(ctx);
+    }
+}
+
+//main loop running for ever
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.apply_appearance(ctx, frame);
+        self.receive_done();
+        self.handle_shortcuts(ctx);
+        self.render_side_panel(ctx);
+        self.swap_draft(ctx);
+        self.render_input_box(ctx);
+        self.render_find_bar(ctx);
+
+        self.render_history_panel(ctx);
+        self.render_search_window(ctx);
+
+        self.render_template_form(ctx);
+        self.render_template_editor(ctx);
+        self.render_recall_search(ctx);
+        self.render_cheat_sheet(ctx);
+
+        self.handle_dropped_files(ctx);
+        self.render_tool_approval(ctx);
+        self.render_notification(ctx);
+    }
+
+    fn on_close_event(&mut self) -> bool {
+        if let Err(err) = self.drafts.set(&self.draft_key, &self.pmt) {
+            eprintln!("failed to save the draft: {err}");
+        }
+        if self.appearance_unsaved {
+            if let Err(err) = self.settings.store(&self.app_name) {
+                eprintln!("failed to save the settings: {err}");
+            }
+        }
+        true
+    }
+}
+
+fn action_label(i18n: &Catalog, action: Action) -> &str {
+    match action {
+        Action::NewChat => i18n.text("action_new_chat"),
+        Action::NextChat => i18n.text("action_next_chat"),
+        Action::PreviousChat => i18n.text("action_previous_chat"),
+        Action::FocusInput => i18n.text("action_focus_input"),
+        Action::Stop => i18n.text("action_stop"),
+        Action::Regenerate => i18n.text("action_regenerate"),
+        Action::CopyLastAnswer => i18n.text("action_copy_last_answer"),
+        Action::ToggleSettings => i18n.text("action_toggle_settings"),
+        Action::Search => i18n.text("search_all"),
+        Action::Find => i18n.text("action_find"),
+        Action::RecallPrompt => i18n.text("action_recall_prompt"),
+        Action::CheatSheet => i18n.text("action_cheat_sheet"),
+    }
+}
+
+fn theme_label(i18n: &Catalog, theme: Theme) -> &str {
+    match theme {
+        Theme::System => i18n.text("follow_system"),
+        Theme::Light => i18n.text("theme_light"),
+        Theme::Dark => i18n.text("theme_dark"),
+    }
+}
+
+fn send_mode_label(i18n: &Catalog, mode: SendMode) -> &str {
+    match mode {
+        SendMode::Enter => i18n.text("send_enter"),
+        SendMode::CtrlEnter => i18n.text("send_ctrl_enter"),
+    }
+}
+
+/// `shortcut` for egui, `None` when its key has no name egui knows
+fn keyboard_shortcut(shortcut: &Shortcut) -> Option<egui::KeyboardShortcut> {
+    use egui::Key::*;
+    let key = [
+        ArrowDown, ArrowLeft, ArrowRight, ArrowUp, Escape, Tab, Backspace, Enter, Space, Insert,
+        Delete, Home, End, PageUp, PageDown, Minus, PlusEquals, Num0, Num1, Num2, Num3, Num4, Num5,
+        Num6, Num7, Num8, Num9, A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V,
+        W, X, Y, Z, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, F14, F15, F16, F17,
+        F18, F19, F20,
+    ]
+    .into_iter()
+    .find(|k| k.name().eq_ignore_ascii_case(&shortcut.key))?;
+    let mut modifiers = egui::Modifiers::NONE;
+    if shortcut.ctrl {
+        modifiers = modifiers | egui::Modifiers::COMMAND;
+    }
+    if shortcut.alt {
+        modifiers = modifiers | egui::Modifiers::ALT;
+    }
+    if shortcut.shift {
+        modifiers = modifiers | egui::Modifiers::SHIFT;
+    }
+    Some(egui::KeyboardShortcut::new(modifiers, key))
+}
+
+/// the text of a message as the history shows it, user messages without their attachments
+fn shown_text(msg: &Message) -> String {
+    match msg.role {
+        Role::User => attachment::text(&msg.content),
+        _ => msg.content.clone(),
+    }
+}
+
+/// puts the cursor of the text edit `id` showing `text` after its last character
+fn move_cursor_to_end(ctx: &egui::Context, id: egui::Id, text: &str) {
+    if let Some(mut state) = egui::TextEdit::load_state(ctx, id) {
+        let end = egui::text::CCursor::new(text.chars().count());
+        state.set_ccursor_range(Some(egui::text::CCursorRange::one(end)));
+        state.store(ctx, id);
+    }
+}
+
+/// puts the decoded image on the clipboard, which only takes raw pixels
+fn copy_image(url: &str) -> Result<(), String> {
+    let bytes = images::decode(url).ok_or("invalid data URL")?;
+    let image = image::load_from_memory(&bytes)
+        .map_err(|e| e.to_string())?
+        .to_rgba8();
+    let image = arboard::ImageData {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        bytes: image.into_raw().into(),
+    };
+    arboard::Clipboard::new()
+        .and_then(|mut c| c.set_image(image))
+        .map_err(|e| e.to_string())
+}
+
+/// the attachments of a message as collapsed headers showing the file when opened
+fn render_attachment_chips(ui: &mut egui::Ui, i18n: &Catalog, index: usize, content: &str) {
+    for (i, part) in attachment::split(content).into_iter().enumerate() {
+        if let Part::Attachment { name, content } = part {
+            egui::CollapsingHeader::new(i18n.format(
+                "attachment_lines",
+                &[
+                    ("name", name.to_string()),
+                    ("lines", content.lines().count().to_string()),
+                ],
+            ))
+            .id_source(("attachment", index, i))
+            .show(ui, |ui| {
+                ui.add(egui::Label::new(egui::RichText::new(content).monospace()).wrap(true));
+            });
+        }
+    }
+}
+
+/// the tool calls of a reply as collapsed steps showing the arguments and the result when opened
+fn render_tool_calls(ui: &mut egui::Ui, i18n: &Catalog, index: usize, calls: &[ToolCall]) {
+    for (i, call) in calls.iter().enumerate() {
+        let status = match &call.result {
+            None => "⏳",
+            Some(result) if result == tools::DECLINED => "🚫",
+            Some(result) if result.starts_with("error:") => "❌",
+            Some(_) => "✔",
+        };
+        egui::CollapsingHeader::new(format!("🛠 {} {status}", call.name))
+            .id_source(("tool_call", index, i))
+            .show(ui, |ui| {
+                ui.label(i18n.text("arguments"));
+                ui.add(
+                    egui::Label::new(egui::RichText::new(&call.arguments).monospace()).wrap(true),
+                );
+                if let Some(result) = &call.result {
+                    ui.label(i18n.text("result"));
+                    ui.add(egui::Label::new(egui::RichText::new(result).monospace()).wrap(true));
+                }
+            });
+    }
+}
+
+/// `text` with a highlighted background behind the byte ranges in `highlights`, the one at
+/// `current` stands out from the others
+fn highlight_job(
+    ui: &egui::Ui,
+    text: &str,
+    highlights: &[std::ops::Range<usize>],
+    current: Option<usize>,
+) -> egui::text::LayoutJob {
+    let normal = egui::TextFormat {
+        color: ui.visuals().text_color(),
+        ..Default::default()
+    };
+    let highlighted = egui::TextFormat {
+        color: egui::Color32::BLACK,
+        background: egui::Color32::from_rgb(255, 214, 102),
+        ..Default::default()
+    };
+    let mut job = egui::text::LayoutJob::default();
+    let selected = egui::TextFormat {
+        background: egui::Color32::from_rgb(255, 140, 0),
+        ..highlighted.clone()
+    };
+    let mut last = 0;
+    for (i, range) in highlights.iter().enumerate() {
+        job.append(&text[last..range.start], 0.0, normal.clone());
+        let format = if current == Some(i) {
+            selected.clone()
+        } else {
+            highlighted.clone()
+        };
+        job.append(&text[range.clone()], 0.0, format);
+        last = range.end;
     }
+    job.append(&text[last..], 0.0, normal);
+    job
 }
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::Deserializer;
+use serde_derive::{Deserialize, Serialize};
+
+/// smallest and largest scale of the window
+pub const ZOOM_RANGE: std::ops::RangeInclusive<f32> = 0.5..=3.0;
+/// smallest and largest size of the body text, in points
+pub const FONT_SIZE_RANGE: std::ops::RangeInclusive<f32> = 8.0..=32.0;
+/// size of the body text in egui's default style, the other text styles are scaled from it
+pub const DEFAULT_FONT_SIZE: f32 = 12.5;
+
+/// Whether the window is drawn light or dark
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Theme {
+    /// like the desktop, dark when it does not tell
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::System, Theme::Light, Theme::Dark];
+}
+
+/// A colour written `#rrggbb` in the settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rgb(pub [u8; 3]);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().strip_prefix('#').unwrap_or(s.trim());
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Rgb([r, g, b])),
+            _ => Err(format!("`{s}` is not a colour like #4a90d9")),
+        }
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(rgb: Rgb) -> Self {
+        rgb.to_string()
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+/// How the window looks
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Appearance {
+    #[serde(deserialize_with = "or_default")]
+    pub theme: Theme,
+    /// colour of selections and links, `None` keeps the one of the theme
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "or_default"
+    )]
+    pub accent: Option<Rgb>,
+    /// scale of the whole window on top of the display's, see [`ZOOM_RANGE`]
+    pub zoom: f32,
+    /// points of the body text, headings and small text are scaled along
+    pub font_size: f32,
+    /// .ttf or .otf file for the text, `None` for egui's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proportional_font: Option<PathBuf>,
+    /// .ttf or .otf file for code, `None` for egui's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monospace_font: Option<PathBuf>,
+    /// directories searched for CJK, Cyrillic and emoji fonts before the system's
+    pub font_dirs: Vec<PathBuf>,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Self {
+            theme: Theme::System,
+            accent: None,
+            zoom: 1.0,
+            font_size: DEFAULT_FONT_SIZE,
+            proportional_font: None,
+            monospace_font: None,
+            font_dirs: Vec::new(),
+        }
+    }
+}
+
+impl Appearance {
+    /// `zoom` and `font_size` within their ranges, for values edited by hand in the settings file
+    pub fn clamped(mut self) -> Self {
+        self.zoom = self.zoom.clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end());
+        self.font_size = self
+            .font_size
+            .clamp(*FONT_SIZE_RANGE.start(), *FONT_SIZE_RANGE.end());
+        self
+    }
+}
+
+/// Reads a value edited by hand, a typo keeps the default with a warning instead of failing to
+/// load the settings.
+fn or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = <serde_json::Value as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value.clone()).unwrap_or_else(|err| {
+        eprintln!("ignoring `{value}` in the appearance settings: {err}");
+        T::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_are_read_with_or_without_hash() {
+        assert_eq!("#4a90d9".parse(), Ok(Rgb([0x4a, 0x90, 0xd9])));
+        assert_eq!(" 4A90D9 ".parse(), Ok(Rgb([0x4a, 0x90, 0xd9])));
+        assert_eq!(Rgb([0x4a, 0x90, 0xd9]).to_string(), "#4a90d9");
+        for text in ["#4a90d", "#4a90d9ff", "#4g90d9", "blue", "#ééé"] {
+            assert!(text.parse::<Rgb>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn typos_keep_the_default_of_those_entries() {
+        let appearance: Appearance = toml::from_str(
+            r##"
+            theme = "Drak"
+            accent = "#4a90dg"
+            zoom = 1.5
+            "##,
+        )
+        .unwrap();
+        assert_eq!(
+            appearance,
+            Appearance {
+                zoom: 1.5,
+                ..Appearance::default()
+            }
+        );
+    }
+
+    #[test]
+    fn appearances_are_read_as_they_are_written() {
+        let appearance = Appearance {
+            theme: Theme::Light,
+            accent: Some(Rgb([1, 2, 3])),
+            font_dirs: vec![PathBuf::from("/fonts")],
+            ..Appearance::default()
+        };
+        let text = toml::to_string(&appearance).unwrap();
+        assert_eq!(toml::from_str::<Appearance>(&text).unwrap(), appearance);
+    }
+}
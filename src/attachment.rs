@@ -0,0 +1,242 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// files larger than this are refused
+pub const MAX_BYTES: u64 = 512 * 1024;
+/// attachments estimated at more tokens than this get a warning
+pub const WARN_TOKENS: usize = 8_000;
+
+const OPEN_TAG: &str = "<attachment name=\"";
+const CLOSE_TAG: &str = "</attachment>";
+
+/// A text file sent along with a prompt
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    /// file name without the directory
+    pub name: String,
+    pub content: String,
+}
+
+impl Attachment {
+    /// Reads a text file, refusing binary files and files over [`MAX_BYTES`].
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let size = fs::metadata(path)?.len();
+        if size > MAX_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} KiB is over the {} KiB limit",
+                    size / 1024,
+                    MAX_BYTES / 1024
+                ),
+            ));
+        }
+        let bytes = fs::read(path)?;
+        let content = match String::from_utf8(bytes) {
+            Ok(content) if !content.contains('\0') => content,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a UTF-8 text file",
+                ))
+            }
+        };
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Self { name, content })
+    }
+
+    pub fn tokens(&self) -> usize {
+        estimate_tokens(&self.content)
+    }
+
+    /// a warning to show when the file takes a big part of the context window
+    pub fn warning(&self) -> Option<String> {
+        let tokens = self.tokens();
+        (tokens > WARN_TOKENS).then(|| {
+            format!(
+                "{} is about {tokens} tokens and may not fit the model's context",
+                self.name
+            )
+        })
+    }
+}
+
+/// A rough token count: four ASCII characters or one other character make a token.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    ascii.div_ceil(4) + other
+}
+
+/// The user message for `text` followed by the attachments, each between
+/// `<attachment name="…">` and `</attachment>` lines.
+pub fn compose(text: &str, attachments: &[Attachment]) -> String {
+    let mut message = text.trim().to_owned();
+    for attachment in attachments {
+        if !message.is_empty() {
+            message.push_str("\n\n");
+        }
+        // quotes would end the name early when reading it back
+        let name = attachment.name.replace('"', "'");
+        message.push_str(&format!(
+            "{OPEN_TAG}{name}\">\n{}\n{CLOSE_TAG}",
+            attachment.content.trim_end_matches('\n')
+        ));
+    }
+    message
+}
+
+/// A piece of a message, as split by [`split`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Part<'a> {
+    Text(&'a str),
+    Attachment { name: &'a str, content: &'a str },
+}
+
+/// Splits a message written by [`compose`] into its text and attachments.
+pub fn split(message: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = message;
+    while let Some(start) = find_open_tag(rest) {
+        let header = &rest[start + OPEN_TAG.len()..];
+        let Some(name_end) = header.find("\">\n") else {
+            break;
+        };
+        let body = &header[name_end + 3..];
+        let (content, after) = match body.find(&format!("\n{CLOSE_TAG}")) {
+            Some(end) => (&body[..end], &body[end + 1 + CLOSE_TAG.len()..]),
+            // cut off, e.g. by an edit
+            None => (body, ""),
+        };
+        let text = rest[..start].trim();
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        parts.push(Part::Attachment {
+            name: &header[..name_end],
+            content,
+        });
+        rest = after;
+    }
+    let text = rest.trim();
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    parts
+}
+
+/// the text parts of a message, without its attachments
+pub fn text(message: &str) -> String {
+    let parts: Vec<&str> = split(message)
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Text(text) => Some(text),
+            Part::Attachment { .. } => None,
+        })
+        .collect();
+    parts.join("\n\n")
+}
+
+/// the tag only counts at the start of a line
+fn find_open_tag(text: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(i) = text[from..].find(OPEN_TAG) {
+        let i = from + i;
+        if i == 0 || text[..i].ends_with('\n') {
+            return Some(i);
+        }
+        from = i + OPEN_TAG.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &str, content: &str) -> Attachment {
+        Attachment {
+            name: name.to_owned(),
+            content: content.to_owned(),
+        }
+    }
+
+    #[test]
+    fn split_gives_back_what_compose_put_together() {
+        let attachments = [
+            attachment("main.rs", "fn main() {}\n"),
+            attachment("empty.txt", ""),
+            attachment("notes.md", "<attachment name=\"inner\">\nquoted\n"),
+        ];
+        let message = compose("  What do these do?\n", &attachments);
+        assert_eq!(
+            split(&message),
+            [
+                Part::Text("What do these do?"),
+                Part::Attachment {
+                    name: "main.rs",
+                    content: "fn main() {}"
+                },
+                Part::Attachment {
+                    name: "empty.txt",
+                    content: ""
+                },
+                Part::Attachment {
+                    name: "notes.md",
+                    content: "<attachment name=\"inner\">\nquoted"
+                },
+            ]
+        );
+        assert_eq!(text(&message), "What do these do?");
+    }
+
+    #[test]
+    fn quotes_in_names_do_not_end_them() {
+        let message = compose("", &[attachment("say \"hi\".txt", "hi")]);
+        assert_eq!(
+            split(&message),
+            [Part::Attachment {
+                name: "say 'hi'.txt",
+                content: "hi"
+            }]
+        );
+        assert_eq!(text(&message), "");
+    }
+
+    #[test]
+    fn tags_only_count_at_the_start_of_a_line() {
+        let message = "see <attachment name=\"a\">\nnot one\n</attachment>";
+        assert_eq!(split(message), [Part::Text(message)]);
+    }
+
+    #[test]
+    fn attachments_cut_off_by_an_edit_keep_the_rest() {
+        let message = "Look\n\n<attachment name=\"a.txt\">\nfirst line\nsecond";
+        assert_eq!(
+            split(message),
+            [
+                Part::Text("Look"),
+                Part::Attachment {
+                    name: "a.txt",
+                    content: "first line\nsecond"
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_count_four_ascii_characters_or_one_other() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("中文 ab"), 3);
+    }
+}
@@ -0,0 +1,291 @@
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavReader, WavWriter};
+use sha2::{Digest, Sha256};
+
+use crate::client::Client;
+use crate::settings;
+
+/// the transcription endpoints refuse larger files
+pub const MAX_BYTES: u64 = 25 * 1024 * 1024;
+
+/// whether `path` looks like a recording the transcription endpoints accept
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            matches!(
+                ext.to_lowercase().as_str(),
+                "mp3" | "mp4" | "mpeg" | "mpga" | "m4a" | "wav" | "webm" | "ogg" | "oga" | "flac"
+            )
+        })
+}
+
+/// A recording read for transcription
+#[derive(Clone, Debug)]
+pub struct Recording {
+    /// file name without the directory, the endpoints tell the format by its extension
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+impl Recording {
+    /// Reads an audio file, refusing files over [`MAX_BYTES`].
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let size = fs::metadata(path)?.len();
+        if size > MAX_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} MiB is over the {} MiB limit",
+                    size / 1024 / 1024,
+                    MAX_BYTES / 1024 / 1024
+                ),
+            ));
+        }
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "audio.wav".to_owned());
+        Ok(Self {
+            name,
+            bytes: fs::read(path)?,
+        })
+    }
+}
+
+/// the speech endpoints read at most this many characters per request
+pub const MAX_SPEECH_CHARS: usize = 4096;
+
+/// The file types text can be read aloud into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeechFormat {
+    Mp3,
+    Wav,
+}
+
+impl SpeechFormat {
+    pub const ALL: [SpeechFormat; 2] = [SpeechFormat::Mp3, SpeechFormat::Wav];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SpeechFormat::Mp3 => "mp3",
+            SpeechFormat::Wav => "wav",
+        }
+    }
+
+    /// accepts the extension in any case, e.g. `mp3` or `WAV`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::ALL.into_iter().find(|f| f.extension() == name)
+    }
+}
+
+/// Splits `text` into pieces of at most `max` characters, preferably at the end of a
+/// paragraph, a sentence or a word.
+pub fn speech_chunks(text: &str, max: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let Some((end, _)) = rest.char_indices().nth(max) else {
+            chunks.push(rest);
+            break;
+        };
+        let head = &rest[..end];
+        let cut = ["\n\n", "\n", "。", ". ", "！", "! ", "？", "? ", " "]
+            .iter()
+            .find_map(|sep| head.rfind(sep).map(|i| i + sep.len()))
+            .unwrap_or(end);
+        let chunk = rest[..cut].trim();
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        rest = rest[cut..].trim_start();
+    }
+    chunks
+}
+
+/// One audio file made of the pieces read by several requests
+pub fn join(format: SpeechFormat, mut parts: Vec<Vec<u8>>) -> io::Result<Vec<u8>> {
+    if parts.len() == 1 {
+        return Ok(parts.remove(0));
+    }
+    match format {
+        // MP3 frames can simply follow each other
+        SpeechFormat::Mp3 => Ok(parts.concat()),
+        SpeechFormat::Wav => {
+            let readers = parts
+                .into_iter()
+                .map(|part| WavReader::new(Cursor::new(part)).map_err(invalid))
+                .collect::<io::Result<Vec<_>>>()?;
+            let mut out = Cursor::new(Vec::new());
+            let mut writer = WavWriter::new(&mut out, readers[0].spec()).map_err(invalid)?;
+            for mut reader in readers {
+                match reader.spec().sample_format {
+                    SampleFormat::Int => {
+                        for sample in reader.samples::<i32>() {
+                            writer
+                                .write_sample(sample.map_err(invalid)?)
+                                .map_err(invalid)?;
+                        }
+                    }
+                    SampleFormat::Float => {
+                        for sample in reader.samples::<f32>() {
+                            writer
+                                .write_sample(sample.map_err(invalid)?)
+                                .map_err(invalid)?;
+                        }
+                    }
+                }
+            }
+            writer.finalize().map_err(invalid)?;
+            Ok(out.into_inner())
+        }
+    }
+}
+
+/// Audio of messages read aloud, kept as files in a directory next to the settings file.
+#[derive(Clone, Debug)]
+pub struct SpeechCache {
+    dir: PathBuf,
+}
+
+impl SpeechCache {
+    pub fn open(app_name: &str) -> io::Result<Self> {
+        let dir = settings::config_dir(app_name)?.join("speech");
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// The file for message `index` of a conversation, a changed text gets a new file.
+    pub fn path(
+        &self,
+        conversation: &str,
+        index: usize,
+        text: &str,
+        format: SpeechFormat,
+    ) -> PathBuf {
+        let digest = Sha256::digest(text);
+        let hash: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+        self.dir.join(format!(
+            "{conversation}-{index}-{hash}.{}",
+            format.extension()
+        ))
+    }
+
+    /// The audio file of a message, asking `client` to read it aloud when it is not cached.
+    pub async fn read_aloud(
+        &self,
+        client: &Client,
+        conversation: &str,
+        index: usize,
+        text: &str,
+        format: SpeechFormat,
+    ) -> chatgpt::Result<PathBuf> {
+        let path = self.path(conversation, index, text, format);
+        if path.exists() {
+            return Ok(path);
+        }
+        let mut parts = Vec::new();
+        for chunk in speech_chunks(text, MAX_SPEECH_CHARS) {
+            parts.push(client.speech(chunk, format).await?);
+        }
+        if parts.is_empty() {
+            return Err(chatgpt::err::Error::ParsingError(
+                "nothing to read aloud".to_owned(),
+            ));
+        }
+        fs::write(&path, join(format, parts)?)?;
+        Ok(path)
+    }
+}
+
+fn invalid(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_speech_paths_do_not_change_between_builds() {
+        let cache = SpeechCache {
+            dir: PathBuf::from("speech"),
+        };
+        assert_eq!(
+            cache.path("1700000000000", 2, "Hello!", SpeechFormat::Mp3),
+            PathBuf::from("speech/1700000000000-2-334d016f755cd6dc.mp3")
+        );
+    }
+    #[test]
+    fn speech_is_cut_at_the_end_of_sentences() {
+        assert_eq!(
+            speech_chunks("第一句。第二句！第三句？", 5),
+            ["第一句。", "第二句！", "第三句？"]
+        );
+        assert_eq!(
+            speech_chunks("One two. Three four", 12),
+            ["One two.", "Three four"]
+        );
+        assert_eq!(
+            speech_chunks("First part\n\nsecond. part", 20),
+            ["First part", "second. part"]
+        );
+    }
+
+    #[test]
+    fn speech_without_separators_is_cut_anywhere() {
+        assert_eq!(speech_chunks("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(speech_chunks("一二三四五", 2), ["一二", "三四", "五"]);
+    }
+
+    #[test]
+    fn speech_of_exactly_max_characters_is_one_chunk() {
+        assert_eq!(speech_chunks("  ab cd \n", 5), ["ab cd"]);
+        assert_eq!(speech_chunks("你好世界", 4), ["你好世界"]);
+        assert!(speech_chunks(" \n ", 4).is_empty());
+    }
+
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut out, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn wav_parts_are_joined_under_one_header() {
+        let joined = join(SpeechFormat::Wav, vec![wav(&[1, 2, 3]), wav(&[-4, 5])]).unwrap();
+        assert_eq!(joined, wav(&[1, 2, 3, -4, 5]));
+        let riff_size = u32::from_le_bytes(joined[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize, joined.len() - 8);
+
+        let mut reader = WavReader::new(Cursor::new(joined)).unwrap();
+        assert_eq!(reader.len(), 5);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples, [1, 2, 3, -4, 5]);
+    }
+
+    #[test]
+    fn mp3_parts_follow_each_other() {
+        let parts = vec![vec![0xff, 0xfb, 1], vec![0xff, 0xfb, 2]];
+        assert_eq!(
+            join(SpeechFormat::Mp3, parts).unwrap(),
+            [0xff, 0xfb, 1, 0xff, 0xfb, 2]
+        );
+        assert_eq!(join(SpeechFormat::Wav, vec![vec![1, 2]]).unwrap(), [1, 2]);
+        assert!(join(SpeechFormat::Wav, vec![vec![1, 2], vec![3]]).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures_util::{future, FutureExt};
+
+use oxidized_gpt::attachment::{self, Attachment};
+use oxidized_gpt::audio::{Recording, SpeechCache, SpeechFormat};
+use oxidized_gpt::client::Client;
+use oxidized_gpt::documents::Library;
+use oxidized_gpt::export::{self, Format};
+use oxidized_gpt::images;
+use oxidized_gpt::import;
+use oxidized_gpt::search::{self, Filter, SearchIndex};
+use oxidized_gpt::session::ChatSession;
+use oxidized_gpt::settings::{self, Settings};
+use oxidized_gpt::store::{Message, Role, Store, ToolCall};
+use oxidized_gpt::templates::{self, Pack};
+use oxidized_gpt::tools::{Confirm, Registry};
+
+/// Arguments of the `ask` subcommand
+#[derive(clap::Args)]
+pub struct AskArgs {
+    /// Role of the profile to answer with, defaults to the first one
+    #[arg(short, long)]
+    role: Option<String>,
+    /// Text or image file to send along, can be given several times
+    #[arg(short, long)]
+    attach: Vec<PathBuf>,
+    /// Let the model call the local tools allowed in the settings
+    #[arg(short, long)]
+    tools: bool,
+    /// Run tool calls without asking
+    #[arg(short, long, requires = "tools")]
+    yes: bool,
+    /// Saved template to send, the prompt fills its one variable not given with --var
+    #[arg(short = 'T', long)]
+    template: Option<String>,
+    /// Value of a template variable as NAME=VALUE, can be given several times
+    #[arg(
+        short = 'V',
+        long = "var",
+        value_name = "NAME=VALUE",
+        requires = "template"
+    )]
+    vars: Vec<String>,
+    prompt: Vec<String>,
+}
+
+/// Sends a single prompt with one of the current profile's roles and prints the answer to stdout.
+///
+/// When stdin is piped its content is appended to the prompt, so both
+/// `oxidized-gpt ask "summarize" < notes.md` and `echo hi | oxidized-gpt ask` work.
+/// The `attach` files are added as attachments, images are sent as images.
+/// Images drawn by an image role are written to the current directory and their names printed.
+/// With `tools` the model may call the local tools, each call is asked about on the terminal
+/// unless `yes` runs them all.
+/// Roles with documents get the matching passages from `library`, which are listed after the answer.
+pub async fn ask(settings: &Settings, library: &Library, args: AskArgs) -> Result<(), String> {
+    let AskArgs {
+        role,
+        attach,
+        tools,
+        yes,
+        template,
+        vars,
+        prompt,
+    } = args;
+    let role = find_role(settings, role)?;
+
+    let mut pmt = prompt.join(" ");
+    let mut stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        let mut piped = String::new();
+        stdin
+            .read_to_string(&mut piped)
+            .map_err(|e| e.to_string())?;
+        if !pmt.is_empty() && !piped.trim().is_empty() {
+            pmt.push_str("\n\n");
+        }
+        pmt.push_str(piped.trim_end());
+    }
+    if let Some(name) = template {
+        pmt = fill_template(settings, &name, vars, pmt)?;
+    }
+    let mut attachments = Vec::new();
+    let mut images = Vec::new();
+    for path in attach {
+        if images::is_image(&path) {
+            images.push(images::read(&path).map_err(|e| format!("{path:?}: {e}"))?);
+            continue;
+        }
+        let attachment = Attachment::read(&path).map_err(|e| format!("{path:?}: {e}"))?;
+        if let Some(warning) = attachment.warning() {
+            eprintln!("warning: {warning}");
+        }
+        attachments.push(attachment);
+    }
+    if pmt.trim().is_empty() && attachments.is_empty() && images.is_empty() {
+        return Err("empty prompt".to_owned());
+    }
+    let mut msg = Message::new(Role::User, attachment::compose(&pmt, &attachments));
+    msg.images = images;
+
+    let mut session = ChatSession::new(settings, role)
+        .map_err(|e| e.to_string())?
+        .with_documents(library.clone());
+    if tools {
+        let registry = Arc::new(Registry::local(&settings.tools));
+        // piped stdin was read above, there is nobody to ask
+        let interactive = std::io::stdin().is_terminal();
+        let confirm: Confirm = Arc::new(move |call: ToolCall| {
+            eprintln!("\n🛠 {}({})", call.name, call.arguments);
+            if yes {
+                return future::ready(true).boxed();
+            }
+            if !interactive {
+                eprintln!("declined, use --yes to run tools without asking");
+                return future::ready(false).boxed();
+            }
+            tokio::task::spawn_blocking(|| {
+                eprint!("run it? [y/N] ");
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer).is_ok()
+                    && matches!(answer.trim(), "y" | "Y" | "yes")
+            })
+            .map(|answer| answer.unwrap_or(false))
+            .boxed()
+        });
+        session = session.with_tools(registry, confirm);
+    }
+    let mut stdout = std::io::stdout();
+    session
+        .stream_message(msg, |delta| {
+            // a closed stdout (e.g. piped into `head`) just stops the output
+            let _ = stdout
+                .write_all(delta.as_bytes())
+                .and_then(|_| stdout.flush());
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    println!();
+
+    let (drawn, citations) = match session.history().last() {
+        Some(msg) if msg.role == Role::Assistant => (msg.images.clone(), msg.citations.clone()),
+        _ => Default::default(),
+    };
+    if !citations.is_empty() {
+        println!();
+    }
+    for (i, citation) in citations.iter().enumerate() {
+        println!("[{}] {}", i + 1, citation.location());
+    }
+    for (i, url) in drawn.iter().enumerate() {
+        let path = PathBuf::from(format!(
+            "{}-{}.{}",
+            session.id(),
+            i + 1,
+            images::extension(url)
+        ));
+        let bytes = images::decode(url).ok_or("the reply has an invalid image")?;
+        std::fs::write(&path, bytes).map_err(|e| format!("{path:?}: {e}"))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Prints the text spoken in an audio file, using the current profile's transcription endpoint.
+pub async fn transcribe(settings: &Settings, path: PathBuf) -> Result<(), String> {
+    let recording = Recording::read(&path).map_err(|e| format!("{path:?}: {e}"))?;
+    let client = Client::new(settings.current_profile()).map_err(|e| e.to_string())?;
+    let text = client
+        .transcribe(recording)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("{}", text.trim());
+    Ok(())
+}
+
+/// Reads a saved assistant message aloud into an audio file, reusing the audio cached by the
+/// window app.
+///
+/// Defaults to the last reply of the latest conversation, written to `<id>-<index>.<format>`.
+pub async fn speak(
+    settings: &Settings,
+    store: &Store,
+    cache: &SpeechCache,
+    id: Option<String>,
+    message: Option<usize>,
+    format: &str,
+    output: Option<PathBuf>,
+) -> Result<(), String> {
+    let format =
+        SpeechFormat::from_name(format).ok_or_else(|| format!("unknown format `{format}`"))?;
+    let conversation = match id {
+        Some(id) => store.load(&id).map_err(|e| format!("{id}: {e}"))?,
+        None => store
+            .list()
+            .into_iter()
+            .next()
+            .ok_or("no saved conversations")?,
+    };
+    let index = match message {
+        Some(index) => index,
+        None => conversation
+            .messages
+            .iter()
+            .rposition(|m| m.role == Role::Assistant)
+            .ok_or("the conversation has no replies")?,
+    };
+    let msg = conversation
+        .messages
+        .get(index)
+        .filter(|m| m.role == Role::Assistant)
+        .ok_or_else(|| format!("message {index} is not a reply"))?;
+
+    let profile = settings
+        .profile(&conversation.profile)
+        .unwrap_or_else(|| settings.current_profile());
+    let client = Client::new(profile).map_err(|e| e.to_string())?;
+    let cached = cache
+        .read_aloud(&client, &conversation.id, index, &msg.content, format)
+        .await
+        .map_err(|e| e.to_string())?;
+    let output = output.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "{}-{index}.{}",
+            conversation.id,
+            format.extension()
+        ))
+    });
+    std::fs::copy(&cached, &output).map_err(|e| format!("{output:?}: {e}"))?;
+    println!("{}", output.display());
+    Ok(())
+}
+
+/// Brings the document index of `role`, or of every role of the current profile with documents,
+/// up to date and prints what changed.
+pub async fn index(
+    settings: &Settings,
+    library: &Library,
+    role: Option<String>,
+) -> Result<(), String> {
+    let roles: Vec<&settings::Role> = match role {
+        Some(name) => vec![find_role(settings, Some(name))?],
+        None => settings
+            .current_profile()
+            .role_list
+            .iter()
+            .filter(|r| r.documents.is_some())
+            .collect(),
+    };
+    let client = Client::new(settings.current_profile()).map_err(|e| e.to_string())?;
+    let mut indexed = 0;
+    for role in roles {
+        let Some(documents) = &role.documents else {
+            return Err(format!("role `{}` has no documents folder", role.name));
+        };
+        let reindexed = library
+            .reindex(&client, &documents.folder)
+            .await
+            .map_err(|e| format!("{}: {e}", role.name))?;
+        println!(
+            "{}  {}  {} files, {} passages ({} added, {} changed, {} removed)",
+            role.name,
+            documents.folder.display(),
+            reindexed.files,
+            reindexed.passages,
+            reindexed.added,
+            reindexed.changed,
+            reindexed.removed
+        );
+        indexed += 1;
+    }
+    if indexed == 0 {
+        return Err("no role of the profile has a documents folder".to_owned());
+    }
+    Ok(())
+}
+
+/// The saved template `name` filled with the `NAME=VALUE` pairs of `vars`, `pmt` fills the one
+/// variable left or follows the template when none is left.
+fn fill_template(
+    settings: &Settings,
+    name: &str,
+    vars: Vec<String>,
+    pmt: String,
+) -> Result<String, String> {
+    let template = settings
+        .templates
+        .iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| format!("unknown template `{name}`"))?;
+    let mut values = std::collections::HashMap::new();
+    for var in vars {
+        let (name, value) = var
+            .split_once('=')
+            .ok_or_else(|| format!("`{var}` is not NAME=VALUE"))?;
+        values.insert(name.trim().to_owned(), value.to_owned());
+    }
+    let missing: Vec<String> = template
+        .variables()
+        .into_iter()
+        .filter(|v| !values.contains_key(v))
+        .collect();
+    match missing.as_slice() {
+        [] if pmt.trim().is_empty() => Ok(template.fill(&values)),
+        [] => Ok(format!("{}\n\n{pmt}", template.fill(&values))),
+        [one] if !pmt.trim().is_empty() => {
+            values.insert(one.clone(), pmt);
+            Ok(template.fill(&values))
+        }
+        _ => Err(format!(
+            "template `{name}` needs {}, give them with --var NAME=VALUE",
+            missing.join(", ")
+        )),
+    }
+}
+
+/// What the `templates` subcommand does
+#[derive(clap::Subcommand)]
+pub enum TemplatesCommand {
+    /// List the saved templates with their variables
+    List,
+    /// Add the templates of a pack file, replacing the ones with the same name
+    Import { path: PathBuf },
+    /// Write templates to a pack file to share
+    Export {
+        path: PathBuf,
+        /// Template to include, can be given several times, defaults to all of them
+        #[arg(short, long)]
+        name: Vec<String>,
+    },
+}
+
+/// Lists, imports or exports the prompt templates, imports are saved to the settings of `app_name`.
+pub fn templates(
+    settings: &mut Settings,
+    app_name: &str,
+    command: TemplatesCommand,
+) -> Result<(), String> {
+    match command {
+        TemplatesCommand::List => {
+            for template in settings.templates.iter() {
+                let variables = template
+                    .variables()
+                    .iter()
+                    .map(|v| format!("{{{{{v}}}}}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                println!("{}  {}  {}", template.name, variables, template.description);
+            }
+        }
+        TemplatesCommand::Import { path } => {
+            let pack = Pack::read(&path).map_err(|e| format!("{path:?}: {e}"))?;
+            let (added, replaced) = templates::merge(&mut settings.templates, pack);
+            settings.store(app_name).map_err(|e| e.to_string())?;
+            println!("added {added} templates, replaced {replaced}");
+        }
+        TemplatesCommand::Export { path, name } => {
+            for name in name.iter() {
+                if !settings.templates.iter().any(|t| &t.name == name) {
+                    return Err(format!("unknown template `{name}`"));
+                }
+            }
+            let pack = Pack {
+                templates: settings
+                    .templates
+                    .iter()
+                    .filter(|t| name.is_empty() || name.contains(&t.name))
+                    .cloned()
+                    .collect(),
+            };
+            pack.write(&path).map_err(|e| format!("{path:?}: {e}"))?;
+            println!("exported {} templates", pack.templates.len());
+        }
+    }
+    Ok(())
+}
+
+/// Prints the saved conversations, most recent first
+pub fn list(store: &Store) {
+    for conversation in store.list() {
+        println!(
+            "{}  {}  {}/{}  {}",
+            conversation.id,
+            export::format_time(conversation.updated),
+            conversation.profile,
+            conversation.role,
+            conversation.title
+        );
+    }
+}
+
+/// Writes a saved conversation, the most recent one when `id` is `None`, to `output` or stdout.
+pub fn export(
+    store: &Store,
+    id: Option<String>,
+    format: &str,
+    output: Option<PathBuf>,
+    messages: Vec<usize>,
+    font: Option<PathBuf>,
+) -> Result<(), String> {
+    let format = Format::from_name(format).ok_or_else(|| format!("unknown format `{format}`"))?;
+    let conversation = match id {
+        Some(id) => store.load(&id).map_err(|e| format!("{id}: {e}"))?,
+        None => store
+            .list()
+            .into_iter()
+            .next()
+            .ok_or("no saved conversations")?,
+    };
+    let font = match font {
+        Some(path) => Some(std::fs::read(&path).map_err(|e| format!("{path:?}: {e}"))?),
+        None => None,
+    };
+    let bytes = export::export(&conversation, &messages, format, font.as_deref())
+        .map_err(|e| e.to_string())?;
+    match output {
+        Some(path) => std::fs::write(&path, bytes).map_err(|e| format!("{path:?}: {e}")),
+        None => std::io::stdout()
+            .write_all(&bytes)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Imports the conversations of a chat.openai.com data export, the zip or its `conversations.json`.
+pub fn import(
+    settings: &Settings,
+    store: &Store,
+    role: Option<String>,
+    path: PathBuf,
+) -> Result<(), String> {
+    let role = find_role(settings, role)?;
+    let conversations = import::chatgpt_export(&path, &settings.current_profile, role)
+        .map_err(|e| format!("{path:?}: {e}"))?;
+    let (saved, kept) = import::save(store, &conversations).map_err(|e| e.to_string())?;
+    println!("imported {saved} conversations");
+    if kept > 0 {
+        println!("kept {kept} conversations continued since the export");
+    }
+    Ok(())
+}
+
+/// Arguments of the `search` subcommand
+#[derive(clap::Args)]
+pub struct SearchArgs {
+    /// Words to find, the last one also matches longer words starting with it
+    #[arg(required = true)]
+    query: Vec<String>,
+    /// Only messages by user, assistant or system
+    #[arg(short, long)]
+    role: Option<String>,
+    /// Only messages sent on or after this day, YYYY-MM-DD
+    #[arg(long)]
+    since: Option<String>,
+    /// Only messages sent on or before this day, YYYY-MM-DD
+    #[arg(long)]
+    until: Option<String>,
+    /// Only conversations started with this model
+    #[arg(short, long)]
+    model: Option<String>,
+    /// Only the conversation with this id
+    #[arg(short, long)]
+    conversation: Option<String>,
+    #[arg(short, long, default_value_t = 20)]
+    limit: usize,
+}
+
+/// Prints the saved messages matching the query, matches are marked with `**`.
+pub fn search(store: &Store, args: SearchArgs) -> Result<(), String> {
+    let role = match args.role.as_deref() {
+        Some("user") => Some(Role::User),
+        Some("assistant") => Some(Role::Assistant),
+        Some("system") => Some(Role::System),
+        Some(other) => {
+            return Err(format!(
+                "unknown role `{other}`, use user, assistant or system"
+            ))
+        }
+        None => None,
+    };
+    let day = |day: Option<String>, end_of_day| match day {
+        Some(day) => search::parse_day(&day, end_of_day)
+            .map(Some)
+            .ok_or_else(|| format!("`{day}` is not a YYYY-MM-DD date")),
+        None => Ok(None),
+    };
+    let filter = Filter {
+        role,
+        since: day(args.since, false)?,
+        until: day(args.until, true)?,
+        model: args.model,
+        conversation: args.conversation,
+    };
+
+    let mut index = SearchIndex::default();
+    index.refresh(store);
+    for hit in index.search(&args.query.join(" "), &filter, args.limit) {
+        let mut snippet = String::new();
+        let mut last = 0;
+        for range in hit.highlights.iter() {
+            snippet.push_str(&hit.snippet[last..range.start]);
+            snippet.push_str(&format!("**{}**", &hit.snippet[range.clone()]));
+            last = range.end;
+        }
+        snippet.push_str(&hit.snippet[last..]);
+        println!(
+            "{} #{}  {}  {}\n    {snippet}",
+            hit.conversation,
+            hit.index,
+            export::format_time(hit.timestamp),
+            hit.title
+        );
+    }
+    Ok(())
+}
+
+/// the role of the current profile called `name`, or its first role
+fn find_role(settings: &Settings, name: Option<String>) -> Result<&settings::Role, String> {
+    let profile = settings.current_profile();
+    match name {
+        Some(name) => profile
+            .role_list
+            .iter()
+            .find(|r| r.name == name)
+            .ok_or_else(|| format!("unknown role `{name}` in profile `{}`", profile.name)),
+        None => Ok(&profile.role_list[0]),
+    }
+}
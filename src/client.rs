@@ -0,0 +1,427 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chatgpt::err::Error;
+use chatgpt::types::{ChatMessage, CompletionRequest, ServerResponse};
+use eventsource_stream::Eventsource;
+use futures_util::{future, stream, Stream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::multipart::{Form, Part};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+
+use crate::audio::{Recording, SpeechFormat};
+use crate::images;
+use crate::settings::{ImageOptions, Profile, Provider};
+use crate::store::Message;
+
+/// Talks to the chat completions endpoint configured by a [`Profile`]
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    api_url: String,
+    model: String,
+    temperature: f32,
+    transcription_url: String,
+    transcription_model: String,
+    speech_url: String,
+    speech_model: String,
+    speech_voice: String,
+    embedding_url: String,
+    embedding_model: String,
+}
+
+impl Client {
+    pub fn new(profile: &Profile) -> chatgpt::Result<Self> {
+        let mut headers = HeaderMap::new();
+        match profile.provider {
+            Provider::OpenAI => {
+                headers.insert(
+                    AUTHORIZATION,
+                    HeaderValue::from_str(&format!("Bearer {}", profile.api_key))?,
+                );
+            }
+            Provider::Azure => {
+                headers.insert("api-key", HeaderValue::from_str(&profile.api_key)?);
+            }
+            Provider::Local => {
+                if !profile.api_key.is_empty() {
+                    headers.insert(
+                        AUTHORIZATION,
+                        HeaderValue::from_str(&format!("Bearer {}", profile.api_key))?,
+                    );
+                }
+            }
+        }
+        let http = reqwest::ClientBuilder::new()
+            .default_headers(headers)
+            .build()?;
+        let transcription_url = if profile.transcription_url.is_empty() {
+            profile
+                .api_url
+                .replacen("chat/completions", "audio/transcriptions", 1)
+        } else {
+            profile.transcription_url.clone()
+        };
+        let speech_url = if profile.speech_url.is_empty() {
+            profile
+                .api_url
+                .replacen("chat/completions", "audio/speech", 1)
+        } else {
+            profile.speech_url.clone()
+        };
+        let embedding_url = if profile.embedding_url.is_empty() {
+            profile
+                .api_url
+                .replacen("chat/completions", "embeddings", 1)
+        } else {
+            profile.embedding_url.clone()
+        };
+        Ok(Self {
+            http,
+            api_url: profile.api_url.clone(),
+            model: profile.model.clone(),
+            temperature: 1.0,
+            transcription_url,
+            transcription_model: profile.transcription_model.clone(),
+            speech_url,
+            speech_model: profile.speech_model.clone(),
+            speech_voice: profile.speech_voice.clone(),
+            embedding_url,
+            embedding_model: profile.embedding_model.clone(),
+        })
+    }
+
+    pub fn set_model(&mut self, model: String) {
+        self.model = model;
+    }
+
+    /// from 0 for the most predictable replies to 2 for the most varied ones
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.temperature = temperature;
+    }
+
+    fn request<'a>(
+        &'a self,
+        messages: &'a Vec<ChatMessage>,
+        stream: bool,
+    ) -> CompletionRequest<'a> {
+        CompletionRequest {
+            model: &self.model,
+            messages,
+            stream,
+            temperature: self.temperature,
+            top_p: 1.0,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            reply_count: 1,
+        }
+    }
+
+    /// Posts an OpenAI style request body as it is, except for the model which is the profile's
+    pub async fn forward(&self, mut body: serde_json::Value) -> chatgpt::Result<reqwest::Response> {
+        body["model"] = self.model.clone().into();
+        Ok(self.http.post(&self.api_url).json(&body).send().await?)
+    }
+
+    /// Sends the history and yields the pieces of the reply as they arrive.
+    ///
+    /// `tools` are the definitions of the functions the model may call instead of answering.
+    pub async fn send_history_streaming(
+        &self,
+        history: &[Message],
+        tools: &[Value],
+    ) -> chatgpt::Result<impl Stream<Item = chatgpt::Result<Delta>>> {
+        let mut body = serde_json::to_value(self.request(&Vec::new(), true))?;
+        body["messages"] = history.iter().flat_map(sent_messages).collect();
+        if !tools.is_empty() {
+            body["tools"] = tools.into();
+        }
+        let resp = self.http.post(&self.api_url).json(&body).send().await?;
+        if !resp.status().is_success() {
+            return Err(backend_error(resp).await);
+        }
+
+        Ok(resp
+            .bytes_stream()
+            .eventsource()
+            .take_while(|event| future::ready(!matches!(event, Ok(e) if e.data == "[DONE]")))
+            .flat_map(|event| {
+                let deltas = match event {
+                    Err(err) => vec![Err(Error::ParsingError(err.to_string()))],
+                    Ok(event) => match serde_json::from_str::<StreamChunk>(&event.data) {
+                        Ok(chunk) => chunk
+                            .choices
+                            .into_iter()
+                            .next()
+                            .map(|c| c.delta.into_deltas())
+                            .unwrap_or_default(),
+                        Err(err) => vec![Err(err.into())],
+                    },
+                };
+                stream::iter(deltas)
+            }))
+    }
+
+    /// Draws `prompt` with the images generations endpoint next to the chat completions one.
+    pub async fn generate_images(
+        &self,
+        prompt: &str,
+        options: &ImageOptions,
+    ) -> chatgpt::Result<GeneratedImages> {
+        let body = json!({
+            "model": options.model,
+            "prompt": prompt,
+            "n": options.count,
+            "size": options.size,
+            "quality": options.quality,
+            "response_format": "b64_json",
+        });
+        let resp = self.http.post(self.images_url()).json(&body).send().await?;
+        if !resp.status().is_success() {
+            return Err(backend_error(resp).await);
+        }
+        let resp: ImagesResponse = resp.json().await?;
+        let mut generated = GeneratedImages::default();
+        for image in resp.data {
+            let bytes = match (image.b64_json, image.url) {
+                (Some(b64), _) => STANDARD
+                    .decode(b64)
+                    .map_err(|e| Error::ParsingError(e.to_string()))?,
+                // servers ignoring `response_format` link to the image instead,
+                // the link is signed so it is fetched without the api key
+                (None, Some(url)) => reqwest::get(url).await?.bytes().await?.to_vec(),
+                (None, None) => continue,
+            };
+            generated
+                .images
+                .push(images::from_bytes(&bytes).map_err(|e| Error::ParsingError(e.to_string()))?);
+            generated.revised_prompts.extend(image.revised_prompt);
+        }
+        // every image of a prompt usually comes with the same rewrite
+        generated.revised_prompts.dedup();
+        Ok(generated)
+    }
+
+    /// Turns speech into text with the profile's transcription endpoint.
+    pub async fn transcribe(&self, recording: Recording) -> chatgpt::Result<String> {
+        let file = Part::bytes(recording.bytes).file_name(recording.name);
+        let form = Form::new()
+            .text("model", self.transcription_model.clone())
+            .text("response_format", "json")
+            .part("file", file);
+        let resp = self
+            .http
+            .post(&self.transcription_url)
+            .multipart(form)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            return Err(backend_error(resp).await);
+        }
+        Ok(resp.json::<Transcription>().await?.text)
+    }
+
+    /// Reads `text` aloud with the profile's speech endpoint, which takes at most
+    /// [`crate::audio::MAX_SPEECH_CHARS`] characters.
+    pub async fn speech(&self, text: &str, format: SpeechFormat) -> chatgpt::Result<Vec<u8>> {
+        let body = json!({
+            "model": self.speech_model,
+            "voice": self.speech_voice,
+            "input": text,
+            "response_format": format.extension(),
+        });
+        let resp = self.http.post(&self.speech_url).json(&body).send().await?;
+        if !resp.status().is_success() {
+            return Err(backend_error(resp).await);
+        }
+        Ok(resp.bytes().await?.to_vec())
+    }
+
+    /// the model vectors of [`Client::embed`] come from, vectors of different models don't mix
+    pub fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+
+    /// One vector per text of `inputs` from the profile's embeddings endpoint, in the same order.
+    pub async fn embed(&self, inputs: &[String]) -> chatgpt::Result<Vec<Vec<f32>>> {
+        let body = json!({ "model": self.embedding_model, "input": inputs });
+        let resp = self
+            .http
+            .post(&self.embedding_url)
+            .json(&body)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            return Err(backend_error(resp).await);
+        }
+        let mut data = resp.json::<EmbeddingsResponse>().await?.data;
+        if data.len() != inputs.len() {
+            return Err(Error::ParsingError(format!(
+                "asked for {} embeddings, got {}",
+                inputs.len(),
+                data.len()
+            )));
+        }
+        data.sort_by_key(|d| d.index);
+        Ok(data.into_iter().map(|d| d.embedding).collect())
+    }
+
+    /// `api_url` with the chat completions path replaced, which keeps Azure's deployment and
+    /// api version
+    fn images_url(&self) -> String {
+        self.api_url
+            .replacen("chat/completions", "images/generations", 1)
+    }
+}
+
+/// The result of [`Client::generate_images`]
+#[derive(Debug, Default)]
+pub struct GeneratedImages {
+    /// `data:` URLs
+    pub images: Vec<String>,
+    /// the prompts the images were actually drawn with, when the model rewrote them
+    pub revised_prompts: Vec<String>,
+}
+
+/// the error message of a failed request
+async fn backend_error(resp: reqwest::Response) -> Error {
+    let status = resp.status();
+    match resp.json::<ServerResponse>().await {
+        Ok(ServerResponse::Error { error }) => Error::BackendError {
+            message: error.message,
+            error_type: error.error_type,
+        },
+        _ => Error::ParsingError(format!("unexpected response status {status}")),
+    }
+}
+
+#[derive(Deserialize)]
+struct Transcription {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<Embedding>,
+}
+
+#[derive(Deserialize)]
+struct Embedding {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct ImagesResponse {
+    data: Vec<ImageData>,
+}
+
+#[derive(Deserialize)]
+struct ImageData {
+    b64_json: Option<String>,
+    url: Option<String>,
+    revised_prompt: Option<String>,
+}
+
+/// A piece of a streamed reply
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Delta {
+    Text(String),
+    /// part of the call of tool number `index`, the arguments arrive a few characters at a time
+    ToolCall {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+}
+
+/// `msg` as sent, followed by the results of its tool calls
+fn sent_messages(msg: &Message) -> Vec<Value> {
+    let mut sent = json!({ "role": msg.role, "content": msg.content });
+    // messages with images are sent as a list of text and image parts
+    if !msg.images.is_empty() {
+        let mut parts = vec![json!({ "type": "text", "text": msg.content })];
+        for url in msg.images.iter() {
+            parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+        }
+        sent["content"] = parts.into();
+    }
+    if msg.tool_calls.is_empty() {
+        return vec![sent];
+    }
+    let calls: Vec<Value> = msg
+        .tool_calls
+        .iter()
+        .map(|call| {
+            json!({
+                "id": call.id,
+                "type": "function",
+                "function": { "name": call.name, "arguments": call.arguments },
+            })
+        })
+        .collect();
+    sent["tool_calls"] = calls.into();
+    let mut messages = vec![sent];
+    for call in msg.tool_calls.iter() {
+        messages.push(json!({
+            "role": "tool",
+            "tool_call_id": call.id,
+            "content": call.result.as_deref().unwrap_or("the call was interrupted"),
+        }));
+    }
+    messages
+}
+
+/// One `data:` event of a streamed completion
+#[derive(Deserialize)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    delta: StreamDelta,
+}
+
+#[derive(Deserialize)]
+struct StreamDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<StreamToolCall>,
+}
+
+impl StreamDelta {
+    fn into_deltas(self) -> Vec<chatgpt::Result<Delta>> {
+        let mut deltas: Vec<_> = self
+            .content
+            .map(|c| Ok(Delta::Text(c)))
+            .into_iter()
+            .collect();
+        for call in self.tool_calls {
+            deltas.push(Ok(Delta::ToolCall {
+                index: call.index,
+                id: call.id,
+                name: call.function.name,
+                arguments: call.function.arguments.unwrap_or_default(),
+            }));
+        }
+        deltas
+    }
+}
+
+#[derive(Deserialize)]
+struct StreamToolCall {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    #[serde(default)]
+    function: StreamFunction,
+}
+
+#[derive(Default, Deserialize)]
+struct StreamFunction {
+    name: Option<String>,
+    arguments: Option<String>,
+}
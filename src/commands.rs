@@ -0,0 +1,150 @@
+use crate::export::Format;
+
+/// highest temperature the chat completions endpoints accept
+pub const MAX_TEMPERATURE: f32 = 2.0;
+
+/// A command typed into the prompt instead of a message, e.g. `/role Translator`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// start a new conversation
+    New,
+    /// start a new conversation with the role of this name
+    Role(String),
+    /// answer the next prompts of the conversation with this model
+    Model(String),
+    /// sampling temperature for the next prompts of the conversation
+    Temperature(f32),
+    Export(Format),
+    /// drop the messages of the conversation, keeping the role prompt
+    Clear,
+    /// ask again for the reply to the last prompt
+    Retry,
+}
+
+impl Command {
+    /// every command name, in the order they are suggested
+    pub const NAMES: [&'static str; 7] =
+        ["new", "role", "model", "temp", "export", "clear", "retry"];
+
+    /// Reads `input` as a command, `None` when it is a message to send.
+    ///
+    /// `//` at the start sends a message starting with `/`, see [`unescape`].
+    pub fn parse(input: &str) -> Option<Result<Self, String>> {
+        let input = input.trim();
+        let rest = input.strip_prefix('/')?;
+        if rest.starts_with('/') {
+            return None;
+        }
+        let (name, argument) = match rest.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (rest, ""),
+        };
+        let needs = |what: &str| Err(format!("/{name} needs {what}"));
+        Some(match (name, argument) {
+            ("new", "") => Ok(Command::New),
+            ("clear", "") => Ok(Command::Clear),
+            ("retry", "") => Ok(Command::Retry),
+            ("new" | "clear" | "retry", _) => Err(format!("/{name} takes no argument")),
+            ("role", "") => needs("the name of a role"),
+            ("role", role) => Ok(Command::Role(role.to_owned())),
+            ("model", "") => needs("the name of a model"),
+            ("model", model) => Ok(Command::Model(model.to_owned())),
+            ("temp", "") => needs(&format!("a temperature from 0 to {MAX_TEMPERATURE}")),
+            ("temp", temperature) => match temperature.parse::<f32>() {
+                Ok(t) if (0.0..=MAX_TEMPERATURE).contains(&t) => Ok(Command::Temperature(t)),
+                _ => Err(format!(
+                    "`{temperature}` is not a temperature from 0 to {MAX_TEMPERATURE}"
+                )),
+            },
+            ("export", "") => needs("md, html, json or pdf"),
+            ("export", format) => Format::from_name(format)
+                .map(Command::Export)
+                .ok_or_else(|| format!("unknown format `{format}`, use md, html, json or pdf")),
+            _ => Err(format!("unknown command /{name}")),
+        })
+    }
+}
+
+/// whether the command called `name` is followed by an argument
+pub fn takes_argument(name: &str) -> bool {
+    matches!(name, "role" | "model" | "temp" | "export")
+}
+
+/// `input` as the message to send, with the `/` doubled to escape a command taken away
+pub fn unescape(input: &str) -> &str {
+    match input.trim_start().strip_prefix("//") {
+        Some(rest) => &input[input.len() - rest.len() - 1..],
+        None => input,
+    }
+}
+
+/// Whether `input` has something to send once unescaped, a single `y` or `好` is a prompt too.
+pub fn has_text(input: &str) -> bool {
+    !unescape(input).trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Option<Result<Command, String>> {
+        Command::parse(input)
+    }
+
+    #[test]
+    fn one_character_prompts_are_sent() {
+        assert!(has_text("y"));
+        assert!(has_text("好"));
+        assert!(has_text(" 👍\n"));
+        assert!(has_text("//"));
+        assert!(!has_text(""));
+        assert!(!has_text(" \n\t"));
+    }
+
+    #[test]
+    fn commands_are_read_with_their_arguments() {
+        assert_eq!(parse("/new"), Some(Ok(Command::New)));
+        assert_eq!(parse("  /clear \n"), Some(Ok(Command::Clear)));
+        assert_eq!(parse("/retry"), Some(Ok(Command::Retry)));
+        assert_eq!(
+            parse("/role  Code Reviewer "),
+            Some(Ok(Command::Role("Code Reviewer".to_owned())))
+        );
+        assert_eq!(
+            parse("/model gpt-4o"),
+            Some(Ok(Command::Model("gpt-4o".to_owned())))
+        );
+        assert_eq!(parse("/temp 0.5"), Some(Ok(Command::Temperature(0.5))));
+        assert_eq!(parse("/temp 2"), Some(Ok(Command::Temperature(2.0))));
+        assert_eq!(parse("/export PDF"), Some(Ok(Command::Export(Format::Pdf))));
+        assert_eq!(
+            parse("/export md"),
+            Some(Ok(Command::Export(Format::Markdown)))
+        );
+    }
+
+    #[test]
+    fn messages_are_not_commands() {
+        assert_eq!(parse("hello /new"), None);
+        assert_eq!(parse("//new"), None);
+        assert_eq!(unescape("//new"), "/new");
+        assert_eq!(unescape("  //new"), "/new");
+        assert_eq!(unescape("a // b"), "a // b");
+    }
+
+    #[test]
+    fn wrong_commands_say_what_is_wrong() {
+        let error = |input| parse(input).unwrap().unwrap_err();
+        assert_eq!(error("/"), "unknown command /");
+        assert_eq!(error("/quit"), "unknown command /quit");
+        assert_eq!(error("/new now"), "/new takes no argument");
+        assert_eq!(error("/role"), "/role needs the name of a role");
+        assert_eq!(error("/temp"), "/temp needs a temperature from 0 to 2");
+        assert_eq!(error("/temp 2.5"), "`2.5` is not a temperature from 0 to 2");
+        assert_eq!(error("/temp hot"), "`hot` is not a temperature from 0 to 2");
+        assert_eq!(
+            error("/export doc"),
+            "unknown format `doc`, use md, html, json or pdf"
+        );
+    }
+}
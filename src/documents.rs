@@ -0,0 +1,510 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::attachment::Attachment;
+use crate::client::Client;
+use crate::settings::{self, DocumentOptions};
+use crate::store::Citation;
+
+/// passages end at the first line break after this many characters
+pub const PASSAGE_CHARS: usize = 1_500;
+/// lines repeated at the start of the next passage, so text around a cut is found as well
+const OVERLAP_LINES: usize = 2;
+/// longer passages, e.g. of minified files, are cut to stay below the embedding models' limit
+const MAX_PASSAGE_CHARS: usize = 4 * PASSAGE_CHARS;
+/// passages embedded by one request
+const BATCH: usize = 64;
+/// build output and dependencies, hidden files and directories are skipped as well
+const SKIPPED_DIRS: [&str; 3] = ["target", "node_modules", "__pycache__"];
+
+/// A piece of a document with its embedding
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Passage {
+    /// relative to the indexed folder
+    pub path: PathBuf,
+    /// first line, counting from 1
+    pub start_line: usize,
+    /// last line, included
+    pub end_line: usize,
+    pub text: String,
+    embedding: Vec<f32>,
+}
+
+impl Passage {
+    pub fn citation(&self, folder: &Path) -> Citation {
+        Citation {
+            path: folder.join(&self.path),
+            start_line: self.start_line,
+            end_line: self.end_line,
+        }
+    }
+}
+
+/// The passages of a file as of its last change
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexedFile {
+    /// unix milliseconds, a change of this or the size re-embeds the file
+    modified: u64,
+    size: u64,
+    /// empty for files that are not text
+    passages: Vec<Passage>,
+}
+
+/// What [`Library::reindex`] did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Reindexed {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    /// text files in the index
+    pub files: usize,
+    pub passages: usize,
+}
+
+/// The embedded passages of the text files in a folder
+#[derive(Debug, Serialize, Deserialize)]
+struct DocumentIndex {
+    folder: PathBuf,
+    /// vectors of different models can't be compared, a new model starts over
+    model: String,
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+impl DocumentIndex {
+    /// Embeds the files added or changed since the last update and forgets removed ones.
+    ///
+    /// The work done before a failed request is kept.
+    async fn update(&mut self, client: &Client) -> chatgpt::Result<Reindexed> {
+        let mut found = Vec::new();
+        walk(&self.folder, &self.folder, &mut found)?;
+        let mut reindexed = Reindexed::default();
+        let before = self.files.len();
+        self.files
+            .retain(|path, _| found.iter().any(|(found, _, _)| found == path));
+        reindexed.removed = before - self.files.len();
+        for (path, modified, size) in found {
+            let known = self.files.get(&path);
+            if known.is_some_and(|f| f.modified == modified && f.size == size) {
+                continue;
+            }
+            if known.is_some() {
+                reindexed.changed += 1;
+            } else {
+                reindexed.added += 1;
+            }
+            let passages = match Attachment::read(&self.folder.join(&path)) {
+                Ok(file) => embed(client, &path, &file.content).await?,
+                // binary or too big, remembered so it is not read again until it changes
+                Err(_) => Vec::new(),
+            };
+            self.files.insert(
+                path,
+                IndexedFile {
+                    modified,
+                    size,
+                    passages,
+                },
+            );
+        }
+        let texts = self.files.values().filter(|f| !f.passages.is_empty());
+        reindexed.files = texts.clone().count();
+        reindexed.passages = texts.map(|f| f.passages.len()).sum();
+        Ok(reindexed)
+    }
+
+    /// the `k` passages whose embeddings point the most in the direction of `query`
+    fn search(&self, query: &[f32], k: usize) -> Vec<Passage> {
+        let mut scored: Vec<(f32, &Passage)> = self
+            .files
+            .values()
+            .flat_map(|f| f.passages.iter())
+            .map(|p| (cosine(query, &p.embedding), p))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().take(k).map(|(_, p)| p.clone()).collect()
+    }
+}
+
+/// The indexes of the document folders, kept as files in a directory next to the settings file.
+///
+/// Clones share the indexes loaded so far.
+#[derive(Clone, Debug)]
+pub struct Library {
+    dir: PathBuf,
+    loaded: Arc<Mutex<HashMap<PathBuf, DocumentIndex>>>,
+}
+
+impl Library {
+    pub fn open(app_name: &str) -> io::Result<Self> {
+        let dir = settings::config_dir(app_name)?.join("indexes");
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            loaded: Default::default(),
+        })
+    }
+
+    /// Brings the index of `folder` up to date with the files changed since the last time.
+    pub async fn reindex(&self, client: &Client, folder: &Path) -> chatgpt::Result<Reindexed> {
+        let folder = canonical(folder)?;
+        let path = self.index_path(&folder, client.embedding_model());
+        let mut loaded = self.loaded.lock().await;
+        let index = loaded.entry(path.clone()).or_insert_with(|| {
+            fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_else(|| DocumentIndex {
+                    folder: folder.clone(),
+                    model: client.embedding_model().to_owned(),
+                    files: BTreeMap::new(),
+                })
+        });
+        let result = index.update(client).await;
+        fs::write(&path, serde_json::to_string(index)?)?;
+        result
+    }
+
+    /// Updates the index of the folder in `options` and finds the passages closest to `query`.
+    pub async fn retrieve(
+        &self,
+        client: &Client,
+        options: &DocumentOptions,
+        query: &str,
+    ) -> chatgpt::Result<Vec<Passage>> {
+        if options.top_k == 0 || query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        self.reindex(client, &options.folder).await?;
+        let query = client.embed(&[query.to_owned()]).await?.remove(0);
+        let folder = canonical(&options.folder)?;
+        let path = self.index_path(&folder, client.embedding_model());
+        let loaded = self.loaded.lock().await;
+        Ok(loaded
+            .get(&path)
+            .map(|index| index.search(&query, options.top_k))
+            .unwrap_or_default())
+    }
+
+    /// one file per folder and embedding model
+    fn index_path(&self, folder: &Path, model: &str) -> PathBuf {
+        let digest = Sha256::new()
+            .chain_update(folder.as_os_str().as_encoded_bytes())
+            .chain_update([0])
+            .chain_update(model)
+            .finalize();
+        let name: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+        self.dir.join(format!("{name}.json"))
+    }
+}
+
+/// The system message handing `passages` to the model, numbered for citing.
+pub fn context(passages: &[Passage]) -> String {
+    let mut context = "Passages of the user's documents that may help with the next message. \
+                       Use them when relevant and cite them by number, e.g. [1]."
+        .to_owned();
+    for (i, passage) in passages.iter().enumerate() {
+        context.push_str(&format!(
+            "\n\n[{}] {}:{}-{}\n{}",
+            i + 1,
+            passage.path.display(),
+            passage.start_line,
+            passage.end_line,
+            passage.text
+        ));
+    }
+    context
+}
+
+/// Cuts `text` into passages of whole lines, returning the first and last line of each.
+pub fn split(text: &str) -> Vec<(usize, usize, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut passages = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        let mut chars = 0;
+        while end < lines.len() && (end == start || chars < PASSAGE_CHARS) {
+            chars += lines[end].chars().count() + 1;
+            end += 1;
+        }
+        let mut passage = lines[start..end].join("\n");
+        let mut end_line = end;
+        if let Some((cut, _)) = passage.char_indices().nth(MAX_PASSAGE_CHARS) {
+            passage.truncate(cut);
+            // cite only the lines that are left
+            end_line = start + passage.lines().count();
+        }
+        if !passage.trim().is_empty() {
+            passages.push((start + 1, end_line, passage));
+        }
+        if end == lines.len() {
+            break;
+        }
+        start = end.saturating_sub(OVERLAP_LINES).max(start + 1);
+    }
+    passages
+}
+
+/// the passages of the file at `path` with their embeddings
+async fn embed(client: &Client, path: &Path, content: &str) -> chatgpt::Result<Vec<Passage>> {
+    let split = split(content);
+    let mut passages = Vec::with_capacity(split.len());
+    for batch in split.chunks(BATCH) {
+        // the path helps finding files by their name
+        let inputs: Vec<String> = batch
+            .iter()
+            .map(|(_, _, text)| format!("{}\n{text}", path.display()))
+            .collect();
+        let embeddings = client.embed(&inputs).await?;
+        for ((start_line, end_line, text), embedding) in batch.iter().zip(embeddings) {
+            passages.push(Passage {
+                path: path.to_owned(),
+                start_line: *start_line,
+                end_line: *end_line,
+                text: text.clone(),
+                embedding,
+            });
+        }
+    }
+    Ok(passages)
+}
+
+/// the files below `dir` by path relative to `folder`, with their modification time and size
+fn walk(folder: &Path, dir: &Path, found: &mut Vec<(PathBuf, u64, u64)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)?.filter_map(Result::ok) {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // links are not followed, they could lead in circles
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if !SKIPPED_DIRS.contains(&name.as_ref()) {
+                // an unreadable subdirectory only misses its files
+                let _ = walk(folder, &path, found);
+            }
+        } else if file_type.is_file() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default();
+            if let Ok(relative) = path.strip_prefix(folder) {
+                found.push((relative.to_owned(), modified, metadata.len()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `folder` without `..` and links, naming it when it can't be found
+fn canonical(folder: &Path) -> io::Result<PathBuf> {
+    folder
+        .canonicalize()
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", folder.display())))
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::settings::Profile;
+
+    #[test]
+    fn index_paths_do_not_change_between_builds() {
+        let library = Library {
+            dir: PathBuf::from("indexes"),
+            loaded: Arc::default(),
+        };
+        let path = library.index_path(Path::new("/home/me/notes"), "text-embedding-3-small");
+        assert_eq!(path, PathBuf::from("indexes/e2c77fedcd8b3d79.json"));
+        assert_ne!(
+            path,
+            library.index_path(Path::new("/home/me/notes"), "nomic-embed-text")
+        );
+    }
+    /// `count` lines of 399 times the letter of their number, so four make a passage
+    fn lines(count: u8) -> String {
+        (0..count)
+            .map(|i| char::from(b'a' + i).to_string().repeat(399))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn line_numbers(text: &str) -> Vec<(usize, usize)> {
+        split(text)
+            .into_iter()
+            .map(|(start, end, _)| (start, end))
+            .collect()
+    }
+
+    #[test]
+    fn passages_repeat_the_last_lines_of_the_one_before() {
+        assert_eq!(line_numbers(&lines(10)), [(1, 4), (3, 6), (5, 8), (7, 10)]);
+        let passages = split(&lines(10));
+        assert!(passages[1].2.starts_with(&"c".repeat(399)));
+        assert!(passages[3].2.ends_with(&"j".repeat(399)));
+        assert_eq!(line_numbers("short\ntext"), [(1, 2)]);
+        assert!(split("").is_empty());
+    }
+
+    #[test]
+    fn long_passages_are_cut_and_cite_the_lines_left() {
+        let text = format!(
+            "{}\n{}\nafter",
+            "a".repeat(10),
+            "b".repeat(2 * MAX_PASSAGE_CHARS)
+        );
+        let passages = split(&text);
+        let (start, end, passage) = &passages[0];
+        assert_eq!((*start, *end), (1, 2));
+        assert_eq!(passage.chars().count(), MAX_PASSAGE_CHARS);
+        assert_eq!(passages.last().unwrap().2, "after");
+
+        let text = format!("{}\n\n\n", "a".repeat(2 * MAX_PASSAGE_CHARS));
+        assert_eq!(line_numbers(&text), [(1, 1)]);
+    }
+
+    #[test]
+    fn blank_passages_are_skipped() {
+        let text = format!("{}x", "\n".repeat(2 * PASSAGE_CHARS));
+        let passages = split(&text);
+        assert!(passages.iter().all(|(_, _, p)| !p.trim().is_empty()));
+        assert_eq!(passages.last().unwrap().1, 2 * PASSAGE_CHARS + 1);
+    }
+
+    /// Serves an embeddings endpoint, counting the texts it was asked to embed.
+    async fn fake_embeddings(embedded: Arc<AtomicUsize>) -> String {
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service_fn(
+            move |_| {
+                let embedded = embedded.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let embedded = embedded.clone();
+                        async move {
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            let body: Value = serde_json::from_slice(&body).unwrap();
+                            let inputs = body["input"].as_array().unwrap();
+                            embedded.fetch_add(inputs.len(), Ordering::SeqCst);
+                            let data: Vec<Value> = inputs
+                                .iter()
+                                .enumerate()
+                                .map(|(index, input)| {
+                                    let len = input.as_str().unwrap().len() as f32;
+                                    json!({ "index": index, "embedding": [len, 1.0] })
+                                })
+                                .collect();
+                            let body = json!({ "data": data }).to_string();
+                            Ok::<_, Infallible>(Response::new(Body::from(body)))
+                        }
+                    }))
+                }
+            },
+        ));
+        let url = format!("http://{}/v1/embeddings", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test]
+    async fn reindexing_only_embeds_what_changed() {
+        let embedded = Arc::new(AtomicUsize::new(0));
+        let client = Client::new(&Profile {
+            embedding_url: fake_embeddings(embedded.clone()).await,
+            ..Profile::default()
+        })
+        .unwrap();
+        let indexes = tempfile::tempdir().unwrap();
+        let library = Library {
+            dir: indexes.path().to_owned(),
+            loaded: Arc::default(),
+        };
+        let folder = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &[u8]| {
+            let path = folder.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("a.txt", b"first");
+        write("notes/b.md", b"second");
+        write("image.bin", b"\0\x01\x02");
+        write(".hidden", b"skipped");
+        write("target/build.txt", b"skipped");
+
+        let reindexed = library.reindex(&client, folder.path()).await.unwrap();
+        let expected = Reindexed {
+            added: 3,
+            files: 2,
+            passages: 2,
+            ..Reindexed::default()
+        };
+        assert_eq!(reindexed, expected);
+        assert_eq!(embedded.load(Ordering::SeqCst), 2);
+
+        let reindexed = library.reindex(&client, folder.path()).await.unwrap();
+        let expected = Reindexed {
+            files: 2,
+            passages: 2,
+            ..Reindexed::default()
+        };
+        assert_eq!(reindexed, expected);
+        assert_eq!(embedded.load(Ordering::SeqCst), 2);
+
+        write("a.txt", b"first, changed");
+        fs::remove_file(folder.path().join("notes/b.md")).unwrap();
+        write("c.txt", b"third");
+        let reindexed = library.reindex(&client, folder.path()).await.unwrap();
+        let expected = Reindexed {
+            added: 1,
+            changed: 1,
+            removed: 1,
+            files: 2,
+            passages: 2,
+        };
+        assert_eq!(reindexed, expected);
+        assert_eq!(embedded.load(Ordering::SeqCst), 4);
+
+        // a new library reads the index file instead of embedding again
+        let library = Library {
+            dir: indexes.path().to_owned(),
+            loaded: Arc::default(),
+        };
+        let reindexed = library.reindex(&client, folder.path()).await.unwrap();
+        assert_eq!((reindexed.added, reindexed.files), (0, 2));
+        assert_eq!(embedded.load(Ordering::SeqCst), 4);
+    }
+}
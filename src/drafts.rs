@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::settings;
+
+/// Prompts written but not sent yet, by conversation, kept in a file next to the settings file
+#[derive(Clone, Debug, Default)]
+pub struct Drafts {
+    /// `None` keeps the drafts in memory only
+    path: Option<PathBuf>,
+    drafts: BTreeMap<String, String>,
+}
+
+impl Drafts {
+    /// the saved drafts, none when nothing was left unsent
+    pub fn open(app_name: &str) -> io::Result<Self> {
+        let path = settings::config_dir(app_name)?.join("drafts.json");
+        let drafts = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: Some(path),
+            drafts,
+        })
+    }
+
+    /// the draft of the conversation `key`, empty when there is none
+    pub fn get(&self, key: &str) -> &str {
+        self.drafts.get(key).map_or("", String::as_str)
+    }
+
+    /// Keeps `text` as the draft of the conversation `key`, blank text drops the draft.
+    ///
+    /// The file is only written when the draft changed.
+    pub fn set(&mut self, key: &str, text: &str) -> io::Result<()> {
+        let changed = if text.trim().is_empty() {
+            self.drafts.remove(key).is_some()
+        } else if self.get(key) != text {
+            self.drafts.insert(key.to_owned(), text.to_owned());
+            true
+        } else {
+            false
+        };
+        match &self.path {
+            Some(path) if changed => fs::write(path, serde_json::to_string_pretty(&self.drafts)?),
+            _ => Ok(()),
+        }
+    }
+
+    /// The key of the draft for a conversation not started yet.
+    ///
+    /// Saved conversations use their id.
+    pub fn new_conversation_key(profile: &str, role: &str) -> String {
+        format!("new/{profile}/{role}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drafts_are_kept_by_conversation() {
+        let new = Drafts::new_conversation_key("default", "Assistant");
+        let mut drafts = Drafts::default();
+        drafts.set("1", "first").unwrap();
+        drafts.set(&new, "second").unwrap();
+        assert_eq!(drafts.get("1"), "first");
+        assert_eq!(drafts.get(&new), "second");
+        assert_eq!(drafts.get("2"), "");
+        assert_ne!(new, Drafts::new_conversation_key("default", "Translator"));
+    }
+
+    #[test]
+    fn blank_drafts_are_removed() {
+        let mut drafts = Drafts::default();
+        drafts.set("1", "text").unwrap();
+        drafts.set("1", " \n").unwrap();
+        assert_eq!(drafts.get("1"), "");
+        assert!(drafts.drafts.is_empty());
+    }
+
+    #[test]
+    fn drafts_are_written_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("drafts.json");
+        let mut drafts = Drafts {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        let saved = || -> BTreeMap<String, String> {
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+        };
+        drafts.set("1", "text").unwrap();
+        assert_eq!(saved()["1"], "text");
+        drafts.set("1", "").unwrap();
+        assert!(saved().is_empty());
+    }
+}
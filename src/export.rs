@@ -0,0 +1,481 @@
+use std::io;
+
+use chrono::{Local, TimeZone};
+use printpdf::{BuiltinFont, IndirectFontRef, Mm, PdfDocument};
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag};
+use syntect::highlighting::ThemeSet;
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+
+use crate::store::{Message, Role, StoredConversation};
+
+/// The file types a conversation can be exported to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    /// a single page with inline styles and highlighted code
+    Html,
+    /// the stored conversation with all its metadata
+    Json,
+    Pdf,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Markdown, Format::Html, Format::Json, Format::Pdf];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Markdown => "Markdown",
+            Format::Html => "HTML",
+            Format::Json => "JSON",
+            Format::Pdf => "PDF",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Html => "html",
+            Format::Json => "json",
+            Format::Pdf => "pdf",
+        }
+    }
+
+    /// accepts the extension or the name in any case, e.g. `md` or `Markdown`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|f| f.extension() == name || f.name().to_lowercase() == name)
+    }
+}
+
+/// Renders the messages at the indexes in `selection`, or the whole conversation when it is empty.
+///
+/// Only JSON keeps the role prompt of a whole conversation, the others show the messages worth
+/// reading unless the prompt is selected.
+///
+/// `font` is a TrueType font for the PDF output, without it the built-in Helvetica is used
+/// which only covers ASCII.
+pub fn export(
+    conversation: &StoredConversation,
+    selection: &[usize],
+    format: Format,
+    font: Option<&[u8]>,
+) -> io::Result<Vec<u8>> {
+    let mut selected = conversation.clone();
+    if !selection.is_empty() {
+        selected.messages = selection
+            .iter()
+            .filter_map(|&i| conversation.messages.get(i).cloned())
+            .collect();
+    } else if format != Format::Json {
+        drop_role_prompt(&mut selected.messages);
+    }
+    match format {
+        Format::Markdown => Ok(markdown(&selected).into_bytes()),
+        Format::Html => Ok(html(&selected).into_bytes()),
+        Format::Json => Ok(serde_json::to_vec_pretty(&selected)?),
+        Format::Pdf => pdf(&selected, font),
+    }
+}
+
+/// a file name for the export made from the title
+pub fn file_name(conversation: &StoredConversation, format: Format) -> String {
+    let title: String = conversation
+        .title
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let title = title.trim_matches('_');
+    let stem = if title.is_empty() {
+        conversation.id.as_str()
+    } else {
+        title
+    };
+    format!("{stem}.{}", format.extension())
+}
+
+/// leaves the messages worth reading, without the role setting message
+fn drop_role_prompt(messages: &mut Vec<Message>) {
+    if messages.len() > 1 && messages[0].role == Role::System {
+        messages.remove(0);
+    }
+}
+
+fn sender(conversation: &StoredConversation, msg: &Message) -> String {
+    match msg.role {
+        Role::User => "User".to_owned(),
+        Role::Assistant => conversation.role.clone(),
+        Role::System => "System".to_owned(),
+    }
+}
+
+pub fn format_time(timestamp: u64) -> String {
+    if timestamp == 0 {
+        return String::new();
+    }
+    match Local.timestamp_opt(timestamp as i64, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => String::new(),
+    }
+}
+
+fn heading(conversation: &StoredConversation, msg: &Message) -> String {
+    let time = format_time(msg.timestamp);
+    if time.is_empty() {
+        sender(conversation, msg)
+    } else {
+        format!("{} · {time}", sender(conversation, msg))
+    }
+}
+
+fn markdown(conversation: &StoredConversation) -> String {
+    let mut out = format!(
+        "# {}\n\n- Profile: {}\n- Role: {}\n- Created: {}\n",
+        conversation.title,
+        conversation.profile,
+        conversation.role,
+        format_time(conversation.created)
+    );
+    for msg in conversation.messages.iter() {
+        out.push_str(&format!(
+            "\n## {}\n\n{}\n",
+            heading(conversation, msg),
+            msg.content.trim_end()
+        ));
+        for url in msg.images.iter() {
+            out.push_str(&format!("\n![image]({url})\n"));
+        }
+        for call in msg.tool_calls.iter() {
+            out.push_str(&format!(
+                "\n<details><summary>🛠 {}</summary>\n\n```json\n{}\n```\n\n```\n{}\n```\n\n</details>\n",
+                call.name,
+                call.arguments,
+                call.result.as_deref().unwrap_or_default()
+            ));
+        }
+        if !msg.citations.is_empty() {
+            out.push_str("\nSources:\n\n");
+        }
+        for (i, citation) in msg.citations.iter().enumerate() {
+            out.push_str(&format!("{}. `{}`\n", i + 1, citation.location()));
+        }
+    }
+    out
+}
+
+const HTML_STYLE: &str = "
+body { max-width: 860px; margin: 2em auto; padding: 0 1em; font-family: sans-serif; line-height: 1.5; color: #222; }
+header p { color: #666; margin: 0; }
+section { border-top: 1px solid #ddd; padding: 0.5em 0; }
+section h2 { font-size: 1em; margin: 0.5em 0; }
+section.user h2 { color: #2a7a2a; }
+section.assistant h2 { color: #1a5a9a; }
+section.system h2 { color: #aa3333; }
+pre { padding: 0.8em; overflow-x: auto; border-radius: 4px; }
+code { font-family: monospace; }
+ol.sources { color: #666; font-size: 0.9em; }
+";
+
+fn html(conversation: &StoredConversation) -> String {
+    let syntaxes = SyntaxSet::load_defaults_newlines();
+    let themes = ThemeSet::load_defaults();
+    let theme = &themes.themes["InspiredGitHub"];
+
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n<header>\n<h1>{title}</h1>\n<p>{} · {} · {}</p>\n</header>\n",
+        escape(&conversation.profile),
+        escape(&conversation.role),
+        format_time(conversation.created),
+        title = escape(&conversation.title),
+    );
+    for msg in conversation.messages.iter() {
+        let class = match msg.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+        };
+        out.push_str(&format!(
+            "<section class=\"{class}\">\n<h2>{}</h2>\n",
+            escape(&heading(conversation, msg))
+        ));
+
+        // replace fenced code blocks by syntect's highlighted html
+        let mut events = Vec::new();
+        let mut code: Option<(String, String)> = None;
+        for event in Parser::new_ext(&msg.content, Options::all()) {
+            match (event, code.as_mut()) {
+                (Event::Start(Tag::CodeBlock(kind)), None) => {
+                    let lang = match kind {
+                        CodeBlockKind::Fenced(info) => info
+                            .split_whitespace()
+                            .next()
+                            .unwrap_or_default()
+                            .to_owned(),
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    code = Some((lang, String::new()));
+                }
+                (Event::Text(text), Some((_, body))) => body.push_str(&text),
+                (Event::End(Tag::CodeBlock(_)), Some(_)) => {
+                    let (lang, body) = code.take().unwrap();
+                    let syntax = syntaxes
+                        .find_syntax_by_token(&lang)
+                        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+                    let highlighted = highlighted_html_for_string(&body, &syntaxes, syntax, theme)
+                        .unwrap_or_else(|_| format!("<pre><code>{}</code></pre>", escape(&body)));
+                    events.push(Event::Html(highlighted.into()));
+                }
+                // raw HTML of a message would run in the exported page, so it is shown as text
+                (Event::Html(raw), None) => events.push(Event::Text(raw)),
+                // and so would `javascript:` links
+                (Event::Start(Tag::Link(kind, url, title)), None) => {
+                    let url = if safe_url(&url, false) {
+                        url
+                    } else {
+                        "#".into()
+                    };
+                    events.push(Event::Start(Tag::Link(kind, url, title)));
+                }
+                (Event::Start(Tag::Image(kind, url, title)), None) => {
+                    let url = if safe_url(&url, true) { url } else { "".into() };
+                    events.push(Event::Start(Tag::Image(kind, url, title)));
+                }
+                (event, _) => events.push(event),
+            }
+        }
+        html::push_html(&mut out, events.into_iter());
+        for url in msg.images.iter() {
+            out.push_str(&format!(
+                "<p><img src=\"{}\" style=\"max-width: 100%\"></p>\n",
+                escape(url)
+            ));
+        }
+        for call in msg.tool_calls.iter() {
+            out.push_str(&format!(
+                "<details><summary>🛠 {}</summary>\n<pre><code>{}</code></pre>\n<pre><code>{}</code></pre>\n</details>\n",
+                escape(&call.name),
+                escape(&call.arguments),
+                escape(call.result.as_deref().unwrap_or_default())
+            ));
+        }
+        if !msg.citations.is_empty() {
+            out.push_str("<ol class=\"sources\">\n");
+            for citation in msg.citations.iter() {
+                out.push_str(&format!(
+                    "<li><code>{}</code></li>\n",
+                    escape(&citation.location())
+                ));
+            }
+            out.push_str("</ol>\n");
+        }
+        out.push_str("</section>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    pulldown_cmark::escape::escape_html(&mut out, text).unwrap();
+    out
+}
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const FONT_SIZE: f32 = 10.0;
+const LINE_HEIGHT: f32 = 5.0;
+/// line width in half-em units, wide characters count as two
+const LINE_UNITS: usize = 92;
+
+fn pdf(conversation: &StoredConversation, font: Option<&[u8]>) -> io::Result<Vec<u8>> {
+    let (doc, page, layer) = PdfDocument::new(
+        conversation.title.clone(),
+        Mm(PAGE_WIDTH),
+        Mm(PAGE_HEIGHT),
+        "Layer 1",
+    );
+    let (text_font, heading_font, latin_only): (IndirectFontRef, IndirectFontRef, bool) = match font
+    {
+        Some(font) => {
+            let font = doc.add_external_font(font).map_err(io::Error::other)?;
+            (font.clone(), font, false)
+        }
+        None => (
+            doc.add_builtin_font(BuiltinFont::Helvetica)
+                .map_err(io::Error::other)?,
+            doc.add_builtin_font(BuiltinFont::HelveticaBold)
+                .map_err(io::Error::other)?,
+            true,
+        ),
+    };
+
+    let mut lines: Vec<(String, bool)> = vec![(conversation.title.clone(), true)];
+    lines.push((
+        format!(
+            "{} · {} · {}",
+            conversation.profile,
+            conversation.role,
+            format_time(conversation.created)
+        ),
+        false,
+    ));
+    for msg in conversation.messages.iter() {
+        lines.push((String::new(), false));
+        lines.push((heading(conversation, msg), true));
+        for line in msg.content.lines() {
+            for wrapped in wrap(line, LINE_UNITS) {
+                lines.push((wrapped, false));
+            }
+        }
+        for _ in msg.images.iter() {
+            lines.push(("[image]".to_owned(), false));
+        }
+        for call in msg.tool_calls.iter() {
+            let step = format!("[tool] {}({})", call.name, call.arguments);
+            for wrapped in wrap(&step, LINE_UNITS) {
+                lines.push((wrapped, false));
+            }
+        }
+        for (i, citation) in msg.citations.iter().enumerate() {
+            let source = format!("[{}] {}", i + 1, citation.location());
+            for wrapped in wrap(&source, LINE_UNITS) {
+                lines.push((wrapped, false));
+            }
+        }
+    }
+
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = PAGE_HEIGHT - MARGIN;
+    for (line, is_heading) in lines {
+        if y < MARGIN {
+            let (page, new_layer) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+            layer = doc.get_page(page).get_layer(new_layer);
+            y = PAGE_HEIGHT - MARGIN;
+        }
+        let line = if latin_only {
+            line.chars()
+                .map(|c| if c.is_ascii() { c } else { '?' })
+                .collect()
+        } else {
+            line
+        };
+        let font = if is_heading {
+            &heading_font
+        } else {
+            &text_font
+        };
+        layer.use_text(line, FONT_SIZE, Mm(MARGIN), Mm(y), font);
+        y -= LINE_HEIGHT;
+    }
+    doc.save_to_bytes().map_err(io::Error::other)
+}
+
+/// breaks `line` into pieces of at most `units`, CJK and other wide characters count as two
+fn wrap(line: &str, units: usize) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        let w = if (c as u32) < 0x1100 { 1 } else { 2 };
+        if width + w > units {
+            out.push(std::mem::take(&mut current));
+            width = 0;
+        }
+        current.push(c);
+        width += w;
+    }
+    out.push(current);
+    out
+}
+
+/// Whether `url` is relative, http, https or mailto, or with `image` a `data:image/` URL.
+fn safe_url(url: &str, image: bool) -> bool {
+    let url = url.trim_start();
+    let Some((scheme, rest)) = url.split_once(':') else {
+        return true;
+    };
+    // a colon in the path or query of a relative URL
+    if scheme.contains(['/', '?', '#']) {
+        return true;
+    }
+    match scheme.to_ascii_lowercase().as_str() {
+        "http" | "https" | "mailto" => true,
+        "data" => image && rest.trim_start().to_ascii_lowercase().starts_with("image/"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(content: &str) -> StoredConversation {
+        let role = crate::settings::Role {
+            name: "Assistant".into(),
+            prompt: String::new(),
+            icon_base64: String::new(),
+            image: None,
+            documents: None,
+        };
+        let mut conversation = StoredConversation::new("default", &role);
+        conversation.title = "<b>title</b>".into();
+        conversation
+            .messages
+            .push(Message::new(Role::Assistant, content.to_owned()));
+        conversation
+    }
+
+    #[test]
+    fn html_escapes_raw_html_of_messages() {
+        let page = html(&conversation(
+            "before <script>alert(1)</script>\n\n<script>\nalert(2)\n</script>\n",
+        ));
+        assert!(!page.contains("<script>"), "{page}");
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(page.contains("&lt;script&gt;"));
+        assert!(page.contains("<h1>&lt;b&gt;title&lt;/b&gt;</h1>"));
+    }
+
+    #[test]
+    fn html_keeps_markdown_formatting() {
+        let page = html(&conversation("**bold** and `code`"));
+        assert!(page.contains("<strong>bold</strong>"));
+        assert!(page.contains("<code>code</code>"));
+    }
+    #[test]
+    fn html_drops_links_that_run_scripts() {
+        let page = html(&conversation(
+            "[a](javascript:alert(1)) [b](JavaScript:alert(2)) [c](data:text/html,x) \
+             ![d](javascript:alert(3)) ![e](data:image/png;base64,AAAA) \
+             [f](https://example.com/a:b) [g](mailto:me@example.com) [h](notes/a:b.md)",
+        ));
+        assert!(!page.to_lowercase().contains("javascript"), "{page}");
+        assert!(!page.contains("data:text"));
+        assert!(page.contains("<a href=\"#\">a</a>"));
+        assert!(page.contains("src=\"data:image/png;base64,AAAA\""));
+        assert!(page.contains("href=\"https://example.com/a:b\""));
+        assert!(page.contains("href=\"mailto:me@example.com\""));
+        assert!(page.contains("href=\"notes/a:b.md\""));
+    }
+
+    #[test]
+    fn selected_role_prompts_are_exported() {
+        let mut conversation = conversation("answer");
+        conversation.messages[0].content = "You are helpful".into();
+        let exported = |selection: &[usize]| {
+            let bytes = export(&conversation, selection, Format::Markdown, None).unwrap();
+            String::from_utf8(bytes).unwrap()
+        };
+        assert!(!exported(&[]).contains("You are helpful"));
+        assert!(exported(&[]).contains("answer"));
+        assert!(exported(&[0, 1]).contains("You are helpful"));
+        assert!(exported(&[0]).contains("You are helpful"));
+
+        let json = export(&conversation, &[], Format::Json, None).unwrap();
+        let json: StoredConversation = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json.messages.len(), 2);
+    }
+}
@@ -0,0 +1,240 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// subdirectories deeper than this are not searched for fonts, which also ends symlink loops
+const MAX_DEPTH: usize = 8;
+
+/// A script the window needs fonts for besides the Latin, Greek and few emoji egui ships with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Script {
+    Cyrillic,
+    /// Chinese, Japanese and Korean
+    Cjk,
+    Emoji,
+}
+
+impl Script {
+    /// in the order their fonts are tried
+    pub const ALL: [Script; 3] = [Script::Cyrillic, Script::Cjk, Script::Emoji];
+
+    /// characters a font has to draw to cover the script
+    fn samples(self) -> &'static [char] {
+        match self {
+            Script::Cyrillic => &['Ж', 'я'],
+            Script::Cjk => &['中', '文', 'あ'],
+            Script::Emoji => &['😀', '👍'],
+        }
+    }
+
+    /// file names of common fonts covering the script, the most wanted first
+    fn known_files(self) -> &'static [&'static str] {
+        match self {
+            Script::Cyrillic => &[
+                "NotoSans-Regular.ttf",
+                "DejaVuSans.ttf",
+                "LiberationSans-Regular.ttf",
+                "Ubuntu-R.ttf",
+                "segoeui.ttf",
+                "Arial.ttf",
+                "Helvetica.ttc",
+            ],
+            Script::Cjk => &[
+                "NotoSansCJK-Regular.ttc",
+                "NotoSansCJKsc-Regular.otf",
+                "NotoSansSC-Regular.otf",
+                "NotoSansSC-Regular.ttf",
+                "SourceHanSansSC-Regular.otf",
+                "SourceHanSans-Regular.ttc",
+                "PingFang.ttc",
+                "Hiragino Sans GB.ttc",
+                "STHeiti Light.ttc",
+                "msyh.ttc",
+                "msyh.ttf",
+                "simhei.ttf",
+                "wqy-microhei.ttc",
+                "wqy-zenhei.ttc",
+                "DroidSansFallbackFull.ttf",
+            ],
+            // color bitmap fonts like Noto Color Emoji and Apple Color Emoji have no outlines
+            // egui can draw
+            Script::Emoji => &[
+                "NotoEmoji-Regular.ttf",
+                "seguiemj.ttf",
+                "TwemojiMozilla.ttf",
+                "Symbola.ttf",
+                "Symbola_hint.ttf",
+            ],
+        }
+    }
+}
+
+/// A face of a font file, `index` picks one of a .ttc collection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FontFile {
+    pub path: PathBuf,
+    pub index: u32,
+}
+
+impl FontFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            index: 0,
+        }
+    }
+
+    /// The content of the file, failing when it has no font face at `index`.
+    pub fn load(&self) -> io::Result<Vec<u8>> {
+        let bytes = fs::read(&self.path)?;
+        ttf_parser::Face::parse(&bytes, self.index)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(bytes)
+    }
+}
+
+/// Whether face `index` of `bytes` draws every sample character of `script`.
+fn covers(bytes: &[u8], index: u32, script: Script) -> bool {
+    let Ok(face) = ttf_parser::Face::parse(bytes, index) else {
+        return false;
+    };
+    script.samples().iter().all(|&c| {
+        face.glyph_index(c)
+            .is_some_and(|glyph| face.glyph_bounding_box(glyph).is_some())
+    })
+}
+
+/// The directories fonts are searched in: `extra` first, then the user's and the system's.
+pub fn font_dirs(extra: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs = extra.to_vec();
+    if let Some(font_dir) =
+        directories::UserDirs::new().and_then(|d| d.font_dir().map(PathBuf::from))
+    {
+        dirs.push(font_dir);
+    }
+    if cfg!(windows) {
+        if let Some(base) = directories::BaseDirs::new() {
+            dirs.push(base.data_local_dir().join(r"Microsoft\Windows\Fonts"));
+        }
+        let windir = env::var_os("WINDIR").unwrap_or_else(|| r"C:\Windows".into());
+        dirs.push(Path::new(&windir).join("Fonts"));
+    } else if cfg!(target_os = "macos") {
+        dirs.extend(
+            [
+                "/Library/Fonts",
+                "/System/Library/Fonts",
+                "/System/Library/Fonts/Supplemental",
+            ]
+            .map(PathBuf::from),
+        );
+    } else {
+        if let Some(base) = directories::BaseDirs::new() {
+            dirs.push(base.home_dir().join(".fonts"));
+        }
+        dirs.extend(["/usr/local/share/fonts", "/usr/share/fonts"].map(PathBuf::from));
+    }
+    let mut unique = Vec::new();
+    for dir in dirs {
+        if dir.is_dir() && !unique.contains(&dir) {
+            unique.push(dir);
+        }
+    }
+    unique
+}
+
+/// The .ttf, .otf and .ttc files in `dirs` and their subdirectories, in the order of `dirs`.
+fn font_files(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    fn walk(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            if path.is_dir() {
+                if depth < MAX_DEPTH {
+                    walk(&path, depth + 1, files);
+                }
+            } else if path.extension().is_some_and(|ext| {
+                ["ttf", "otf", "ttc"]
+                    .iter()
+                    .any(|e| ext.eq_ignore_ascii_case(e))
+            }) {
+                files.push(path);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    for dir in dirs {
+        walk(dir, 0, &mut files);
+    }
+    files
+}
+
+/// The face of the font file `path` with content `bytes` covering `script`, if any.
+fn face_covering(path: &Path, bytes: &[u8], script: Script) -> Option<FontFile> {
+    let faces = ttf_parser::fonts_in_collection(bytes).unwrap_or(1);
+    (0..faces)
+        .find(|&index| covers(bytes, index, script))
+        .map(|index| FontFile {
+            path: path.to_owned(),
+            index,
+        })
+}
+
+/// A font for each script found in `dirs`, in the order of [`Script::ALL`], leaving out the
+/// scripts no font there covers.
+///
+/// Common fonts are looked for by file name. The other files are only read for Cyrillic and
+/// CJK when none of those is there, egui's own emoji font draws the common emoji without one.
+pub fn discover(dirs: &[PathBuf]) -> Vec<(Script, FontFile)> {
+    let files = font_files(dirs);
+    let mut found: Vec<(Script, FontFile)> = Script::ALL
+        .into_iter()
+        .filter_map(|script| {
+            let font = script.known_files().iter().find_map(|name| {
+                let path = files.iter().find(|path| {
+                    path.file_name()
+                        .is_some_and(|file_name| file_name.eq_ignore_ascii_case(name))
+                })?;
+                face_covering(path, &fs::read(path).ok()?, script)
+            })?;
+            Some((script, font))
+        })
+        .collect();
+
+    let mut missing: Vec<Script> = [Script::Cyrillic, Script::Cjk]
+        .into_iter()
+        .filter(|script| found.iter().all(|(s, _)| s != script))
+        .collect();
+    for path in &files {
+        if missing.is_empty() {
+            break;
+        }
+        let Ok(bytes) = fs::read(path) else {
+            continue;
+        };
+        missing.retain(|&script| match face_covering(path, &bytes, script) {
+            Some(font) => {
+                found.push((script, font));
+                false
+            }
+            None => true,
+        });
+    }
+    found.sort_by_key(|(script, _)| Script::ALL.iter().position(|s| s == script));
+    found
+}
+
+/// The first of `fonts` a PDF can embed, which takes single TrueType faces, not collections or
+/// CFF outlines.
+pub fn pdf_font<'a>(fonts: impl IntoIterator<Item = &'a FontFile>) -> Option<Vec<u8>> {
+    fonts.into_iter().find_map(|font| {
+        let bytes = font.load().ok()?;
+        let single = ttf_parser::fonts_in_collection(&bytes).is_none();
+        let truetype =
+            ttf_parser::Face::parse(&bytes, 0).is_ok_and(|face| face.tables().glyf.is_some());
+        (single && truetype).then_some(bytes)
+    })
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::env;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// A language the window has a catalog of texts for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Language {
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "zh-CN")]
+    SimplifiedChinese,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::SimplifiedChinese];
+
+    /// the name of the language in itself, for picking it
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::SimplifiedChinese => "简体中文",
+        }
+    }
+
+    /// The language of a locale like `zh_CN.UTF-8` or `en-US`, `None` without a catalog for it.
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let language = locale
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match language.as_str() {
+            "en" => Some(Language::English),
+            "zh" => Some(Language::SimplifiedChinese),
+            _ => None,
+        }
+    }
+
+    /// The language of the locale set by `LC_ALL`, `LC_MESSAGES` or `LANG`, in that order.
+    ///
+    /// English when none of them names a language with a catalog.
+    pub fn system() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|name| env::var(name).ok())
+            // `C` and `POSIX` set no language, the next variable may
+            .find(|locale| !locale.is_empty() && locale != "C" && locale != "POSIX")
+            .and_then(|locale| Language::from_locale(&locale))
+            .unwrap_or(Language::English)
+    }
+
+    fn catalog(self) -> &'static str {
+        match self {
+            Language::English => include_str!("../locales/en.json"),
+            Language::SimplifiedChinese => include_str!("../locales/zh-CN.json"),
+        }
+    }
+}
+
+/// The texts of the window in one language, by key
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    language: Language,
+    texts: HashMap<String, String>,
+    /// English, for the keys the language has no text for yet
+    fallback: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn new(language: Language) -> Self {
+        let parse = |language: Language| -> HashMap<String, String> {
+            serde_json::from_str(language.catalog()).expect("the catalogs are valid JSON")
+        };
+        Self {
+            language,
+            texts: parse(language),
+            fallback: parse(Language::English),
+        }
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// The text of `key`, in English when it is missing from the language and `key` itself when
+    /// English misses it too.
+    pub fn text<'a>(&'a self, key: &'a str) -> &'a str {
+        self.texts
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map_or(key, String::as_str)
+    }
+
+    /// The text of `key` with every `{name}` in it replaced by the value of `name` in `args`.
+    pub fn format(&self, key: &str, args: &[(&str, String)]) -> String {
+        let mut text = self.text(key).to_owned();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{name}}}"), value);
+        }
+        text
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+
+/// images are scaled down to fit into a square this big before they are sent
+pub const MAX_SIDE: u32 = 2048;
+
+/// whether `path` looks like an image the vision models accept
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            matches!(
+                ext.to_lowercase().as_str(),
+                "png" | "jpg" | "jpeg" | "gif" | "webp"
+            )
+        })
+}
+
+/// Reads an image file into a `data:` URL as sent to the model and kept in the conversation.
+pub fn read(path: &Path) -> io::Result<String> {
+    from_bytes(&fs::read(path)?)
+}
+
+/// An encoded image as a `data:` URL, scaled down when it is bigger than [`MAX_SIDE`].
+pub fn from_bytes(bytes: &[u8]) -> io::Result<String> {
+    let format = image::guess_format(bytes).map_err(invalid)?;
+    let image = image::load_from_memory_with_format(bytes, format).map_err(invalid)?;
+    let mime = match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::WebP => "image/webp",
+        // formats the API does not take
+        _ => return png(image),
+    };
+    if image.width() > MAX_SIDE || image.height() > MAX_SIDE {
+        return png(image);
+    }
+    Ok(data_url(mime, bytes))
+}
+
+/// Raw RGBA pixels, e.g. from the clipboard, as a PNG `data:` URL.
+pub fn from_rgba(width: u32, height: u32, rgba: Vec<u8>) -> io::Result<String> {
+    let image = RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| invalid("the pixels do not match the size"))?;
+    png(DynamicImage::ImageRgba8(image))
+}
+
+fn png(image: DynamicImage) -> io::Result<String> {
+    let image = if image.width() > MAX_SIDE || image.height() > MAX_SIDE {
+        image.resize(MAX_SIDE, MAX_SIDE, image::imageops::FilterType::Triangle)
+    } else {
+        image
+    };
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .map_err(invalid)?;
+    Ok(data_url("image/png", &bytes))
+}
+
+pub fn data_url(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{mime};base64,{}", STANDARD.encode(bytes))
+}
+
+/// a file extension matching the type of a `data:` URL
+pub fn extension(data_url: &str) -> &'static str {
+    let mime = data_url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split(';').next());
+    match mime {
+        Some("image/jpeg") => "jpg",
+        Some("image/gif") => "gif",
+        Some("image/webp") => "webp",
+        _ => "png",
+    }
+}
+
+/// the bytes of a base64 `data:` URL
+pub fn decode(data_url: &str) -> Option<Vec<u8>> {
+    let (_, data) = data_url.strip_prefix("data:")?.split_once(";base64,")?;
+    STANDARD.decode(data).ok()
+}
+
+fn invalid(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
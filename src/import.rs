@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use serde_derive::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::settings;
+use crate::store::{Message, Role, Store, StoredConversation};
+
+/// One conversation of the `conversations.json` file in a chat.openai.com data export
+#[derive(Debug, Deserialize)]
+struct ExportedConversation {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    update_time: Option<f64>,
+    /// every message of every branch, keyed by node id
+    mapping: HashMap<String, Node>,
+    /// the last message of the branch that was shown when the export was made
+    #[serde(default)]
+    current_node: Option<String>,
+    #[serde(default, alias = "conversation_id")]
+    id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(default)]
+    message: Option<ExportedMessage>,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportedMessage {
+    author: Author,
+    #[serde(default)]
+    create_time: Option<f64>,
+    content: Value,
+    #[serde(default)]
+    metadata: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    role: String,
+}
+
+/// Reads a chat.openai.com data export, either the zip archive or the `conversations.json`
+/// inside it, into conversations that continue with `role` under `profile`.
+///
+/// Only the branch that was shown last is kept, tool output and hidden messages like custom
+/// instructions are dropped.
+/// Ids are derived from the exported ones so importing the same archive again replaces the
+/// conversations instead of duplicating them.
+pub fn chatgpt_export(
+    path: &Path,
+    profile: &str,
+    role: &settings::Role,
+) -> io::Result<Vec<StoredConversation>> {
+    let mut json = String::new();
+    if path.extension().is_some_and(|ext| ext == "zip") {
+        let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+        archive
+            .by_name("conversations.json")
+            .map_err(io::Error::other)?
+            .read_to_string(&mut json)?;
+    } else {
+        File::open(path)?.read_to_string(&mut json)?;
+    }
+    let exported: Vec<ExportedConversation> = serde_json::from_str(&json)?;
+    Ok(exported
+        .iter()
+        .filter_map(|c| convert(c, profile, role))
+        .collect())
+}
+
+/// Saves imported `conversations`, keeping the stored ones that were continued after the
+/// export was made.
+///
+/// Returns how many were saved and how many were kept.
+pub fn save(store: &Store, conversations: &[StoredConversation]) -> io::Result<(usize, usize)> {
+    let mut kept = 0;
+    for conversation in conversations {
+        if store
+            .load(&conversation.id)
+            .is_ok_and(|stored| stored.updated > conversation.updated)
+        {
+            kept += 1;
+            continue;
+        }
+        store.save(conversation)?;
+    }
+    Ok((conversations.len() - kept, kept))
+}
+
+fn convert(
+    exported: &ExportedConversation,
+    profile: &str,
+    role: &settings::Role,
+) -> Option<StoredConversation> {
+    let mut conversation = StoredConversation::new(profile, role);
+    conversation.created = exported.create_time.unwrap_or_default() as u64;
+    conversation.updated = exported
+        .update_time
+        .or(exported.create_time)
+        .unwrap_or_default() as u64;
+    conversation.messages[0].timestamp = conversation.created;
+
+    for id in branch(exported) {
+        let Some(msg) = exported.mapping.get(id).and_then(|n| n.message.as_ref()) else {
+            continue;
+        };
+        let role = match msg.author.role.as_str() {
+            "user" => Role::User,
+            "assistant" => Role::Assistant,
+            "system" => Role::System,
+            // browsing, code interpreter and plugin output
+            _ => continue,
+        };
+        if msg.metadata["is_visually_hidden_from_conversation"] == Value::Bool(true) {
+            continue;
+        }
+        let Some(content) = text(&msg.content) else {
+            continue;
+        };
+        if content.trim().is_empty() {
+            continue;
+        }
+        if let Some(model) = msg.metadata["model_slug"].as_str() {
+            conversation.model = model.to_owned();
+        }
+        conversation.messages.push(Message {
+            role,
+            content,
+            timestamp: msg.create_time.unwrap_or_default() as u64,
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            citations: Vec::new(),
+        });
+    }
+    if conversation.messages.len() < 2 {
+        return None;
+    }
+    // the id names the file of the conversation, so one with `/` or `..` could write outside
+    // the store and is replaced by a hash of the conversation
+    let id = match exported.id.as_deref().filter(|id| is_safe_id(id)) {
+        Some(id) => id.to_owned(),
+        None => content_hash(exported.create_time, &conversation.messages[1..]),
+    };
+    conversation.id = format!("chatgpt-{id}");
+    conversation.title = match &exported.title {
+        Some(title) if !title.trim().is_empty() => title.trim().to_owned(),
+        _ => conversation.make_title(),
+    };
+    Some(conversation)
+}
+
+/// the same for every import of a conversation, unlike its position in the export
+fn content_hash(create_time: Option<f64>, messages: &[Message]) -> String {
+    let mut hasher = Sha256::new().chain_update(create_time.unwrap_or_default().to_le_bytes());
+    for msg in messages {
+        hasher.update(msg.content.as_bytes());
+        hasher.update([0]);
+    }
+    let digest = hasher.finalize();
+    digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// whether `id` is made of ASCII letters, digits, `_` and `-` only
+fn is_safe_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// node ids from the root to the current node, or along the newest children without one
+fn branch(exported: &ExportedConversation) -> Vec<&str> {
+    let mapping = &exported.mapping;
+    let mut ids = Vec::new();
+    match exported
+        .current_node
+        .as_deref()
+        .filter(|id| mapping.contains_key(*id))
+    {
+        Some(mut id) => loop {
+            ids.push(id);
+            match mapping[id].parent.as_deref() {
+                // a broken export could link back into the branch
+                Some(parent) if mapping.contains_key(parent) && !ids.contains(&parent) => {
+                    id = parent
+                }
+                _ => break,
+            }
+        },
+        // older exports have no current node
+        None => {
+            let root = mapping.iter().find(|(_, node)| {
+                node.parent
+                    .as_deref()
+                    .is_none_or(|p| !mapping.contains_key(p))
+            });
+            let mut next = root.map(|(id, _)| id.as_str());
+            while let Some(id) = next {
+                if ids.contains(&id) {
+                    break;
+                }
+                ids.push(id);
+                next = mapping[id]
+                    .children
+                    .last()
+                    .map(String::as_str)
+                    .filter(|c| mapping.contains_key(*c));
+            }
+            return ids;
+        }
+    }
+    ids.reverse();
+    ids
+}
+
+/// the text of a message, code is fenced and pictures and other attachments are skipped
+fn text(content: &Value) -> Option<String> {
+    match content["content_type"].as_str()? {
+        "text" | "multimodal_text" => {
+            let parts: Vec<&str> = content["parts"]
+                .as_array()?
+                .iter()
+                .filter_map(Value::as_str)
+                .collect();
+            Some(parts.join("\n"))
+        }
+        "code" => Some(format!(
+            "```{}\n{}\n```",
+            content["language"]
+                .as_str()
+                .filter(|l| *l != "unknown")
+                .unwrap_or_default(),
+            content["text"].as_str()?
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exported(id: &str) -> ExportedConversation {
+        let json = serde_json::json!({
+            "title": "Greetings",
+            "id": id,
+            "current_node": "b",
+            "mapping": {
+                "a": {
+                    "message": {
+                        "author": {"role": "user"},
+                        "content": {"content_type": "text", "parts": ["Hi"]}
+                    },
+                    "children": ["b"]
+                },
+                "b": {
+                    "message": {
+                        "author": {"role": "assistant"},
+                        "content": {"content_type": "text", "parts": ["Hello!"]}
+                    },
+                    "parent": "a"
+                }
+            }
+        });
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn import(id: &str) -> StoredConversation {
+        convert(
+            &exported(id),
+            "default",
+            &settings::Profile::default().role_list[0],
+        )
+        .unwrap()
+    }
+
+    /// a greeting answered twice, the second answer was continued
+    fn branched(current_node: Option<&str>) -> ExportedConversation {
+        let json = serde_json::json!({
+            "current_node": current_node,
+            "mapping": {
+                "root": {"children": ["a"]},
+                "a": {"parent": "root", "children": ["b1", "b2"]},
+                "b1": {"parent": "a"},
+                "b2": {"parent": "a", "children": ["c"]},
+                "c": {"parent": "b2"}
+            }
+        });
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn exported_ids_name_the_conversations() {
+        let conversation = import("6e5c0d6a-8f3b-4c1e-9d2a-1b2c3d4e5f60");
+        assert_eq!(
+            conversation.id,
+            "chatgpt-6e5c0d6a-8f3b-4c1e-9d2a-1b2c3d4e5f60"
+        );
+        assert_eq!(conversation.title, "Greetings");
+        assert_eq!(conversation.messages.len(), 3);
+    }
+
+    #[test]
+    fn ids_that_are_not_file_names_are_replaced() {
+        let replaced = import("../../.bashrc").id;
+        assert!(replaced.starts_with("chatgpt-"));
+        assert!(is_safe_id(&replaced));
+        for id in ["a/b", "a\\b", "", "名前", "x.json"] {
+            assert_eq!(import(id).id, replaced, "{id}");
+        }
+
+        let mut other = exported("a/b");
+        other.create_time = Some(1.0);
+        let role = &settings::Profile::default().role_list[0];
+        assert_ne!(convert(&other, "default", role).unwrap().id, replaced);
+    }
+
+    #[test]
+    fn conversations_continued_since_the_export_are_kept() {
+        let (_dir, store) = crate::store::tests::temp_store();
+        let mut continued = import("continued");
+        continued.updated = 20;
+        store.save(&continued).unwrap();
+        let mut stale = import("stale");
+        stale.updated = 5;
+        store.save(&stale).unwrap();
+
+        let mut imported = vec![import("continued"), import("stale"), import("new")];
+        for conversation in imported.iter_mut() {
+            conversation.updated = 10;
+        }
+        assert_eq!(save(&store, &imported).unwrap(), (2, 1));
+        assert_eq!(store.load("chatgpt-continued").unwrap().updated, 20);
+        assert_eq!(store.load("chatgpt-stale").unwrap().updated, 10);
+        assert_eq!(store.load("chatgpt-new").unwrap().updated, 10);
+    }
+
+    #[test]
+    fn the_current_node_picks_the_branch() {
+        assert_eq!(branch(&branched(Some("b1"))), ["root", "a", "b1"]);
+        assert_eq!(branch(&branched(Some("c"))), ["root", "a", "b2", "c"]);
+    }
+
+    #[test]
+    fn older_exports_follow_the_newest_answers() {
+        assert_eq!(branch(&branched(None)), ["root", "a", "b2", "c"]);
+        assert_eq!(branch(&branched(Some("gone"))), ["root", "a", "b2", "c"]);
+    }
+
+    #[test]
+    fn parents_linking_back_into_the_branch_end_it() {
+        let mut exported = branched(Some("c"));
+        exported.mapping.get_mut("root").unwrap().parent = Some("c".to_owned());
+        assert_eq!(branch(&exported), ["root", "a", "b2", "c"]);
+    }
+}
@@ -0,0 +1,25 @@
+//! The chat logic of Oxidized GPT, free of any UI so that the window, terminal and
+//! command line front-ends can share it.
+
+pub mod appearance;
+pub mod attachment;
+pub mod audio;
+pub mod client;
+pub mod commands;
+pub mod documents;
+pub mod drafts;
+pub mod export;
+pub mod fonts;
+pub mod i18n;
+pub mod images;
+pub mod import;
+pub mod prompt_history;
+pub mod search;
+pub mod session;
+pub mod settings;
+pub mod shortcuts;
+pub mod store;
+pub mod templates;
+pub mod tools;
+
+pub const APP_NAME: &str = "Oxidized GPT";
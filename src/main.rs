@@ -1,42 +1,217 @@
 use app::App;
 use chatgpt::err;
+use clap::{Parser, Subcommand};
 use eframe::IconData;
+use oxidized_gpt::audio::SpeechCache;
+use oxidized_gpt::documents::Library;
+use oxidized_gpt::prompt_history::PromptHistory;
+use oxidized_gpt::settings::Settings;
+use oxidized_gpt::store::Store;
+use oxidized_gpt::APP_NAME;
 mod app;
-mod settings;
-
-pub const APP_NAME: &str = "Oxidized GPT";
-
-fn config_font(ctx: &egui::Context) {
-    // Start with the default fonts (we will be adding to them rather than replacing them).
-    let mut fonts = egui::FontDefinitions::default();
-
-    // Install my own font (maybe supporting non-latin characters).
-    // .ttf and .otf files supported.
-    fonts.font_data.insert(
-        "my_font".to_owned(),
-        egui::FontData::from_static(include_bytes!("../media/PingFangSC.ttf")),
-    );
-
-    // Put my font first (highest priority) for proportional text:
-    fonts
-        .families
-        .entry(egui::FontFamily::Proportional)
-        .or_default()
-        .insert(0, "my_font".to_owned());
-
-    // Put my font as last fallback for monospace:
-    fonts
-        .families
-        .entry(egui::FontFamily::Monospace)
-        .or_default()
-        .push("my_font".to_owned());
-
-    // Tell egui to use these fonts:
-    ctx.set_fonts(fonts);
+mod cli;
+mod server;
+mod tui;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Name of the settings profile to start with
+    #[arg(short, long, global = true)]
+    profile: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send a one-shot prompt and print the answer, reading stdin when it is piped
+    Ask(cli::AskArgs),
+    /// List, import or export the prompt templates
+    Templates {
+        #[command(subcommand)]
+        command: cli::TemplatesCommand,
+    },
+    /// Embed the changed files of the roles' documents folders ahead of the next prompt
+    Index {
+        /// Role to index, defaults to every role of the profile with documents
+        #[arg(short, long)]
+        role: Option<String>,
+    },
+    /// Print the text spoken in an audio file
+    Transcribe {
+        /// mp3, m4a, wav, webm, ogg or flac recording of at most 25 MiB
+        path: std::path::PathBuf,
+    },
+    /// Read a saved reply aloud into an mp3 or wav file
+    Speak {
+        /// Id of the conversation as printed by `list`, defaults to the most recent one
+        id: Option<String>,
+        /// Index of the message, defaults to the last reply
+        #[arg(short, long)]
+        message: Option<usize>,
+        /// mp3 or wav
+        #[arg(short, long, default_value = "mp3")]
+        format: String,
+        /// File to write, defaults to `<id>-<message>.<format>`
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Chat in the terminal instead of opening a window
+    Tui,
+    /// List the saved conversations
+    List,
+    /// Export a saved conversation
+    Export {
+        /// Id of the conversation as printed by `list`, defaults to the most recent one
+        id: Option<String>,
+        /// md, html, json or pdf
+        #[arg(short, long, default_value = "md")]
+        format: String,
+        /// File to write, defaults to stdout
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+        /// Comma separated indexes of the messages to export, 0 is the role prompt
+        #[arg(short, long, value_delimiter = ',')]
+        messages: Vec<usize>,
+        /// TrueType font for PDF output, needed for non-latin text
+        #[arg(long)]
+        font: Option<std::path::PathBuf>,
+    },
+    /// Search the messages of all saved conversations
+    Search(cli::SearchArgs),
+    /// Import the conversations of a chat.openai.com data export
+    Import {
+        /// Role to continue the conversations with, defaults to the first one
+        #[arg(short, long)]
+        role: Option<String>,
+        /// The export's zip archive or the conversations.json inside it
+        path: std::path::PathBuf,
+    },
+    /// Serve an OpenAI compatible API on localhost with the profile's roles as models
+    Serve {
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8089")]
+        listen: std::net::SocketAddr,
+        /// Bearer token clients have to send, needed to listen beyond localhost
+        #[arg(short, long)]
+        token: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), err::Error> {
+    let cli = Cli::parse();
+
+    let mut settings = Settings::load(APP_NAME).unwrap();
+    if let Some(profile) = cli.profile {
+        if settings.profile(&profile).is_none() {
+            eprintln!("unknown profile: {profile}");
+            std::process::exit(2);
+        }
+        settings.current_profile = profile;
+    }
+
+    match cli.command {
+        Some(Command::Ask(args)) => {
+            if let Err(err) = cli::ask(&settings, &Library::open(APP_NAME)?, args).await {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Templates { command }) => {
+            if let Err(err) = cli::templates(&mut settings, APP_NAME, command) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Index { role }) => {
+            if let Err(err) = cli::index(&settings, &Library::open(APP_NAME)?, role).await {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Transcribe { path }) => {
+            if let Err(err) = cli::transcribe(&settings, path).await {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Speak {
+            id,
+            message,
+            format,
+            output,
+        }) => {
+            let store = Store::open(APP_NAME)?;
+            let cache = SpeechCache::open(APP_NAME)?;
+            if let Err(err) =
+                cli::speak(&settings, &store, &cache, id, message, &format, output).await
+            {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Tui) => {
+            tui::run(
+                settings,
+                Store::open(APP_NAME)?,
+                Library::open(APP_NAME)?,
+                PromptHistory::open(APP_NAME)?,
+            )?;
+            return Ok(());
+        }
+        Some(Command::List) => {
+            cli::list(&Store::open(APP_NAME)?);
+            return Ok(());
+        }
+        Some(Command::Export {
+            id,
+            format,
+            output,
+            messages,
+            font,
+        }) => {
+            let store = Store::open(APP_NAME)?;
+            if let Err(err) = cli::export(&store, id, &format, output, messages, font) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Search(args)) => {
+            if let Err(err) = cli::search(&Store::open(APP_NAME)?, args) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Import { role, path }) => {
+            let store = Store::open(APP_NAME)?;
+            if let Err(err) = cli::import(&settings, &store, role, path) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Serve { listen, token }) => {
+            let usage_log = oxidized_gpt::settings::config_dir(APP_NAME)?.join("usage.jsonl");
+            if let Err(err) = server::serve(&settings, listen, token, usage_log).await {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    let store = Store::open(APP_NAME)?;
+
     let native_options = eframe::NativeOptions {
         icon_data: Some(IconData {
             rgba: include_bytes!("../media/chatgpt_logo.jpeg").to_vec(),
@@ -48,11 +223,9 @@ async fn main() -> std::result::Result<(), err::Error> {
     eframe::run_native(
         APP_NAME,
         native_options,
-        Box::new(|cc| {
-            config_font(&cc.egui_ctx);
-            Box::new(App::new(cc, APP_NAME))
-        }),
-    );
+        Box::new(|cc| Box::new(App::new(cc, APP_NAME, settings, store))),
+    )
+    .unwrap();
 
     Ok(())
 }
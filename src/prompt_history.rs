@@ -0,0 +1,139 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::settings;
+
+/// prompts older than the last this many are forgotten
+pub const MAX_ENTRIES: usize = 1_000;
+
+/// The prompts sent from any front-end, oldest first, kept in a file next to the settings file
+#[derive(Clone, Debug, Default)]
+pub struct PromptHistory {
+    /// `None` keeps the history in memory only
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl PromptHistory {
+    /// the saved history, empty when nothing was sent yet
+    pub fn open(app_name: &str) -> io::Result<Self> {
+        let path = settings::config_dir(app_name)?.join("prompt_history.json");
+        let entries = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: Some(path),
+            entries,
+        })
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a sent prompt as the newest entry, an earlier copy of it is dropped.
+    pub fn push(&mut self, prompt: &str) -> io::Result<()> {
+        let prompt = prompt.trim();
+        if prompt.is_empty() {
+            return Ok(());
+        }
+        self.entries.retain(|e| e != prompt);
+        self.entries.push(prompt.to_owned());
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+        match &self.path {
+            Some(path) => fs::write(path, serde_json::to_string(&self.entries)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Entries containing every word of `query` in any case, newest first.
+    pub fn search(&self, query: &str) -> Vec<&str> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| {
+                let entry = entry.to_lowercase();
+                words.iter().all(|w| entry.contains(w))
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(prompts: &[&str]) -> PromptHistory {
+        let mut history = PromptHistory::default();
+        for prompt in prompts {
+            history.push(prompt).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn sent_again_prompts_move_to_the_end() {
+        let history = history(&["first", "second", "first"]);
+        assert_eq!(history.entries(), ["second", "first"]);
+    }
+
+    #[test]
+    fn prompts_are_trimmed_and_blank_ones_skipped() {
+        let history = history(&["  hello\n", "", " \n\t", "hello"]);
+        assert_eq!(history.entries(), ["hello"]);
+    }
+
+    #[test]
+    fn the_oldest_prompts_are_forgotten() {
+        let mut history = PromptHistory::default();
+        for i in 0..MAX_ENTRIES + 2 {
+            history.push(&i.to_string()).unwrap();
+        }
+        assert_eq!(history.len(), MAX_ENTRIES);
+        assert_eq!(history.entries()[0], "2");
+        assert_eq!(
+            history.entries()[MAX_ENTRIES - 1],
+            (MAX_ENTRIES + 1).to_string()
+        );
+    }
+
+    #[test]
+    fn search_matches_every_word_newest_first() {
+        let history = history(&["Rust traits", "python lists", "trait objects in rust"]);
+        assert_eq!(
+            history.search("RUST trait"),
+            ["trait objects in rust", "Rust traits"]
+        );
+        assert_eq!(history.search("lists"), ["python lists"]);
+        assert_eq!(history.search("").len(), 3);
+        assert!(history.search("rust lists").is_empty());
+    }
+
+    #[test]
+    fn pushed_prompts_are_written_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prompt_history.json");
+        let mut history = PromptHistory {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        history.push("hello").unwrap();
+        let saved: Vec<String> = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(saved, ["hello"]);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::time::SystemTime;
+
+use chrono::{Local, NaiveDate, TimeZone};
+
+use crate::store::{Role, Store, StoredConversation};
+
+/// Narrows a search down, `None` fields match everything
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    pub role: Option<Role>,
+    /// unix seconds, inclusive
+    pub since: Option<u64>,
+    /// unix seconds, inclusive
+    pub until: Option<u64>,
+    pub model: Option<String>,
+    /// id of the only conversation to search
+    pub conversation: Option<String>,
+}
+
+/// A message matching a search
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub conversation: String,
+    pub title: String,
+    /// index of the message in the conversation
+    pub index: usize,
+    pub role: Role,
+    pub timestamp: u64,
+    /// the part of the message around the first match
+    pub snippet: String,
+    /// byte ranges of the matches in `snippet`
+    pub highlights: Vec<Range<usize>>,
+}
+
+/// An inverted index over the messages of all saved conversations.
+///
+/// [`SearchIndex::refresh`] only reloads the files written since the last refresh, so it is
+/// cheap to call before every search.
+#[derive(Default)]
+pub struct SearchIndex {
+    conversations: HashMap<String, (SystemTime, StoredConversation)>,
+    /// token to the conversation id and message index of every message containing it
+    postings: HashMap<String, HashSet<(String, usize)>>,
+}
+
+impl SearchIndex {
+    /// picks up conversations saved, changed or deleted since the last call
+    pub fn refresh(&mut self, store: &Store) {
+        let modified = store.modified();
+        let mut changed = modified.len() != self.conversations.len();
+        let mut conversations = HashMap::new();
+        for (id, time) in modified {
+            match self.conversations.remove(&id) {
+                Some((indexed, conversation)) if indexed == time => {
+                    conversations.insert(id, (time, conversation));
+                }
+                _ => {
+                    changed = true;
+                    if let Ok(conversation) = store.load(&id) {
+                        conversations.insert(id, (time, conversation));
+                    }
+                }
+            }
+        }
+        self.conversations = conversations;
+        if changed {
+            self.rebuild();
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.postings.clear();
+        for (id, (_, conversation)) in self.conversations.iter() {
+            // the first message is the role setting message
+            for (index, msg) in conversation.messages.iter().enumerate().skip(1) {
+                for token in tokens(&msg.content) {
+                    self.postings
+                        .entry(token)
+                        .or_default()
+                        .insert((id.clone(), index));
+                }
+            }
+        }
+    }
+
+    /// the indexed conversations, most recently updated first
+    pub fn conversations(&self) -> Vec<&StoredConversation> {
+        let mut list: Vec<&StoredConversation> =
+            self.conversations.values().map(|(_, c)| c).collect();
+        list.sort_by_key(|c| std::cmp::Reverse(c.updated));
+        list
+    }
+
+    /// the models the indexed conversations were started with
+    pub fn models(&self) -> Vec<String> {
+        let mut models: Vec<String> = self
+            .conversations
+            .values()
+            .map(|(_, c)| c.model.clone())
+            .filter(|m| !m.is_empty())
+            .collect();
+        models.sort();
+        models.dedup();
+        models
+    }
+
+    /// Messages containing every word of `query`, newest first, at most `limit` of them.
+    ///
+    /// The last word also matches longer words starting with it, so results show up while
+    /// typing.
+    pub fn search(&self, query: &str, filter: &Filter, limit: usize) -> Vec<Hit> {
+        let query_tokens = tokens(query);
+        let Some((last, words)) = query_tokens.split_last() else {
+            return Vec::new();
+        };
+        let mut candidates: Option<HashSet<&(String, usize)>> = None;
+        for token in words {
+            let postings: HashSet<_> = self.postings.get(token).into_iter().flatten().collect();
+            candidates = Some(match candidates {
+                Some(c) => c.intersection(&postings).copied().collect(),
+                None => postings,
+            });
+        }
+        let prefixed: HashSet<_> = self
+            .postings
+            .iter()
+            .filter(|(token, _)| token.starts_with(last.as_str()))
+            .flat_map(|(_, postings)| postings)
+            .collect();
+        let candidates = match candidates {
+            Some(c) => c.intersection(&prefixed).copied().collect(),
+            None => prefixed,
+        };
+
+        let mut hits: Vec<Hit> = candidates
+            .into_iter()
+            .filter_map(|(id, index)| {
+                let (_, conversation) = &self.conversations[id];
+                let msg = &conversation.messages[*index];
+                let timestamp = if msg.timestamp == 0 {
+                    conversation.updated
+                } else {
+                    msg.timestamp
+                };
+                let matches = filter.role.is_none_or(|r| r == msg.role)
+                    && filter.since.is_none_or(|t| timestamp >= t)
+                    && filter.until.is_none_or(|t| timestamp <= t)
+                    && filter
+                        .model
+                        .as_ref()
+                        .is_none_or(|m| *m == conversation.model)
+                    && filter.conversation.as_ref().is_none_or(|c| c == id);
+                if !matches {
+                    return None;
+                }
+                let ranges = find(&msg.content, query);
+                let (snippet, highlights) = snippet(&msg.content, &ranges);
+                Some(Hit {
+                    conversation: id.clone(),
+                    title: conversation.title.clone(),
+                    index: *index,
+                    role: msg.role,
+                    timestamp,
+                    snippet,
+                    highlights,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.timestamp
+                .cmp(&a.timestamp)
+                .then(a.conversation.cmp(&b.conversation))
+                .then(a.index.cmp(&b.index))
+        });
+        hits.truncate(limit);
+        hits
+    }
+}
+
+/// Lowercased words of `text`, every CJK character counts as a word of its own.
+fn tokens(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if is_cjk(c) {
+            out.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+            out.push(c.to_string());
+        } else if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            out.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        out.push(word);
+    }
+    out
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x2E80..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F)
+}
+
+/// Byte ranges of the case-insensitive occurrences of the words of `query` in `text`,
+/// sorted and without overlaps.
+pub fn find(text: &str, query: &str) -> Vec<Range<usize>> {
+    let chars: Vec<(usize, char)> = text.char_indices().map(|(i, c)| (i, fold(c))).collect();
+    let mut ranges = Vec::new();
+    for word in query.split_whitespace() {
+        let word: Vec<char> = word.chars().map(fold).collect();
+        if word.is_empty() || word.len() > chars.len() {
+            continue;
+        }
+        for start in 0..=chars.len() - word.len() {
+            if chars[start..start + word.len()]
+                .iter()
+                .map(|(_, c)| *c)
+                .eq(word.iter().copied())
+            {
+                let end = chars
+                    .get(start + word.len())
+                    .map_or(text.len(), |(i, _)| *i);
+                ranges.push(chars[start].0..end);
+            }
+        }
+    }
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// characters kept around the first match in a snippet
+const CONTEXT: usize = 60;
+
+fn snippet(text: &str, ranges: &[Range<usize>]) -> (String, Vec<Range<usize>>) {
+    let first = ranges.first().map_or(0, |r| r.start);
+    let start = text[..first]
+        .char_indices()
+        .rev()
+        .nth(CONTEXT - 1)
+        .map_or(0, |(i, _)| i);
+    let end = text[first..]
+        .char_indices()
+        .nth(CONTEXT * 2)
+        .map_or(text.len(), |(i, _)| first + i);
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let offset = snippet.len();
+    snippet.push_str(&text[start..end]);
+    if end < text.len() {
+        snippet.push('…');
+    }
+    let highlights = ranges
+        .iter()
+        .filter(|r| r.start >= start && r.start < end)
+        .map(|r| r.start - start + offset..r.end.min(end) - start + offset)
+        .collect();
+    // keep snippets on one line in result lists
+    (snippet.replace('\n', " "), highlights)
+}
+
+/// Unix seconds of the start of `day`, or of its last second with `end_of_day`, in local time.
+///
+/// `day` is written as `YYYY-MM-DD`.
+pub fn parse_day(day: &str, end_of_day: bool) -> Option<u64> {
+    let date = NaiveDate::parse_from_str(day.trim(), "%Y-%m-%d").ok()?;
+    let time = if end_of_day {
+        date.and_hms_opt(23, 59, 59)?
+    } else {
+        date.and_hms_opt(0, 0, 0)?
+    };
+    let time = Local.from_local_datetime(&time).earliest()?;
+    u64::try_from(time.timestamp()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the ranges of `find` as pairs, which compare without type annotations
+    fn found(text: &str, query: &str) -> Vec<(usize, usize)> {
+        find(text, query)
+            .into_iter()
+            .map(|r| (r.start, r.end))
+            .collect()
+    }
+
+    #[test]
+    fn finds_every_occurrence_whatever_the_case() {
+        assert_eq!(found("Ab ab AB", "ab"), [(0, 2), (3, 5), (6, 8)]);
+        assert_eq!(found("Ab ab AB", "AB"), [(0, 2), (3, 5), (6, 8)]);
+    }
+
+    #[test]
+    fn offsets_are_bytes_of_the_original_text() {
+        // ß and Ä take two bytes each
+        assert_eq!(found("Straße ÄRGER", "ärger"), [(8, 14)]);
+        assert_eq!(found("Straße ÄRGER", "STRAßE"), [(0, 7)]);
+        // İ lowercases to two characters, of which the first is compared
+        assert_eq!(found("İstanbul", "istanbul"), [(0, 9)]);
+        assert_eq!(found("搜索中文", "中文"), [(6, 12)]);
+    }
+
+    #[test]
+    fn overlapping_words_are_merged() {
+        assert_eq!(found("hello world", "hell hello"), [(0, 5)]);
+        assert_eq!(found("hello world", "world hello"), [(0, 5), (6, 11)]);
+        assert!(found("hello", "  ").is_empty());
+        assert!(found("hi", "high").is_empty());
+    }
+}
@@ -0,0 +1,370 @@
+use std::convert::Infallible;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use futures_util::{stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+
+use oxidized_gpt::client::Client;
+use oxidized_gpt::settings::{Profile, Settings};
+use oxidized_gpt::store;
+
+/// State shared by all requests of the proxy
+struct Proxy {
+    profile: Profile,
+    client: Client,
+    /// bearer token requests have to carry, `None` lets every request through
+    token: Option<String>,
+    usage_log: PathBuf,
+}
+
+/// Serves an OpenAI compatible API on `addr` where every role of the current profile is a
+/// model, requests are forwarded to the profile's provider with the role prompt prepended.
+///
+/// Requests spend the profile's API key, so an address other hosts can reach is refused
+/// without a `token` for clients to send as `Authorization: Bearer <token>`.
+pub async fn serve(
+    settings: &Settings,
+    addr: SocketAddr,
+    token: Option<String>,
+    usage_log: PathBuf,
+) -> Result<(), String> {
+    if !addr.ip().is_loopback() && token.is_none() {
+        return Err(format!(
+            "refusing to serve on {addr} without --token, anyone reaching it could spend the API \
+             key of the profile"
+        ));
+    }
+    let profile = settings.current_profile().clone();
+    let client = Client::new(&profile).map_err(|e| e.to_string())?;
+    let proxy = Arc::new(Proxy {
+        profile,
+        client,
+        token,
+        usage_log,
+    });
+
+    let make_service = make_service_fn(move |_| {
+        let proxy = proxy.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(proxy.clone(), req))) }
+    });
+    let server = Server::try_bind(&addr)
+        .map_err(|e| e.to_string())?
+        .serve(make_service);
+    println!(
+        "serving roles as models on http://{}/v1",
+        server.local_addr()
+    );
+    server.await.map_err(|e| e.to_string())
+}
+
+async fn handle(proxy: Arc<Proxy>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if !authorized(proxy.token.as_deref(), &req) {
+        return Ok(error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_api_key",
+            "missing or wrong bearer token",
+        ));
+    }
+    let resp = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/v1/models") => list_models(&proxy),
+        (&Method::POST, "/v1/chat/completions") => chat_completions(&proxy, req).await,
+        _ => error(StatusCode::NOT_FOUND, "not_found", "unknown endpoint"),
+    };
+    Ok(resp)
+}
+
+/// whether `req` carries `token` as its bearer token, any request does without a token
+fn authorized(token: Option<&str>, req: &Request<Body>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|sent| same_token(sent.trim(), token))
+}
+
+/// compares every byte so the time taken does not tell how much of a guess was right
+fn same_token(sent: &str, token: &str) -> bool {
+    sent.len() == token.len()
+        && sent
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn list_models(proxy: &Proxy) -> Response<Body> {
+    let data: Vec<Value> = proxy
+        .profile
+        .role_list
+        .iter()
+        .map(|role| {
+            json!({
+                "id": role.name,
+                "object": "model",
+                "created": 0,
+                "owned_by": proxy.profile.name,
+            })
+        })
+        .collect();
+    json_response(StatusCode::OK, json!({ "object": "list", "data": data }))
+}
+
+async fn chat_completions(proxy: &Arc<Proxy>, req: Request<Body>) -> Response<Body> {
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(err) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &err.to_string(),
+            )
+        }
+    };
+    let mut body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(err) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                &err.to_string(),
+            )
+        }
+    };
+
+    let model = body["model"].as_str().unwrap_or_default().to_owned();
+    let Some(role) = proxy.profile.role_list.iter().find(|r| r.name == model) else {
+        return error(
+            StatusCode::NOT_FOUND,
+            "model_not_found",
+            &format!(
+                "no role named `{model}` in profile `{}`",
+                proxy.profile.name
+            ),
+        );
+    };
+    let Some(messages) = body["messages"].as_array_mut() else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "`messages` is required",
+        );
+    };
+    if !role.prompt.is_empty() {
+        messages.insert(0, json!({ "role": "system", "content": role.prompt }));
+    }
+    let is_stream = body["stream"].as_bool().unwrap_or(false);
+
+    let upstream = match proxy.client.forward(body).await {
+        Ok(upstream) => upstream,
+        Err(err) => return error(StatusCode::BAD_GATEWAY, "upstream_error", &err.to_string()),
+    };
+    let status = upstream.status().as_u16();
+    let content_type = upstream
+        .headers()
+        .get(CONTENT_TYPE)
+        .cloned()
+        .unwrap_or_else(|| "application/json".parse().unwrap());
+    let mut entry = UsageEntry {
+        time: store::now(),
+        role: model,
+        is_stream,
+        status,
+        usage: Value::Null,
+    };
+
+    let body = if is_stream {
+        // pass the events through as they come, picking up a `usage` object if the provider sends one
+        let usage = Arc::new(Mutex::new(Value::Null));
+        let mut scanner = UsageScanner::default();
+        let seen = usage.clone();
+        let chunks = upstream.bytes_stream().map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                if let Some(found) = scanner.feed(chunk) {
+                    *seen.lock().unwrap() = found;
+                }
+            }
+            chunk
+        });
+        let proxy = proxy.clone();
+        let done = stream::once(async move {
+            entry.usage = usage.lock().unwrap().take();
+            proxy.log(&entry);
+            Ok(Bytes::new())
+        });
+        Body::wrap_stream(chunks.chain(done))
+    } else {
+        let bytes = match upstream.bytes().await {
+            Ok(bytes) => bytes,
+            Err(err) => return error(StatusCode::BAD_GATEWAY, "upstream_error", &err.to_string()),
+        };
+        if let Ok(value) = serde_json::from_slice::<Value>(&bytes) {
+            entry.usage = value["usage"].clone();
+        }
+        proxy.log(&entry);
+        Body::from(bytes)
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(body)
+        .unwrap()
+}
+
+struct UsageEntry {
+    time: u64,
+    role: String,
+    is_stream: bool,
+    status: u16,
+    /// the `usage` object reported by the provider, null if there was none
+    usage: Value,
+}
+
+impl Proxy {
+    /// prints the request and appends it to the usage log as a JSON line
+    fn log(&self, entry: &UsageEntry) {
+        let line = json!({
+            "time": entry.time,
+            "profile": self.profile.name,
+            "role": entry.role,
+            "model": self.profile.model,
+            "stream": entry.is_stream,
+            "status": entry.status,
+            "usage": entry.usage,
+        });
+        println!("{line}");
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.usage_log)
+            .and_then(|mut file| writeln!(file, "{line}"));
+        if let Err(err) = result {
+            println!("failed to write {:?}: {err}", self.usage_log);
+        }
+    }
+}
+
+/// longest event line kept, usage is reported in short lines and a provider sending one
+/// without newlines would otherwise fill the memory
+const MAX_LINE: usize = 64 * 1024;
+
+/// Finds the `usage` object in server-sent events which may be split across chunks
+#[derive(Default)]
+struct UsageScanner {
+    line: Vec<u8>,
+}
+
+impl UsageScanner {
+    fn feed(&mut self, chunk: &[u8]) -> Option<Value> {
+        let mut found = None;
+        for &byte in chunk {
+            if byte != b'\n' {
+                // a cut line no longer parses and is skipped at its end
+                if self.line.len() < MAX_LINE {
+                    self.line.push(byte);
+                }
+                continue;
+            }
+            let line = std::mem::take(&mut self.line);
+            let Some(data) = line.strip_prefix(b"data:") else {
+                continue;
+            };
+            if let Ok(value) = serde_json::from_slice::<Value>(data.trim_ascii()) {
+                if !value["usage"].is_null() {
+                    found = Some(value["usage"].clone());
+                }
+            }
+        }
+        found
+    }
+}
+
+fn json_response(status: StatusCode, value: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+/// an error in the shape OpenAI clients expect
+fn error(status: StatusCode, error_type: &str, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        json!({ "error": { "message": message, "type": error_type } }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri("/v1/models");
+        if let Some(value) = authorization {
+            builder = builder.header(AUTHORIZATION, value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn requests_need_the_bearer_token() {
+        let token = Some("secret");
+        assert!(!authorized(token, &request(None)));
+        assert!(!authorized(token, &request(Some("Bearer wrong"))));
+        assert!(!authorized(token, &request(Some("Bearer secre"))));
+        assert!(!authorized(token, &request(Some("secret"))));
+        assert!(authorized(token, &request(Some("Bearer secret"))));
+    }
+
+    #[test]
+    fn any_request_passes_without_a_token() {
+        assert!(authorized(None, &request(None)));
+        assert!(authorized(None, &request(Some("Bearer anything"))));
+    }
+
+    #[tokio::test]
+    async fn other_hosts_are_refused_without_a_token() {
+        let addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        let err = serve(&Settings::default(), addr, None, PathBuf::new())
+            .await
+            .unwrap_err();
+        assert!(err.contains("--token"), "{err}");
+    }
+
+    #[test]
+    fn usage_split_across_chunks_is_found() {
+        let mut scanner = UsageScanner::default();
+        assert_eq!(
+            scanner.feed(b"data: {\"choices\": []}\n\ndata: {\"usa"),
+            None
+        );
+        assert_eq!(scanner.feed(b"ge\": {\"total_tokens\": 7"), None);
+        assert_eq!(
+            scanner.feed(b"}}\n\ndata: [DONE]\n\n"),
+            Some(json!({ "total_tokens": 7 }))
+        );
+    }
+
+    #[test]
+    fn overlong_lines_are_cut() {
+        let mut scanner = UsageScanner::default();
+        scanner.feed(&vec![b'x'; 3 * MAX_LINE]);
+        assert_eq!(scanner.line.len(), MAX_LINE);
+        assert_eq!(
+            scanner.feed(b"\ndata: {\"usage\": {\"total_tokens\": 1}}\n"),
+            Some(json!({ "total_tokens": 1 }))
+        );
+    }
+}
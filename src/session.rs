@@ -0,0 +1,701 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures_util::StreamExt;
+use tokio::task::AbortHandle;
+
+use crate::attachment;
+use crate::client::{Client, Delta};
+use crate::documents::{self, Library};
+use crate::settings::{self, DocumentOptions, ImageOptions, Settings};
+use crate::store::{self, Citation, Message, Role, Store, StoredConversation, ToolCall};
+use crate::tools::{self, Confirm, Registry};
+
+/// rounds of tool calls answered before a reply is given up on
+const MAX_TOOL_ROUNDS: usize = 8;
+
+/// Chat state and request dispatch shared by the egui, terminal and command line front-ends.
+///
+/// [`ChatSession::send`] streams the reply in a background task, front-ends read the state
+/// from their render loop and get woken up through its `on_update` callback.
+/// [`ChatSession::stream`] does the same inside the caller's task instead.
+///
+/// Clones share the same conversation.
+#[derive(Clone)]
+pub struct ChatSession {
+    client: Client,
+    store: Option<Store>,
+    /// local tools the model may call, each call asked about with the `Confirm`
+    tools: Option<(Arc<Registry>, Confirm)>,
+    /// indexes of the document folders of roles answering from documents
+    library: Option<Library>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    conversation: StoredConversation,
+    /// the reply currently being streamed in
+    reply: Option<String>,
+    /// images of the reply, from roles drawing instead of chatting
+    reply_images: Vec<String>,
+    /// set for roles that draw the prompt instead of chatting
+    image: Option<ImageOptions>,
+    /// set for roles answering from a folder of documents
+    documents: Option<DocumentOptions>,
+    /// the passages sent along with the prompt of the reply
+    reply_citations: Vec<Citation>,
+    /// the background task started by `send`, aborted by `cancel`
+    task: Option<AbortHandle>,
+    /// bumped on every change, so front-ends know when to scroll
+    version: u64,
+}
+
+impl ChatSession {
+    /// starts a conversation with `role` under the current profile
+    pub fn new(settings: &Settings, role: &settings::Role) -> chatgpt::Result<Self> {
+        let mut conversation = StoredConversation::new(&settings.current_profile, role);
+        conversation.model = settings.current_profile().model.clone();
+        Self::open(settings, conversation)
+    }
+
+    /// continues a saved conversation with the profile it was created under, and the model and
+    /// temperature last set for it
+    pub fn open(settings: &Settings, conversation: StoredConversation) -> chatgpt::Result<Self> {
+        let profile = settings
+            .profile(&conversation.profile)
+            .unwrap_or_else(|| settings.current_profile());
+        let role = profile
+            .role_list
+            .iter()
+            .find(|r| r.name == conversation.role);
+        let image = role.and_then(|r| r.image.clone());
+        let documents = role.and_then(|r| r.documents.clone());
+        let mut client = Client::new(profile)?;
+        if !conversation.model.is_empty() {
+            client.set_model(conversation.model.clone());
+        }
+        if let Some(temperature) = conversation.temperature {
+            client.set_temperature(temperature);
+        }
+        Ok(Self {
+            client,
+            store: None,
+            tools: None,
+            library: None,
+            shared: Arc::new(Mutex::new(Shared {
+                conversation,
+                reply: None,
+                reply_images: Vec::new(),
+                image,
+                documents,
+                reply_citations: Vec::new(),
+                task: None,
+                version: 0,
+            })),
+        })
+    }
+
+    /// saves the conversation to `store` whenever a reply is finished
+    pub fn with_store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// lets the model call the tools of `registry`, every call runs only when `confirm` agrees
+    pub fn with_tools(mut self, registry: Arc<Registry>, confirm: Confirm) -> Self {
+        self.tools = Some((registry, confirm));
+        self
+    }
+
+    pub fn without_tools(mut self) -> Self {
+        self.tools = None;
+        self
+    }
+
+    /// sends the passages of the role's documents matching each prompt along with it, the
+    /// index in `library` is brought up to date first
+    pub fn with_documents(mut self, library: Library) -> Self {
+        self.library = Some(library);
+        self
+    }
+
+    pub fn conversation(&self) -> StoredConversation {
+        self.shared.lock().unwrap().conversation.clone()
+    }
+
+    pub fn id(&self) -> String {
+        self.shared.lock().unwrap().conversation.id.clone()
+    }
+
+    /// name of the profile the conversation was created under
+    pub fn profile(&self) -> String {
+        self.shared.lock().unwrap().conversation.profile.clone()
+    }
+
+    /// follows the profile the conversation was created under when it is renamed
+    pub fn rename_profile(&self, from: &str, to: &str) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.conversation.profile == from {
+            shared.conversation.profile = to.to_owned();
+        }
+    }
+
+    /// the history including the part of the reply received so far
+    pub fn history(&self) -> Vec<Message> {
+        let shared = self.shared.lock().unwrap();
+        let mut messages = shared.conversation.messages.clone();
+        if let Some(reply) = &shared.reply {
+            let mut msg = Message::new(Role::Assistant, reply.clone());
+            msg.citations = shared.reply_citations.clone();
+            messages.push(msg);
+        }
+        messages
+    }
+
+    /// the image options of a drawing role, `None` for chat roles
+    pub fn image_options(&self) -> Option<ImageOptions> {
+        self.shared.lock().unwrap().image.clone()
+    }
+
+    /// changes the size, count or quality of the next images of a drawing role
+    pub fn set_image_options(&self, options: ImageOptions) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.image.is_some() {
+            shared.image = Some(options);
+        }
+    }
+
+    /// changes the documents folder the next replies are given from, `None` to stop using one
+    pub fn set_documents(&self, documents: Option<DocumentOptions>) {
+        self.shared.lock().unwrap().documents = documents;
+    }
+
+    /// answers the next prompts with `model`, which is recorded with the conversation
+    pub fn set_model(&mut self, model: String) {
+        self.client.set_model(model.clone());
+        self.shared.lock().unwrap().conversation.model = model;
+    }
+
+    /// answers the next prompts with `temperature`, which is recorded with the conversation
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.client.set_temperature(temperature);
+        self.shared.lock().unwrap().conversation.temperature = Some(temperature);
+    }
+
+    /// Drops every message but the role prompt and saves the conversation, does nothing while
+    /// a reply is being received.
+    pub fn clear(&self) -> io::Result<()> {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.reply.is_some() {
+                return Ok(());
+            }
+            shared.conversation.messages.truncate(1);
+            shared.conversation.updated = store::now();
+            shared.version += 1;
+        }
+        self.save()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.shared.lock().unwrap().version
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.shared.lock().unwrap().reply.is_some()
+    }
+
+    /// Appends `pmt` to the history and streams the reply in the background.
+    ///
+    /// Does nothing while a reply is still being received.
+    pub fn send(&self, pmt: String, on_update: impl Fn() + Send + Sync + 'static) {
+        self.send_message(Message::new(Role::User, pmt), on_update);
+    }
+
+    /// Like [`ChatSession::send`] for a message carrying more than text, e.g. images.
+    pub fn send_message(&self, msg: Message, on_update: impl Fn() + Send + Sync + 'static) {
+        let Some(history) = self.begin(msg) else {
+            return;
+        };
+        let session = self.clone();
+        let task = tokio::spawn(async move {
+            let result = session.receive(&history, |_| on_update()).await;
+            session.finish(result.err().map(|e| e.to_string()));
+            on_update();
+        });
+        self.shared.lock().unwrap().task = Some(task.abort_handle());
+    }
+
+    /// Appends `pmt` to the history and streams the reply, handing every piece to `on_delta`.
+    pub async fn stream(&self, pmt: String, on_delta: impl FnMut(&str)) -> chatgpt::Result<()> {
+        self.stream_message(Message::new(Role::User, pmt), on_delta)
+            .await
+    }
+
+    /// Like [`ChatSession::stream`] for a message carrying more than text, e.g. images.
+    pub async fn stream_message(
+        &self,
+        msg: Message,
+        on_delta: impl FnMut(&str),
+    ) -> chatgpt::Result<()> {
+        let Some(history) = self.begin(msg) else {
+            return Err(chatgpt::err::Error::ParsingError(
+                "a reply is still being received".to_owned(),
+            ));
+        };
+        let result = self.receive(&history, on_delta).await;
+        self.finish(result.as_ref().err().map(|e| e.to_string()));
+        result
+    }
+
+    /// Stops receiving the current reply, the part received so far is kept.
+    pub fn cancel(&self) {
+        let task = self.shared.lock().unwrap().task.take();
+        if let Some(task) = task {
+            task.abort();
+        }
+        self.finish(None);
+    }
+
+    /// Replaces the text of the user message at `index` with `content`, drops everything
+    /// after it and asks again.
+    pub fn edit(
+        &self,
+        index: usize,
+        content: String,
+        on_update: impl Fn() + Send + Sync + 'static,
+    ) {
+        let msg = {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.reply.is_some()
+                || shared.conversation.messages.get(index).map(|m| m.role) != Some(Role::User)
+            {
+                return;
+            }
+            let mut msg = Message::new(Role::User, content);
+            msg.images = shared.conversation.messages[index].images.clone();
+            shared.conversation.messages.truncate(index);
+            msg
+        };
+        self.send_message(msg, on_update);
+    }
+
+    /// Asks again for the reply to the last user message.
+    pub fn retry(&self, on_update: impl Fn() + Send + Sync + 'static) {
+        let last = {
+            let shared = self.shared.lock().unwrap();
+            let messages = &shared.conversation.messages;
+            messages
+                .iter()
+                .rposition(|m| m.role == Role::User)
+                .map(|i| (i, messages[i].content.clone()))
+        };
+        if let Some((index, content)) = last {
+            self.edit(index, content, on_update);
+        }
+    }
+
+    /// writes the conversation to the store given to [`ChatSession::with_store`], if any
+    pub fn save(&self) -> io::Result<()> {
+        match &self.store {
+            Some(store) => store.save(&self.shared.lock().unwrap().conversation),
+            None => Ok(()),
+        }
+    }
+
+    /// pushes the user message, returns the history to send or `None` while busy
+    fn begin(&self, msg: Message) -> Option<Vec<Message>> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.reply.is_some() {
+            return None;
+        }
+        shared.conversation.messages.push(msg);
+        if shared.conversation.title.is_empty() {
+            shared.conversation.title = shared.conversation.make_title();
+        }
+        shared.reply = Some(String::new());
+        shared.version += 1;
+        Some(shared.conversation.messages.clone())
+    }
+
+    async fn receive(
+        &self,
+        history: &[Message],
+        mut on_delta: impl FnMut(&str),
+    ) -> chatgpt::Result<()> {
+        let image = self.shared.lock().unwrap().image.clone();
+        if let Some(options) = image {
+            let prompt = history
+                .last()
+                .map(|m| m.content.as_str())
+                .unwrap_or_default();
+            let generated = self.client.generate_images(prompt, &options).await?;
+            let text = generated.revised_prompts.join("\n\n");
+            let mut shared = self.shared.lock().unwrap();
+            match shared.reply.as_mut() {
+                Some(reply) => reply.push_str(&text),
+                // cancelled
+                None => return Ok(()),
+            }
+            shared.reply_images = generated.images;
+            shared.version += 1;
+            drop(shared);
+            on_delta(&text);
+            return Ok(());
+        }
+        let context = self.retrieve(history).await?;
+        // the passages go right before the prompt, also when resending after tool calls
+        let with_context = |mut history: Vec<Message>| {
+            if let Some(context) = &context {
+                let i = history
+                    .iter()
+                    .rposition(|m| m.role == Role::User)
+                    .unwrap_or(history.len());
+                history.insert(i, context.clone());
+            }
+            history
+        };
+        let mut history = with_context(history.to_vec());
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let definitions = match &self.tools {
+                Some((registry, _)) => registry.definitions(),
+                None => Vec::new(),
+            };
+            let mut stream = self
+                .client
+                .send_history_streaming(&history, &definitions)
+                .await?;
+            let mut calls: Vec<ToolCall> = Vec::new();
+            while let Some(delta) = stream.next().await {
+                match delta? {
+                    Delta::Text(delta) => {
+                        let mut shared = self.shared.lock().unwrap();
+                        match shared.reply.as_mut() {
+                            Some(reply) => reply.push_str(&delta),
+                            // cancelled
+                            None => return Ok(()),
+                        }
+                        shared.version += 1;
+                        drop(shared);
+                        on_delta(&delta);
+                    }
+                    Delta::ToolCall {
+                        index,
+                        id,
+                        name,
+                        arguments,
+                    } => {
+                        if calls.len() <= index {
+                            calls.resize(index + 1, ToolCall::default());
+                        }
+                        let call = &mut calls[index];
+                        call.id.extend(id);
+                        call.name.extend(name);
+                        call.arguments.push_str(&arguments);
+                    }
+                }
+            }
+            if calls.is_empty() {
+                return Ok(());
+            }
+            match self.run_tools(calls).await {
+                Some(messages) => history = with_context(messages),
+                None => return Ok(()),
+            }
+            on_delta("");
+        }
+        Err(chatgpt::err::Error::ParsingError(format!(
+            "gave up after {MAX_TOOL_ROUNDS} rounds of tool calls"
+        )))
+    }
+
+    /// The system message with the passages of the role's documents matching the prompt, when
+    /// there are any.
+    async fn retrieve(&self, history: &[Message]) -> chatgpt::Result<Option<Message>> {
+        let documents = self.shared.lock().unwrap().documents.clone();
+        let (Some(library), Some(options)) = (&self.library, documents) else {
+            return Ok(None);
+        };
+        let query = history
+            .last()
+            .map(|m| attachment::text(&m.content))
+            .unwrap_or_default();
+        let passages = library.retrieve(&self.client, &options, &query).await?;
+        let mut shared = self.shared.lock().unwrap();
+        // cancelled meanwhile, the citations would end up under the next reply
+        if shared.reply.is_none() || passages.is_empty() {
+            return Ok(None);
+        }
+        let folder = options.folder.canonicalize()?;
+        shared.reply_citations = passages.iter().map(|p| p.citation(&folder)).collect();
+        shared.version += 1;
+        Ok(Some(Message::new(
+            Role::System,
+            documents::context(&passages),
+        )))
+    }
+
+    /// Moves the text received so far into a message with the tool `calls`, runs the ones
+    /// the user agrees to and returns the history to send next, `None` when cancelled.
+    async fn run_tools(&self, calls: Vec<ToolCall>) -> Option<Vec<Message>> {
+        let index = {
+            let mut shared = self.shared.lock().unwrap();
+            let reply = std::mem::take(shared.reply.as_mut()?);
+            let mut msg = Message::new(Role::Assistant, reply);
+            msg.tool_calls = calls.clone();
+            shared.conversation.messages.push(msg);
+            shared.version += 1;
+            shared.conversation.messages.len() - 1
+        };
+        for (i, call) in calls.into_iter().enumerate() {
+            let result = match &self.tools {
+                Some((registry, confirm)) => {
+                    if confirm(call.clone()).await {
+                        registry.call(&call.name, &call.arguments).await
+                    } else {
+                        tools::DECLINED.to_owned()
+                    }
+                }
+                None => format!("error: there is no tool called `{}`", call.name),
+            };
+            let mut shared = self.shared.lock().unwrap();
+            shared.reply.as_ref()?;
+            shared.conversation.messages[index].tool_calls[i].result = Some(result);
+            shared.version += 1;
+        }
+        Some(self.shared.lock().unwrap().conversation.messages.clone())
+    }
+
+    /// moves the received reply into the history and saves the conversation
+    fn finish(&self, error: Option<String>) {
+        let mut shared = self.shared.lock().unwrap();
+        // already finished by `cancel`
+        let Some(reply) = shared.reply.take() else {
+            return;
+        };
+        shared.task = None;
+        let images = std::mem::take(&mut shared.reply_images);
+        let citations = std::mem::take(&mut shared.reply_citations);
+        if !reply.is_empty() || !images.is_empty() {
+            let mut msg = Message::new(Role::Assistant, reply);
+            msg.images = images;
+            msg.citations = citations;
+            shared.conversation.messages.push(msg);
+        }
+        if let Some(error) = error {
+            shared
+                .conversation
+                .messages
+                .push(Message::new(Role::System, error));
+        }
+        shared.conversation.updated = store::now();
+        shared.version += 1;
+        if let Some(store) = &self.store {
+            if let Err(err) = store.save(&shared.conversation) {
+                shared.conversation.messages.push(Message::new(
+                    Role::System,
+                    format!("failed to save conversation: {err}"),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::store::tests::temp_store;
+
+    /// Serves a chat completions endpoint replying `re: ` and the last user message, except to
+    /// `hang` which gets `partial` and then nothing more. `settings?` is answered with the
+    /// model and temperature asked for.
+    async fn fake_api() -> String {
+        async fn reply(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            let prompt = body["messages"]
+                .as_array()
+                .and_then(|m| m.iter().rev().find(|m| m["role"] == "user"))
+                .and_then(|m| m["content"].as_str())
+                .unwrap_or_default()
+                .to_owned();
+            let prompt = match prompt.as_str() {
+                "settings?" => format!("{} {}", body["model"], body["temperature"]),
+                _ => prompt,
+            };
+            let chunk = |text: &str| {
+                let chunk = serde_json::json!({ "choices": [{ "delta": { "content": text } }] });
+                format!("data: {chunk}\n\n")
+            };
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                if prompt == "hang" {
+                    sender.send_data(chunk("partial").into()).await.unwrap();
+                    std::future::pending::<()>().await;
+                }
+                let text = chunk(&format!("re: {prompt}")) + "data: [DONE]\n\n";
+                sender.send_data(text.into()).await.unwrap();
+            });
+            Ok(Response::new(body))
+        }
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service_fn(
+            |_| async { Ok::<_, Infallible>(service_fn(reply)) },
+        ));
+        let url = format!("http://{}/v1/chat/completions", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    async fn settings() -> Settings {
+        let mut settings = Settings::default();
+        let profile = settings.current_profile_mut();
+        profile.provider = settings::Provider::Local;
+        profile.api_url = fake_api().await;
+        settings
+    }
+
+    async fn session() -> ChatSession {
+        let settings = settings().await;
+        let role = settings.current_profile().role_list[0].clone();
+        ChatSession::new(&settings, &role).unwrap()
+    }
+
+    /// waits until `check` holds for the session, failing after a few seconds
+    async fn wait_for(session: &ChatSession, check: impl Fn(&ChatSession) -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !check(session) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the session did not get there in time");
+    }
+
+    async fn send(session: &ChatSession, pmt: &str) {
+        session.send(pmt.to_owned(), || {});
+        wait_for(session, |s| !s.is_busy()).await;
+    }
+
+    /// roles and contents of the messages after the role prompt
+    fn messages(session: &ChatSession) -> Vec<(Role, String)> {
+        session.history()[1..]
+            .iter()
+            .map(|m| (m.role, m.content.clone()))
+            .collect()
+    }
+
+    fn user(content: &str) -> (Role, String) {
+        (Role::User, content.to_owned())
+    }
+
+    fn assistant(content: &str) -> (Role, String) {
+        (Role::Assistant, content.to_owned())
+    }
+
+    #[tokio::test]
+    async fn replies_are_added_to_the_history() {
+        let session = session().await;
+        send(&session, "one").await;
+        send(&session, "two").await;
+        assert_eq!(
+            messages(&session),
+            [
+                user("one"),
+                assistant("re: one"),
+                user("two"),
+                assistant("re: two")
+            ]
+        );
+        assert_eq!(session.conversation().title, "one");
+    }
+
+    #[tokio::test]
+    async fn clear_keeps_the_role_prompt_and_saves() {
+        let (_dir, store) = temp_store();
+        let session = session().await.with_store(store.clone());
+        send(&session, "one").await;
+        session.clear().unwrap();
+        assert_eq!(session.history().len(), 1);
+        assert_eq!(session.history()[0].role, Role::System);
+        assert_eq!(store.load(&session.id()).unwrap().messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn edit_drops_the_later_messages_and_asks_again() {
+        let session = session().await;
+        send(&session, "one").await;
+        send(&session, "two").await;
+        session.edit(1, "uno".to_owned(), || {});
+        wait_for(&session, |s| !s.is_busy()).await;
+        assert_eq!(messages(&session), [user("uno"), assistant("re: uno")]);
+
+        // only user messages can be edited
+        session.edit(2, "changed".to_owned(), || {});
+        assert!(!session.is_busy());
+        assert_eq!(messages(&session), [user("uno"), assistant("re: uno")]);
+    }
+
+    #[tokio::test]
+    async fn retry_asks_again_for_the_last_reply() {
+        let session = session().await;
+        send(&session, "one").await;
+        send(&session, "two").await;
+        let version = session.version();
+        session.retry(|| {});
+        wait_for(&session, |s| !s.is_busy()).await;
+        // the reply is the same as before but was received anew
+        assert!(session.version() > version + 1);
+        assert_eq!(
+            messages(&session),
+            [
+                user("one"),
+                assistant("re: one"),
+                user("two"),
+                assistant("re: two")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_keeps_the_part_received_so_far() {
+        let (_dir, store) = temp_store();
+        let session = session().await.with_store(store.clone());
+        session.send("hang".to_owned(), || {});
+        wait_for(&session, |s| {
+            s.history().last().map(|m| m.content.as_str()) == Some("partial")
+        })
+        .await;
+        // busy sessions are neither cleared nor sent to
+        session.clear().unwrap();
+        session.send("two".to_owned(), || {});
+        assert_eq!(messages(&session), [user("hang"), assistant("partial")]);
+
+        session.cancel();
+        assert!(!session.is_busy());
+        assert_eq!(messages(&session), [user("hang"), assistant("partial")]);
+        assert_eq!(store.load(&session.id()).unwrap().messages.len(), 3);
+    }
+    #[tokio::test]
+    async fn reopened_conversations_keep_their_model_and_temperature() {
+        let settings = settings().await;
+        let role = settings.current_profile().role_list[0].clone();
+        let mut session = ChatSession::new(&settings, &role).unwrap();
+        session.set_model("gpt-4o".to_owned());
+        session.set_temperature(0.5);
+        send(&session, "settings?").await;
+        let reply = r#"re: "gpt-4o" 0.5"#;
+        assert_eq!(session.history().last().unwrap().content, reply);
+
+        let reopened = ChatSession::open(&settings, session.conversation()).unwrap();
+        send(&reopened, "settings?").await;
+        assert_eq!(reopened.history().last().unwrap().content, reply);
+    }
+}
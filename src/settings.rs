@@ -1,49 +1,389 @@
+use std::io;
+use std::path::PathBuf;
+
 use serde_derive::{Deserialize, Serialize};
 
+use crate::appearance::Appearance;
+use crate::i18n::Language;
+use crate::shortcuts::Keymap;
+use crate::templates::{self, Template};
+
+/// the directory of the settings file, other files of the app are kept next to it
+pub fn config_dir(app_name: &str) -> io::Result<PathBuf> {
+    let config_path =
+        confy::get_configuration_file_path(app_name, None).map_err(io::Error::other)?;
+    Ok(config_path
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default())
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
+    /// name of the profile used for new conversations
+    pub current_profile: String,
+    /// language of the window, `None` follows the system locale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<Language>,
+    pub profiles: Vec<Profile>,
+    pub tools: ToolSettings,
+    /// keyboard shortcuts of the window
+    pub keymap: Keymap,
+    /// theme, text size and fonts of the window
+    pub appearance: Appearance,
+    /// user prompts with `{{variable}}` placeholders, shared by every profile
+    pub templates: Vec<Template>,
+
+    // fields of the single-profile format, only read to migrate old config files
+    #[serde(skip_serializing)]
+    api_key: Option<String>,
+    #[serde(skip_serializing)]
+    api_url: Option<String>,
+    #[serde(skip_serializing)]
+    role_list: Option<Vec<Role>>,
+}
+
+/// Which local tools the model may call, each call still needs the user's approval
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolSettings {
+    /// advertise the tools to the model, servers without tool support reject requests with them
+    pub enabled: bool,
+    /// directories the file tools may read, nothing outside of them
+    pub allowed_dirs: Vec<PathBuf>,
+    /// host names the fetch tool may request, e.g. `docs.rs`
+    pub allowed_hosts: Vec<String>,
+}
+
+/// A named bundle of provider, credentials, default model and roles
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub provider: Provider,
     pub api_key: String,
     pub api_url: String,
+    pub model: String,
+    /// transcription endpoint, e.g. of a local whisper server, empty for the one next to
+    /// `api_url`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub transcription_url: String,
+    #[serde(default = "default_transcription_model")]
+    pub transcription_model: String,
+    /// text to speech endpoint, e.g. of a local engine, empty for the one next to `api_url`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub speech_url: String,
+    #[serde(default = "default_speech_model")]
+    pub speech_model: String,
+    #[serde(default = "default_speech_voice")]
+    pub speech_voice: String,
+    /// embeddings endpoint for document folders, e.g. of a local embedding model, empty for
+    /// the one next to `api_url`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub embedding_url: String,
+    #[serde(default = "default_embedding_model")]
+    pub embedding_model: String,
+    // TOML writes tables after plain values, so the roles come last
+    /// never empty in loaded settings, the first role starts new conversations
     pub role_list: Vec<Role>,
 }
 
+fn default_transcription_model() -> String {
+    "whisper-1".into()
+}
+
+fn default_speech_model() -> String {
+    "tts-1".into()
+}
+
+fn default_speech_voice() -> String {
+    "alloy".into()
+}
+
+fn default_embedding_model() -> String {
+    "text-embedding-3-small".into()
+}
+
+/// The kind of service behind a profile, which decides how requests are authenticated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Provider {
+    /// `Authorization: Bearer <api_key>`
+    OpenAI,
+    /// `api-key: <api_key>`, the model is picked by the deployment in `api_url`
+    Azure,
+    /// OpenAI compatible local server, the api key is optional
+    Local,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 3] = [Provider::OpenAI, Provider::Azure, Provider::Local];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => "OpenAI",
+            Provider::Azure => "Azure",
+            Provider::Local => "Local",
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Role {
     pub name: String,
     pub prompt: String,
     pub icon_base64: String,
+    /// roles with image options draw the prompt with the images endpoint instead of chatting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageOptions>,
+    /// passages of these documents matching the prompt are sent along with it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documents: Option<DocumentOptions>,
+}
+
+/// A folder of Markdown, text or source files a role answers from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentOptions {
+    pub folder: PathBuf,
+    /// passages sent with every prompt
+    pub top_k: usize,
+}
+
+impl Default for DocumentOptions {
+    fn default() -> Self {
+        Self {
+            folder: PathBuf::new(),
+            top_k: 4,
+        }
+    }
+}
+
+/// What the images generations endpoint is asked for
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageOptions {
+    pub model: String,
+    /// `WIDTHxHEIGHT`, one of [`ImageOptions::SIZES`]
+    pub size: String,
+    /// images per prompt
+    pub count: u8,
+    /// `standard` or `hd`
+    pub quality: String,
+}
+
+impl ImageOptions {
+    pub const SIZES: [&'static str; 5] =
+        ["256x256", "512x512", "1024x1024", "1792x1024", "1024x1792"];
+    pub const QUALITIES: [&'static str; 2] = ["standard", "hd"];
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            model: "dall-e-3".into(),
+            size: "1024x1024".into(),
+            count: 1,
+            quality: "standard".into(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(app_name: &str) -> Result<Self, confy::ConfyError> {
+        let mut settings: Settings = confy::load(app_name, None)?;
+        settings.migrate_legacy();
+        settings.fill_in();
+        Ok(settings)
+    }
+
+    /// Completes a config file edited by hand: there is a profile, the current one exists and
+    /// every profile has a role to start conversations with.
+    fn fill_in(&mut self) {
+        if self.profiles.is_empty() {
+            self.profiles.push(Profile::default());
+        }
+        if self.profile(&self.current_profile).is_none() {
+            self.current_profile = self.profiles[0].name.clone();
+        }
+        for profile in self.profiles.iter_mut() {
+            if profile.role_list.is_empty() {
+                profile.role_list = Profile::default().role_list;
+            }
+        }
+    }
+
+    pub fn store(&self, app_name: &str) -> Result<(), confy::ConfyError> {
+        confy::store(app_name, None, self.clone())
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn current_profile(&self) -> &Profile {
+        self.profile(&self.current_profile)
+            .unwrap_or_else(|| &self.profiles[0])
+    }
+
+    pub fn current_profile_mut(&mut self) -> &mut Profile {
+        let idx = self
+            .profiles
+            .iter()
+            .position(|p| p.name == self.current_profile)
+            .unwrap_or(0);
+        &mut self.profiles[idx]
+    }
+
+    /// turn a config file written before profiles existed into a "default" profile
+    fn migrate_legacy(&mut self) {
+        if self.api_key.is_none() && self.api_url.is_none() && self.role_list.is_none() {
+            return;
+        }
+        let mut profile = Profile::default();
+        if let Some(api_key) = self.api_key.take() {
+            profile.api_key = api_key;
+        }
+        if let Some(api_url) = self.api_url.take() {
+            profile.api_url = api_url;
+        }
+        if let Some(role_list) = self.role_list.take() {
+            profile.role_list = role_list;
+        }
+        // old files have no profiles, so the one to replace is the built-in default
+        self.profiles.retain(|p| p.name != profile.name);
+        self.current_profile = profile.name.clone();
+        self.profiles.insert(0, profile);
+    }
 }
 
 /// `MyConfig` implements `Default`
 impl ::std::default::Default for Settings {
     fn default() -> Self {
+        let profile = Profile::default();
         Self {
+            current_profile: profile.name.clone(),
+            language: None,
+            profiles: vec![profile],
+            tools: ToolSettings::default(),
+            keymap: Keymap::default(),
+            appearance: Appearance::default(),
+            templates: templates::defaults(),
+            api_key: None,
+            api_url: None,
+            role_list: None,
+        }
+    }
+}
+
+impl ::std::default::Default for Profile {
+    fn default() -> Self {
+        Self {
+            name: "default".into(),
+            provider: Provider::OpenAI,
             api_key: "".into(),
             api_url: "https://api.openai.com/v1/chat/completions".into(),
+            model: "gpt-3.5-turbo".into(),
+            transcription_url: "".into(),
+            transcription_model: default_transcription_model(),
+            speech_url: "".into(),
+            speech_model: default_speech_model(),
+            speech_voice: default_speech_voice(),
+            embedding_url: "".into(),
+            embedding_model: default_embedding_model(),
             role_list: Vec::from_iter([
                 Role {
                     name: "XXXGPT".into(),
                     prompt: "You are XXXGPT, an ai model".into(),
                     icon_base64: "".into(),
+                    image: None,
+                    documents: None,
                 },
                 Role {
                     name: "ChatGPT".into(),
                     prompt: "You are ChatGPT, an ai model".into(),
                     icon_base64: "".into(),
+                    image: None,
+                    documents: None,
                 },
                 Role {
                     name: "Translator".into(),
                     prompt: "You are TranGPT dedicated for translating between Chinese and English"
                         .into(),
                     icon_base64: "".into(),
+                    image: None,
+                    documents: None,
                 },
                 Role {
                     name: "Last".into(),
                     prompt: "You are LastGPT dedicated for translating between Chinese and English"
                         .into(),
                     icon_base64: "".into(),
+                    image: None,
+                    documents: None,
+                },
+                Role {
+                    name: "Painter".into(),
+                    prompt: "".into(),
+                    icon_base64: "".into(),
+                    image: Some(ImageOptions::default()),
+                    documents: None,
                 },
             ]),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TOML fails with `ValueAfterTable` when a plain value follows a table, which breaks
+    /// `Settings::store`
+    fn round_trip(settings: &Settings) -> Settings {
+        let text = toml::to_string(settings).expect("plain values come before tables");
+        let back: Settings = toml::from_str(&text).unwrap();
+        assert_eq!(toml::to_string(&back).unwrap(), text);
+        back
+    }
+
+    #[test]
+    fn profiles_without_roles_get_the_default_ones() {
+        let mut settings: Settings = toml::from_str(
+            "current_profile = 'gone'\n\n[[profiles]]\nname = 'empty'\nprovider = 'Local'\napi_key = ''\napi_url = 'http://localhost:8080'\nmodel = 'llama'\nrole_list = []\n",
+        )
+        .unwrap();
+        settings.fill_in();
+        assert_eq!(settings.current_profile, "empty");
+        assert_eq!(
+            settings.current_profile().role_list,
+            Profile::default().role_list
+        );
+    }
+
+    #[test]
+    fn default_settings_round_trip_through_toml() {
+        round_trip(&Settings::default());
+    }
+
+    #[test]
+    fn optional_fields_round_trip_through_toml() {
+        let mut settings = Settings {
+            language: Some(Language::SimplifiedChinese),
+            ..Settings::default()
+        };
+        let profile = settings.current_profile_mut();
+        profile.transcription_url = "http://localhost:8080/inference".into();
+        profile.speech_url = "http://localhost:5002/speak".into();
+        profile.embedding_url = "http://localhost:11434/v1/embeddings".into();
+        profile.role_list[0].documents = Some(DocumentOptions {
+            folder: "/tmp/notes".into(),
+            top_k: 3,
+        });
+
+        let back = round_trip(&settings);
+        let profile = back.current_profile();
+        assert_eq!(profile.speech_url, "http://localhost:5002/speak");
+        assert_eq!(profile.role_list, settings.current_profile().role_list);
+        assert_eq!(back.language, Some(Language::SimplifiedChinese));
+    }
+}
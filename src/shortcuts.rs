@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserializer;
+use serde_derive::{Deserialize, Serialize};
+
+/// What a keyboard shortcut of the window does
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    NewChat,
+    /// open the next conversation of the list
+    NextChat,
+    /// open the previous conversation of the list
+    PreviousChat,
+    FocusInput,
+    /// stop receiving the reply
+    Stop,
+    /// ask again for the reply to the last prompt
+    Regenerate,
+    CopyLastAnswer,
+    /// show or hide the side panel with the settings and conversations
+    ToggleSettings,
+    /// search all conversations
+    Search,
+    /// find text in the open conversation
+    Find,
+    /// search the prompts sent before
+    RecallPrompt,
+    /// show the shortcuts
+    CheatSheet,
+}
+
+impl Action {
+    pub const ALL: [Action; 12] = [
+        Action::NewChat,
+        Action::NextChat,
+        Action::PreviousChat,
+        Action::FocusInput,
+        Action::Stop,
+        Action::Regenerate,
+        Action::CopyLastAnswer,
+        Action::ToggleSettings,
+        Action::Search,
+        Action::Find,
+        Action::RecallPrompt,
+        Action::CheatSheet,
+    ];
+
+    /// the name of the action's shortcut in the settings
+    fn setting(self) -> &'static str {
+        match self {
+            Action::NewChat => "new_chat",
+            Action::NextChat => "next_chat",
+            Action::PreviousChat => "previous_chat",
+            Action::FocusInput => "focus_input",
+            Action::Stop => "stop",
+            Action::Regenerate => "regenerate",
+            Action::CopyLastAnswer => "copy_last_answer",
+            Action::ToggleSettings => "toggle_settings",
+            Action::Search => "search",
+            Action::Find => "find",
+            Action::RecallPrompt => "recall_prompt",
+            Action::CheatSheet => "cheat_sheet",
+        }
+    }
+}
+
+/// A key with modifiers as written in the settings, e.g. `Ctrl+Shift+F`.
+///
+/// Ctrl stands for Cmd on macOS. Keys are named like `A`, `1`, `F5`, `Enter`, `Escape`,
+/// `PageUp` or `Up`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Shortcut {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub key: String,
+}
+
+impl Shortcut {
+    fn ctrl(key: &str) -> Self {
+        Self {
+            ctrl: true,
+            ..Self::key(key)
+        }
+    }
+
+    fn ctrl_shift(key: &str) -> Self {
+        Self {
+            shift: true,
+            ..Self::ctrl(key)
+        }
+    }
+
+    fn key(key: &str) -> Self {
+        Self {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key: key.to_owned(),
+        }
+    }
+}
+
+impl FromStr for Shortcut {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        if key.is_empty() {
+            return Err(format!("`{s}` names no key"));
+        }
+        let mut shortcut = Shortcut::key(key);
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "cmd" | "command" => shortcut.ctrl = true,
+                "alt" | "option" => shortcut.alt = true,
+                "shift" => shortcut.shift = true,
+                _ => return Err(format!("unknown modifier `{modifier}` in `{s}`")),
+            }
+        }
+        Ok(shortcut)
+    }
+}
+
+impl TryFrom<String> for Shortcut {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Shortcut> for String {
+    fn from(shortcut: Shortcut) -> Self {
+        shortcut.to_string()
+    }
+}
+
+impl fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            f.write_str("Ctrl+")?;
+        }
+        if self.alt {
+            f.write_str("Alt+")?;
+        }
+        if self.shift {
+            f.write_str("Shift+")?;
+        }
+        f.write_str(&self.key)
+    }
+}
+
+/// Which key sends the prompt, the other one of Enter and Ctrl+Enter starts a new line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendMode {
+    #[default]
+    Enter,
+    CtrlEnter,
+}
+
+impl SendMode {
+    /// whether Enter pressed with these modifiers sends, Shift+Enter always starts a new line
+    pub fn sends(self, ctrl: bool, shift: bool) -> bool {
+        match self {
+            SendMode::Enter => !shift,
+            SendMode::CtrlEnter => ctrl && !shift,
+        }
+    }
+}
+
+/// The shortcuts of the window by action.
+///
+/// It is meant to be edited by hand, so entries that do not read as a shortcut keep their
+/// default with a warning instead of failing to load the settings.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Keymap {
+    pub send_mode: SendMode,
+    pub new_chat: Shortcut,
+    pub next_chat: Shortcut,
+    pub previous_chat: Shortcut,
+    pub focus_input: Shortcut,
+    pub stop: Shortcut,
+    pub regenerate: Shortcut,
+    pub copy_last_answer: Shortcut,
+    pub toggle_settings: Shortcut,
+    pub search: Shortcut,
+    pub find: Shortcut,
+    pub recall_prompt: Shortcut,
+    pub cheat_sheet: Shortcut,
+}
+
+impl Keymap {
+    pub fn get(&self, action: Action) -> &Shortcut {
+        match action {
+            Action::NewChat => &self.new_chat,
+            Action::NextChat => &self.next_chat,
+            Action::PreviousChat => &self.previous_chat,
+            Action::FocusInput => &self.focus_input,
+            Action::Stop => &self.stop,
+            Action::Regenerate => &self.regenerate,
+            Action::CopyLastAnswer => &self.copy_last_answer,
+            Action::ToggleSettings => &self.toggle_settings,
+            Action::Search => &self.search,
+            Action::Find => &self.find,
+            Action::RecallPrompt => &self.recall_prompt,
+            Action::CheatSheet => &self.cheat_sheet,
+        }
+    }
+
+    fn get_mut(&mut self, action: Action) -> &mut Shortcut {
+        match action {
+            Action::NewChat => &mut self.new_chat,
+            Action::NextChat => &mut self.next_chat,
+            Action::PreviousChat => &mut self.previous_chat,
+            Action::FocusInput => &mut self.focus_input,
+            Action::Stop => &mut self.stop,
+            Action::Regenerate => &mut self.regenerate,
+            Action::CopyLastAnswer => &mut self.copy_last_answer,
+            Action::ToggleSettings => &mut self.toggle_settings,
+            Action::Search => &mut self.search,
+            Action::Find => &mut self.find,
+            Action::RecallPrompt => &mut self.recall_prompt,
+            Action::CheatSheet => &mut self.cheat_sheet,
+        }
+    }
+
+    /// Binds `shortcut` to `action`.
+    ///
+    /// Fails with the action already bound to it, which keeps its shortcut.
+    pub fn set(&mut self, action: Action, shortcut: Shortcut) -> Result<(), Action> {
+        if let Some(other) = Action::ALL
+            .into_iter()
+            .find(|a| *a != action && *self.get(*a) == shortcut)
+        {
+            return Err(other);
+        }
+        *self.get_mut(action) = shortcut;
+        Ok(())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Keymap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+        let mut keymap = Keymap::default();
+        for (name, value) in entries {
+            let result = if name == "send_mode" {
+                serde_json::from_value(value.clone())
+                    .map(|send_mode| keymap.send_mode = send_mode)
+                    .map_err(|_| format!("`{value}` is neither \"Enter\" nor \"CtrlEnter\""))
+            } else if let Some(action) = Action::ALL.into_iter().find(|a| a.setting() == name) {
+                match value.as_str() {
+                    Some(text) => text
+                        .parse()
+                        .map(|shortcut| *keymap.get_mut(action) = shortcut),
+                    None => Err(format!("`{value}` is not a shortcut like \"Ctrl+F\"")),
+                }
+            } else {
+                Err("there is no such shortcut".to_owned())
+            };
+            if let Err(err) = result {
+                eprintln!("ignoring the `{name}` shortcut in the settings: {err}");
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            send_mode: SendMode::Enter,
+            new_chat: Shortcut::ctrl("N"),
+            next_chat: Shortcut::ctrl("PageDown"),
+            previous_chat: Shortcut::ctrl("PageUp"),
+            focus_input: Shortcut::ctrl("L"),
+            stop: Shortcut::key("Escape"),
+            regenerate: Shortcut::ctrl_shift("R"),
+            copy_last_answer: Shortcut::ctrl_shift("C"),
+            toggle_settings: Shortcut::ctrl("B"),
+            search: Shortcut::ctrl_shift("F"),
+            find: Shortcut::ctrl("F"),
+            recall_prompt: Shortcut::ctrl("R"),
+            cheat_sheet: Shortcut::key("F1"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enter_sends_and_shift_enter_starts_a_new_line() {
+        assert!(SendMode::Enter.sends(false, false));
+        assert!(SendMode::Enter.sends(true, false));
+        assert!(!SendMode::Enter.sends(false, true));
+        assert!(!SendMode::Enter.sends(true, true));
+    }
+
+    #[test]
+    fn ctrl_enter_sends_and_enter_starts_a_new_line() {
+        assert!(!SendMode::CtrlEnter.sends(false, false));
+        assert!(SendMode::CtrlEnter.sends(true, false));
+        assert!(!SendMode::CtrlEnter.sends(false, true));
+        assert!(!SendMode::CtrlEnter.sends(true, true));
+    }
+    #[test]
+    fn shortcuts_are_read_with_any_modifier_names() {
+        assert_eq!("F1".parse(), Ok(Shortcut::key("F1")));
+        assert_eq!("Ctrl+N".parse(), Ok(Shortcut::ctrl("N")));
+        assert_eq!("cmd + shift + F".parse(), Ok(Shortcut::ctrl_shift("F")));
+        assert_eq!(
+            "Option+Command+Up".parse(),
+            Ok(Shortcut {
+                alt: true,
+                ..Shortcut::ctrl("Up")
+            })
+        );
+    }
+
+    #[test]
+    fn shortcuts_are_written_as_they_are_read() {
+        for text in [
+            "Escape",
+            "Ctrl+PageDown",
+            "Alt+Shift+1",
+            "Ctrl+Alt+Shift+F5",
+        ] {
+            let shortcut: Shortcut = text.parse().unwrap();
+            assert_eq!(shortcut.to_string(), text);
+        }
+        let keymap = Keymap::default();
+        for action in Action::ALL {
+            let shortcut = keymap.get(action);
+            assert_eq!(shortcut.to_string().parse().as_ref(), Ok(shortcut));
+        }
+    }
+
+    #[test]
+    fn shortcuts_without_a_key_or_with_unknown_modifiers_are_refused() {
+        assert_eq!(Shortcut::from_str(""), Err("`` names no key".to_owned()));
+        assert_eq!(
+            Shortcut::from_str("Ctrl+"),
+            Err("`Ctrl+` names no key".to_owned())
+        );
+        assert_eq!(
+            Shortcut::from_str("Hyper+K"),
+            Err("unknown modifier `Hyper` in `Hyper+K`".to_owned())
+        );
+    }
+
+    #[test]
+    fn shortcuts_bound_to_another_action_are_refused() {
+        let mut keymap = Keymap::default();
+        assert_eq!(
+            keymap.set(Action::Find, Shortcut::ctrl_shift("F")),
+            Err(Action::Search)
+        );
+        assert_eq!(keymap, Keymap::default());
+
+        assert_eq!(keymap.set(Action::Find, Shortcut::ctrl("F")), Ok(()));
+        assert_eq!(keymap.set(Action::Find, Shortcut::ctrl("G")), Ok(()));
+        assert_eq!(keymap.find, Shortcut::ctrl("G"));
+        assert_eq!(keymap.set(Action::Search, Shortcut::ctrl("F")), Ok(()));
+    }
+    #[test]
+    fn keymaps_with_typos_keep_the_default_of_those_entries() {
+        let keymap: Keymap = toml::from_str(
+            r#"
+            send_mode = "CtrlEnter"
+            find = "Ctrl+Shft+F"
+            search = "Alt+S"
+            stop = 3
+            mystery = "Ctrl+M"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            keymap,
+            Keymap {
+                send_mode: SendMode::CtrlEnter,
+                search: "Alt+S".parse().unwrap(),
+                ..Keymap::default()
+            }
+        );
+
+        let keymap: Keymap = toml::from_str(r#"send_mode = "Space""#).unwrap();
+        assert_eq!(keymap, Keymap::default());
+    }
+
+    #[test]
+    fn keymaps_are_read_as_they_are_written() {
+        let mut keymap = Keymap {
+            send_mode: SendMode::CtrlEnter,
+            ..Keymap::default()
+        };
+        keymap.set(Action::Find, "Ctrl+G".parse().unwrap()).unwrap();
+        let text = toml::to_string(&keymap).unwrap();
+        assert_eq!(toml::from_str::<Keymap>(&text).unwrap(), keymap);
+    }
+}
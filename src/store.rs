@@ -0,0 +1,314 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use chatgpt::types::Role;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::settings;
+
+/// A message of a conversation, stored files without timestamps still load
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// unix seconds, 0 when unknown
+    #[serde(default)]
+    pub timestamp: u64,
+    /// `data:` URLs of the images sent with the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    /// local tools an assistant message asked to run, with their results
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// passages of local documents the reply was given with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+}
+
+impl Message {
+    pub fn new(role: Role, content: String) -> Self {
+        Self {
+            role,
+            content,
+            timestamp: now(),
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            citations: Vec::new(),
+        }
+    }
+}
+
+/// A call of a local tool requested by the model
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// the model's id of the call, sent back with the result
+    pub id: String,
+    pub name: String,
+    /// JSON object of the arguments as written by the model
+    pub arguments: String,
+    /// `None` until the tool ran or the user declined it
+    #[serde(default)]
+    pub result: Option<String>,
+}
+
+/// A passage of a local document sent along with a prompt
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Citation {
+    pub path: PathBuf,
+    /// first line of the passage, counting from 1
+    pub start_line: usize,
+    /// last line of the passage, included
+    pub end_line: usize,
+}
+
+impl Citation {
+    /// `file.md:12-40`
+    pub fn label(&self) -> String {
+        let name = self
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        format!("{name}:{}-{}", self.start_line, self.end_line)
+    }
+
+    /// `/path/to/file.md:12-40`
+    pub fn location(&self) -> String {
+        format!(
+            "{}:{}-{}",
+            self.path.display(),
+            self.start_line,
+            self.end_line
+        )
+    }
+}
+
+/// A saved chat, one JSON file per conversation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredConversation {
+    pub id: String,
+    pub title: String,
+    /// name of the profile the conversation was created under
+    pub profile: String,
+    /// name of the role whose prompt starts the conversation
+    pub role: String,
+    /// model the conversation is answered with, empty for files saved before it was recorded
+    #[serde(default)]
+    pub model: String,
+    /// sampling temperature set with `/temp`, `None` for the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// unix seconds
+    pub created: u64,
+    /// unix seconds
+    pub updated: u64,
+    /// All the messages sent and received, starting with the role's system message
+    pub messages: Vec<Message>,
+}
+
+impl StoredConversation {
+    pub fn new(profile: &str, role: &settings::Role) -> Self {
+        let now = now();
+        Self {
+            // millis keep ids unique enough for conversations started by hand
+            id: format!("{}", now_millis()),
+            title: String::new(),
+            profile: profile.to_owned(),
+            role: role.name.clone(),
+            model: String::new(),
+            temperature: None,
+            created: now,
+            updated: now,
+            messages: vec![Message::new(Role::System, role.prompt.clone())],
+        }
+    }
+
+    /// first user message, shortened to fit into lists
+    pub fn make_title(&self) -> String {
+        let first = self
+            .messages
+            .iter()
+            .find(|m| m.role == Role::User)
+            .map(|m| m.content.trim())
+            .unwrap_or_default();
+        let line = first.lines().next().unwrap_or_default();
+        let mut title: String = line.chars().take(40).collect();
+        if title.len() < line.len() {
+            title.push('…');
+        }
+        title
+    }
+}
+
+/// The directory holding all saved conversations, shared by every front-end
+#[derive(Clone, Debug)]
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    /// conversations live next to the settings file of `app_name`
+    pub fn open(app_name: &str) -> io::Result<Self> {
+        Self::in_dir(settings::config_dir(app_name)?.join("conversations"))
+    }
+
+    /// conversations live in `dir`, which is created when missing
+    pub(crate) fn in_dir(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    /// all conversations, most recently updated first
+    pub fn list(&self) -> Vec<StoredConversation> {
+        let mut list: Vec<StoredConversation> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+                .filter_map(|e| {
+                    let content = fs::read_to_string(e.path()).ok()?;
+                    // skip files that are not conversations instead of failing the whole list
+                    serde_json::from_str(&content).ok()
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        list.sort_by_key(|c| std::cmp::Reverse(c.updated));
+        list
+    }
+
+    /// ids of all conversations with the time their file was last written
+    pub fn modified(&self) -> Vec<(String, SystemTime)> {
+        match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let path = e.path();
+                    if path.extension()? != "json" {
+                        return None;
+                    }
+                    let id = path.file_stem()?.to_str()?.to_owned();
+                    Some((id, e.metadata().ok()?.modified().ok()?))
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn load(&self, id: &str) -> io::Result<StoredConversation> {
+        let content = fs::read_to_string(self.path(id))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, conversation: &StoredConversation) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(conversation)?;
+        fs::write(self.path(&conversation.id), content)
+    }
+
+    pub fn delete(&self, id: &str) -> io::Result<()> {
+        fs::remove_file(self.path(id))
+    }
+
+    /// Moves the conversations created under the profile `from` to the profile `to`, which is
+    /// how they find their profile again after it was renamed.
+    pub fn rename_profile(&self, from: &str, to: &str) -> io::Result<()> {
+        for mut conversation in self.list() {
+            if conversation.profile == from {
+                conversation.profile = to.to_owned();
+                self.save(&conversation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// a store in an empty directory of its own, deleted when the `TempDir` is dropped
+    pub(crate) fn temp_store() -> (tempfile::TempDir, Store) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::in_dir(dir.path().to_owned()).unwrap();
+        (dir, store)
+    }
+
+    fn conversation(id: &str, updated: u64) -> StoredConversation {
+        let mut conversation =
+            StoredConversation::new("default", &settings::Profile::default().role_list[0]);
+        conversation.id = id.to_owned();
+        conversation.updated = updated;
+        conversation
+            .messages
+            .push(Message::new(Role::User, "Hi".to_owned()));
+        conversation
+    }
+
+    #[test]
+    fn saved_conversations_load_as_they_were() {
+        let (_dir, store) = temp_store();
+        let mut saved = conversation("1", 10);
+        saved.messages[1].images = vec!["data:image/png;base64,AAAA".to_owned()];
+        saved.messages.push(Message {
+            tool_calls: vec![ToolCall {
+                id: "call_1".to_owned(),
+                name: "calculator".to_owned(),
+                arguments: r#"{"expression":"1+1"}"#.to_owned(),
+                result: Some("2".to_owned()),
+            }],
+            ..Message::new(Role::Assistant, String::new())
+        });
+        store.save(&saved).unwrap();
+        assert_eq!(store.load("1").unwrap(), saved);
+    }
+
+    #[test]
+    fn lists_the_most_recently_updated_first() {
+        let (_dir, store) = temp_store();
+        for (id, updated) in [("old", 1), ("new", 3), ("middle", 2)] {
+            store.save(&conversation(id, updated)).unwrap();
+        }
+        fs::write(store.dir.join("notes.json"), "not a conversation").unwrap();
+        let ids: Vec<String> = store.list().into_iter().map(|c| c.id).collect();
+        assert_eq!(ids, ["new", "middle", "old"]);
+
+        store.delete("middle").unwrap();
+        let mut modified: Vec<String> = store.modified().into_iter().map(|(id, _)| id).collect();
+        modified.sort();
+        assert_eq!(modified, ["new", "notes", "old"]);
+        assert!(store.load("middle").is_err());
+    }
+
+    #[test]
+    fn renaming_a_profile_moves_its_conversations() {
+        let (_dir, store) = temp_store();
+        store.save(&conversation("1", 1)).unwrap();
+        let mut other = conversation("2", 2);
+        other.profile = "work".to_owned();
+        store.save(&other).unwrap();
+
+        store.rename_profile("default", "home").unwrap();
+        assert_eq!(store.load("1").unwrap().profile, "home");
+        assert_eq!(store.load("1").unwrap().updated, 1);
+        assert_eq!(store.load("2").unwrap(), other);
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// A user prompt reused often, `{{name}}` placeholders are asked for before it is sent
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Template {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    pub body: String,
+}
+
+impl Template {
+    /// the names of the placeholders, each once in the order they first appear
+    pub fn variables(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for (_, name) in placeholders(&self.body) {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_owned());
+            }
+        }
+        names
+    }
+
+    /// The body with every placeholder replaced by its value, placeholders without one stay.
+    pub fn fill(&self, values: &HashMap<String, String>) -> String {
+        let mut filled = String::new();
+        let mut last = 0;
+        for (range, name) in placeholders(&self.body) {
+            if let Some(value) = values.get(name) {
+                filled.push_str(&self.body[last..range.start]);
+                filled.push_str(value);
+                last = range.end;
+            }
+        }
+        filled.push_str(&self.body[last..]);
+        filled
+    }
+}
+
+/// Templates shared as one JSON file
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pack {
+    pub templates: Vec<Template>,
+}
+
+impl Pack {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Adds the templates of `pack` to `library`, replacing the ones with the same name.
+///
+/// Returns how many were added and how many replaced.
+pub fn merge(library: &mut Vec<Template>, pack: Pack) -> (usize, usize) {
+    let (mut added, mut replaced) = (0, 0);
+    for template in pack.templates {
+        match library.iter_mut().find(|t| t.name == template.name) {
+            Some(known) => {
+                *known = template;
+                replaced += 1;
+            }
+            None => {
+                library.push(template);
+                added += 1;
+            }
+        }
+    }
+    (added, replaced)
+}
+
+/// the templates a new settings file starts with
+pub fn defaults() -> Vec<Template> {
+    vec![
+        Template {
+            name: "Review diff".into(),
+            description: "bugs, risks and missing tests of a change".into(),
+            body: "Review this diff. Point out bugs, risky changes and missing tests, \
+                   most important first.\n\n```diff\n{{diff}}\n```"
+                .into(),
+        },
+        Template {
+            name: "Write tests".into(),
+            description: "unit tests for a piece of code".into(),
+            body: "Write unit tests with {{framework}} for the following {{language}} code. \
+                   Cover the edge cases.\n\n```\n{{code}}\n```"
+                .into(),
+        },
+    ]
+}
+
+/// byte ranges of the `{{name}}` placeholders in `body` with their trimmed names
+fn placeholders(body: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(start) = body[from..].find("{{").map(|i| from + i) {
+        let Some(end) = body[start + 2..].find("}}").map(|i| start + 2 + i) else {
+            break;
+        };
+        let inner = &body[start + 2..end];
+        let name = inner.trim();
+        // `{{ }}` and text spanning lines are not placeholders, e.g. in code
+        if name.is_empty() || inner.contains('\n') || inner.contains('{') {
+            from = start + 2;
+            continue;
+        }
+        found.push((start..end + 2, name));
+        from = end + 2;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(body: &str) -> Template {
+        Template {
+            name: "test".into(),
+            body: body.into(),
+            ..Template::default()
+        }
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn variables_are_listed_once_in_order() {
+        let template = template("{{ b }} and {{a}}, then {{b}} again");
+        assert_eq!(template.variables(), ["b", "a"]);
+        assert_eq!(defaults()[1].variables(), ["framework", "language", "code"]);
+    }
+
+    #[test]
+    fn braces_that_are_not_placeholders_are_left_alone() {
+        let template = template("{{ }} {{\nx}} {{open and fn f() { {} }");
+        assert!(template.variables().is_empty());
+        assert_eq!(template.fill(&values(&[("x", "1")])), template.body);
+    }
+
+    #[test]
+    fn fill_replaces_every_placeholder_with_a_value() {
+        let template = template("{{ b }} and {{a}}, then {{b}} again");
+        assert_eq!(
+            template.fill(&values(&[("a", "one"), ("b", "{{a}}")])),
+            "{{a}} and one, then {{a}} again"
+        );
+        assert_eq!(
+            template.fill(&values(&[("a", "one")])),
+            "{{ b }} and one, then {{b}} again"
+        );
+    }
+
+    #[test]
+    fn merge_replaces_templates_of_the_same_name() {
+        let mut library = defaults();
+        let mut changed = library[0].clone();
+        changed.body = "{{diff}}".into();
+        let pack = Pack {
+            templates: vec![changed.clone(), template("new")],
+        };
+        assert_eq!(merge(&mut library, pack), (1, 1));
+        assert_eq!(library.len(), 3);
+        assert_eq!(library[0], changed);
+    }
+}
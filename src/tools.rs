@@ -0,0 +1,519 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::{self, BoxFuture};
+use futures_util::FutureExt;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::attachment;
+use crate::settings::ToolSettings;
+use crate::store::ToolCall;
+
+/// tool output longer than this many characters is cut, it goes back into the context window
+pub const MAX_OUTPUT_CHARS: usize = 16_000;
+/// the result of a call the user declined
+pub const DECLINED: &str = "the user did not allow this call";
+/// bytes of a fetched page read at most, enough for [`MAX_OUTPUT_CHARS`] of any script
+const MAX_FETCH_BYTES: usize = MAX_OUTPUT_CHARS * 4;
+
+/// A function the model can ask to run on this machine
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    /// tells the model what the tool is for
+    fn description(&self) -> &str;
+    /// JSON schema of the arguments object
+    fn parameters(&self) -> Value;
+    /// runs the tool with the parsed arguments, errors are shown to the model as well
+    fn call(&self, arguments: Value) -> BoxFuture<'_, Result<String, String>>;
+}
+
+/// The tools advertised to the model
+#[derive(Default)]
+pub struct Registry {
+    tools: Vec<Box<dyn Tool>>,
+}
+
+impl Registry {
+    /// The built-in tools: calculator and current time, plus reading and listing files and
+    /// fetching URLs when `settings` allows any directory or host.
+    pub fn local(settings: &ToolSettings) -> Self {
+        let mut registry = Self::default();
+        registry.register(Box::new(Calculator));
+        registry.register(Box::new(CurrentTime));
+        if !settings.allowed_dirs.is_empty() {
+            registry.register(Box::new(ReadFile(settings.allowed_dirs.clone())));
+            registry.register(Box::new(ListDirectory(settings.allowed_dirs.clone())));
+        }
+        if !settings.allowed_hosts.is_empty() {
+            registry.register(Box::new(Fetch(settings.allowed_hosts.clone())));
+        }
+        registry
+    }
+
+    /// adds a tool, replacing one with the same name
+    pub fn register(&mut self, tool: Box<dyn Tool>) {
+        self.tools.retain(|t| t.name() != tool.name());
+        self.tools.push(tool);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.tools.iter().map(|t| t.name()).collect()
+    }
+
+    /// the `tools` of a chat completions request
+    pub fn definitions(&self) -> Vec<Value> {
+        self.tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name(),
+                        "description": tool.description(),
+                        "parameters": tool.parameters(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Runs the tool called `name`, the result or the error is what the model gets to see.
+    pub async fn call(&self, name: &str, arguments: &str) -> String {
+        let Some(tool) = self.tools.iter().find(|t| t.name() == name) else {
+            return format!("error: there is no tool called `{name}`");
+        };
+        let arguments = if arguments.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            match serde_json::from_str(arguments) {
+                Ok(arguments) => arguments,
+                Err(err) => return format!("error: the arguments are not valid JSON: {err}"),
+            }
+        };
+        let mut output = match tool.call(arguments).await {
+            Ok(output) => output,
+            Err(err) => format!("error: {err}"),
+        };
+        if let Some((cut, _)) = output.char_indices().nth(MAX_OUTPUT_CHARS) {
+            output.truncate(cut);
+            output.push_str("\n[output cut]");
+        }
+        output
+    }
+}
+
+/// decides whether a tool call the model asked for may run
+pub type Confirm = Arc<dyn Fn(ToolCall) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// a call and where its answer goes
+type Pending = (ToolCall, oneshot::Sender<bool>);
+
+/// Tool calls waiting for the user's answer, for front-ends asking from their render loop.
+///
+/// Clones share the same queue.
+#[derive(Clone, Default)]
+pub struct Approvals {
+    pending: Arc<Mutex<VecDeque<Pending>>>,
+}
+
+impl Approvals {
+    /// a [`Confirm`] queueing the calls here, `on_update` wakes up the front-end
+    pub fn confirm(&self, on_update: impl Fn() + Send + Sync + 'static) -> Confirm {
+        let pending = self.pending.clone();
+        Arc::new(move |call| {
+            let (tx, rx) = oneshot::channel();
+            pending.lock().unwrap().push_back((call, tx));
+            on_update();
+            // a dropped answer, e.g. when the window closes, declines
+            rx.map(|answer| answer.unwrap_or(false)).boxed()
+        })
+    }
+
+    /// the call to ask about now
+    pub fn current(&self) -> Option<ToolCall> {
+        let mut pending = self.pending.lock().unwrap();
+        // calls of a cancelled reply no longer need an answer
+        while pending.front().is_some_and(|(_, tx)| tx.is_closed()) {
+            pending.pop_front();
+        }
+        pending.front().map(|(call, _)| call.clone())
+    }
+
+    /// answers the current call
+    pub fn answer(&self, run: bool) {
+        if let Some((_, tx)) = self.pending.lock().unwrap().pop_front() {
+            let _ = tx.send(run);
+        }
+    }
+}
+
+struct Calculator;
+
+impl Tool for Calculator {
+    fn name(&self) -> &str {
+        "calculator"
+    }
+
+    fn description(&self) -> &str {
+        "Evaluates an arithmetic expression, e.g. `(3.5 + 2) * 4` or `math::sqrt(2.0)`. \
+         Integers divide as integers, write `7.0 / 2` for fractions."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": { "type": "string", "description": "the expression to evaluate" }
+            },
+            "required": ["expression"]
+        })
+    }
+
+    fn call(&self, arguments: Value) -> BoxFuture<'_, Result<String, String>> {
+        let result = string_argument(&arguments, "expression").and_then(|expression| {
+            evalexpr::eval(expression)
+                .map(|value| value.to_string())
+                .map_err(|e| e.to_string())
+        });
+        future::ready(result).boxed()
+    }
+}
+
+struct CurrentTime;
+
+impl Tool for CurrentTime {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Tells the current local date, time and time zone offset."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    fn call(&self, _arguments: Value) -> BoxFuture<'_, Result<String, String>> {
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S %:z (%A)");
+        future::ready(Ok(now.to_string())).boxed()
+    }
+}
+
+struct ReadFile(Vec<PathBuf>);
+
+impl Tool for ReadFile {
+    fn name(&self) -> &str {
+        "read_file"
+    }
+
+    fn description(&self) -> &str {
+        "Reads a UTF-8 text file from one of the directories the user allowed."
+    }
+
+    fn parameters(&self) -> Value {
+        path_parameters(&self.0)
+    }
+
+    fn call(&self, arguments: Value) -> BoxFuture<'_, Result<String, String>> {
+        let result = string_argument(&arguments, "path")
+            .and_then(|path| allowed_path(&self.0, path))
+            .and_then(|path| {
+                attachment::Attachment::read(&path)
+                    .map(|file| file.content)
+                    .map_err(|e| e.to_string())
+            });
+        future::ready(result).boxed()
+    }
+}
+
+struct ListDirectory(Vec<PathBuf>);
+
+impl Tool for ListDirectory {
+    fn name(&self) -> &str {
+        "list_directory"
+    }
+
+    fn description(&self) -> &str {
+        "Lists the entries of a directory the user allowed, or of one inside it. \
+         Directories end with `/`."
+    }
+
+    fn parameters(&self) -> Value {
+        path_parameters(&self.0)
+    }
+
+    fn call(&self, arguments: Value) -> BoxFuture<'_, Result<String, String>> {
+        let result = string_argument(&arguments, "path")
+            .and_then(|path| allowed_path(&self.0, path))
+            .and_then(|path| {
+                let mut names: Vec<String> = fs::read_dir(&path)
+                    .map_err(|e| e.to_string())?
+                    .filter_map(Result::ok)
+                    .map(|entry| {
+                        let name = entry.file_name().to_string_lossy().into_owned();
+                        match entry.file_type() {
+                            Ok(t) if t.is_dir() => format!("{name}/"),
+                            _ => name,
+                        }
+                    })
+                    .collect();
+                names.sort();
+                Ok(names.join("\n"))
+            });
+        future::ready(result).boxed()
+    }
+}
+
+struct Fetch(Vec<String>);
+
+impl Tool for Fetch {
+    fn name(&self) -> &str {
+        "fetch_url"
+    }
+
+    fn description(&self) -> &str {
+        "Downloads a web page or API response with HTTP GET and returns its text. \
+         Only some hosts are allowed."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": {
+                    "type": "string",
+                    "description": format!("http or https URL on one of these hosts: {}", self.0.join(", "))
+                }
+            },
+            "required": ["url"]
+        })
+    }
+
+    fn call(&self, arguments: Value) -> BoxFuture<'_, Result<String, String>> {
+        async move {
+            let url = allowed_url(&self.0, string_argument(&arguments, "url")?)?;
+            // no redirects, they could leave the allowed hosts
+            let client = reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .map_err(|e| e.to_string())?;
+            let mut resp = client.get(url).send().await.map_err(|e| e.to_string())?;
+            let status = resp.status();
+            // the rest would be cut anyway, and a page may never end
+            let mut body = Vec::new();
+            while body.len() < MAX_FETCH_BYTES {
+                match resp.chunk().await.map_err(|e| e.to_string())? {
+                    Some(chunk) => body.extend_from_slice(&chunk),
+                    None => break,
+                }
+            }
+            body.truncate(MAX_FETCH_BYTES);
+            let text = String::from_utf8_lossy(&body);
+            Ok(format!("HTTP {status}\n\n{text}"))
+        }
+        .boxed()
+    }
+}
+
+fn string_argument<'a>(arguments: &'a Value, name: &str) -> Result<&'a str, String> {
+    arguments[name]
+        .as_str()
+        .ok_or_else(|| format!("the `{name}` argument is missing"))
+}
+
+fn path_parameters(dirs: &[PathBuf]) -> Value {
+    let dirs: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+    json!({
+        "type": "object",
+        "properties": {
+            "path": {
+                "type": "string",
+                "description": format!("absolute path inside one of: {}", dirs.join(", "))
+            }
+        },
+        "required": ["path"]
+    })
+}
+
+/// `url` when it is an http or https URL on one of `hosts`
+fn allowed_url(hosts: &[String], url: &str) -> Result<reqwest::Url, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("{} URLs are not allowed", url.scheme()));
+    }
+    let host = url.host_str().unwrap_or_default();
+    if !hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Err(format!("{host} is not an allowed host"));
+    }
+    Ok(url)
+}
+
+/// `path` when it is inside one of `dirs`, relative paths are taken from each of them.
+///
+/// `..` is resolved before anything is read, so paths leaving the directories fail with the
+/// same error whether they exist or not. Links leaving them fail with it too.
+fn allowed_path(dirs: &[PathBuf], path: &str) -> Result<PathBuf, String> {
+    dirs.iter()
+        .find_map(|dir| {
+            let dir = normalize(&std::path::absolute(dir).ok()?)?;
+            let joined = normalize(&dir.join(path))?;
+            if !joined.starts_with(&dir) {
+                return None;
+            }
+            let resolved = joined.canonicalize().ok()?;
+            resolved
+                .starts_with(dir.canonicalize().ok()?)
+                .then_some(resolved)
+        })
+        .ok_or_else(|| format!("{path} is not an existing path in the allowed directories"))
+}
+
+/// absolute `path` with `.` and `..` taken away without looking at the disk, `None` when `..`
+/// goes above the root
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, Server};
+
+    use super::*;
+
+    /// an allowed directory with `inside.txt` next to a directory with `secret.txt`
+    fn sandbox() -> (tempfile::TempDir, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        let allowed = root.path().join("allowed");
+        fs::create_dir_all(allowed.join("sub")).unwrap();
+        fs::write(allowed.join("inside.txt"), "inside").unwrap();
+        fs::create_dir(root.path().join("outside")).unwrap();
+        fs::write(root.path().join("outside/secret.txt"), "secret").unwrap();
+        (root, allowed)
+    }
+
+    fn check(allowed: &Path, path: impl AsRef<Path>) -> Result<PathBuf, String> {
+        allowed_path(&[allowed.to_owned()], path.as_ref().to_str().unwrap())
+    }
+
+    #[test]
+    fn paths_inside_the_allowed_directories_are_allowed() {
+        let (_root, allowed) = sandbox();
+        let inside = allowed.canonicalize().unwrap().join("inside.txt");
+        assert_eq!(
+            check(&allowed, allowed.join("inside.txt")),
+            Ok(inside.clone())
+        );
+        assert_eq!(check(&allowed, "inside.txt"), Ok(inside.clone()));
+        assert_eq!(check(&allowed, "./sub/../inside.txt"), Ok(inside));
+    }
+
+    #[test]
+    fn paths_leaving_the_allowed_directories_are_refused() {
+        let (root, allowed) = sandbox();
+        for path in [
+            allowed.join("../outside/secret.txt"),
+            allowed.join("sub/../../outside/secret.txt"),
+            root.path().join("outside/secret.txt"),
+            PathBuf::from("../outside/secret.txt"),
+            PathBuf::from("/"),
+        ] {
+            assert!(check(&allowed, &path).is_err(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn refusals_do_not_tell_whether_a_path_exists() {
+        let (root, allowed) = sandbox();
+        let error = |path: PathBuf| {
+            let error = check(&allowed, &path).unwrap_err();
+            error.replace(path.to_str().unwrap(), "PATH")
+        };
+        let existing = error(root.path().join("outside/secret.txt"));
+        assert_eq!(error(root.path().join("outside/missing.txt")), existing);
+        assert_eq!(error(allowed.join("../outside/secret.txt")), existing);
+        assert_eq!(error(allowed.join("missing.txt")), existing);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_leaving_the_allowed_directories_are_refused() {
+        let (root, allowed) = sandbox();
+        std::os::unix::fs::symlink(root.path().join("outside"), allowed.join("link")).unwrap();
+        std::os::unix::fs::symlink(
+            root.path().join("outside/secret.txt"),
+            allowed.join("secret.txt"),
+        )
+        .unwrap();
+        assert!(check(&allowed, "link/secret.txt").is_err());
+        assert!(check(&allowed, "secret.txt").is_err());
+        assert!(check(&allowed, "link").is_err());
+    }
+
+    #[test]
+    fn only_http_urls_on_allowed_hosts_are_fetched() {
+        let hosts = ["docs.rs".to_owned()];
+        assert!(allowed_url(&hosts, "https://docs.rs/serde").is_ok());
+        assert!(allowed_url(&hosts, "http://DOCS.rs/").is_ok());
+        for url in [
+            "https://evil.example/docs.rs",
+            "https://docs.rs.evil.example/",
+            "https://docs.rs@evil.example/",
+            "file:///etc/passwd",
+            "ftp://docs.rs/",
+            "docs.rs",
+        ] {
+            assert!(allowed_url(&hosts, url).is_err(), "{url}");
+        }
+        assert_eq!(
+            allowed_url(&hosts, "https://evil.example/").unwrap_err(),
+            "evil.example is not an allowed host"
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_that_never_end_are_cut() {
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service_fn(
+            |_| async {
+                Ok::<_, Infallible>(service_fn(|_| async {
+                    let (mut sender, body) = Body::channel();
+                    tokio::spawn(async move {
+                        while sender.send_data("0123456789".into()).await.is_ok() {}
+                    });
+                    Ok::<_, Infallible>(Response::new(body))
+                }))
+            },
+        ));
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        let mut registry = Registry::default();
+        registry.register(Box::new(Fetch(vec!["127.0.0.1".to_owned()])));
+        let arguments = json!({ "url": url }).to_string();
+        let output = registry.call("fetch_url", &arguments).await;
+        assert!(output.starts_with("HTTP 200 OK\n\n0123456789"));
+        assert!(output.ends_with("\n[output cut]"));
+    }
+}
@@ -0,0 +1,429 @@
+use std::io;
+use std::time::Duration;
+
+use crossterm::event::{
+    self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers,
+};
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Borders, Clear, List, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use tui_textarea::TextArea;
+
+use oxidized_gpt::attachment::{self, Part};
+use oxidized_gpt::documents::Library;
+use oxidized_gpt::prompt_history::PromptHistory;
+use oxidized_gpt::session::ChatSession;
+use oxidized_gpt::settings::{self, Settings};
+use oxidized_gpt::store::{Role, Store, StoredConversation};
+
+const HELP: &str =
+    "Enter send · Alt+Enter newline · ↑/↓ history · Esc stop · Ctrl+R role · Ctrl+O chats · Ctrl+N new · PgUp/PgDn scroll · Ctrl+Q quit";
+
+/// Runs the terminal front-end until the user quits
+pub fn run(
+    settings: Settings,
+    store: Store,
+    library: Library,
+    prompt_history: PromptHistory,
+) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    // without it a pasted text arrives as key presses, sending every line on its own
+    if let Err(err) = crossterm::execute!(io::stdout(), EnableBracketedPaste) {
+        ratatui::restore();
+        return Err(err);
+    }
+    let result = Tui::new(settings, store, library, prompt_history).run(&mut terminal);
+    let disabled = crossterm::execute!(io::stdout(), DisableBracketedPaste);
+    ratatui::restore();
+    result.and(disabled)
+}
+
+enum Popup {
+    Roles(ListState),
+    Conversations(Vec<StoredConversation>, ListState),
+}
+
+struct Tui {
+    settings: Settings,
+    store: Store,
+    library: Library,
+    current_role: settings::Role,
+    session: Option<ChatSession>,
+    input: TextArea<'static>,
+    /// prompts sent before, shared with the window app
+    prompt_history: PromptHistory,
+    /// position in `prompt_history` while going through it with Up/Down, with the prompt
+    /// that was being written before
+    recalling: Option<(usize, String)>,
+    /// lines scrolled up from the bottom of the history
+    scroll: u16,
+    popup: Option<Popup>,
+    /// last error, shown in the footer until the next key press
+    status: Option<String>,
+    quit: bool,
+}
+
+impl Tui {
+    fn new(
+        settings: Settings,
+        store: Store,
+        library: Library,
+        prompt_history: PromptHistory,
+    ) -> Self {
+        let current_role = settings.current_profile().role_list[0].clone();
+        Self {
+            settings,
+            store,
+            library,
+            current_role,
+            session: None,
+            input: new_input(),
+            prompt_history,
+            recalling: None,
+            scroll: 0,
+            popup: None,
+            status: None,
+            quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            // replies stream in from a background task, so redraw on a short tick as well
+            if event::poll(Duration::from_millis(100))? {
+                match event::read()? {
+                    Event::Key(key) if key.kind == KeyEventKind::Press => self.on_key(key),
+                    Event::Paste(text) => {
+                        self.input.insert_str(text);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        self.status = None;
+        if self.popup.is_some() {
+            self.on_popup_key(key);
+            return;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::Char('n') if ctrl => self.reset_session(),
+            KeyCode::Esc => {
+                if let Some(session) = &self.session {
+                    session.cancel();
+                }
+            }
+            KeyCode::Char('r') if ctrl => {
+                let mut state = ListState::default();
+                let roles = &self.settings.current_profile().role_list;
+                state.select(roles.iter().position(|r| r == &self.current_role));
+                self.popup = Some(Popup::Roles(state));
+            }
+            KeyCode::Char('o') if ctrl => {
+                let mut state = ListState::default();
+                state.select(Some(0));
+                self.popup = Some(Popup::Conversations(self.store.list(), state));
+            }
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Enter
+                if key
+                    .modifiers
+                    .intersects(KeyModifiers::ALT | KeyModifiers::SHIFT) =>
+            {
+                self.input.insert_newline()
+            }
+            KeyCode::Char('j') if ctrl => self.input.insert_newline(),
+            KeyCode::Enter => self.submit(),
+            // on the first or last line the arrows go through the prompts sent before
+            KeyCode::Up if self.input.cursor().0 == 0 => self.recall_older(),
+            KeyCode::Down
+                if self.recalling.is_some()
+                    && self.input.cursor().0 + 1 == self.input.lines().len() =>
+            {
+                self.recall_newer()
+            }
+            _ => {
+                self.input.input(key);
+            }
+        }
+    }
+
+    fn recall_older(&mut self) {
+        let index = match &self.recalling {
+            _ if self.prompt_history.is_empty() => return,
+            None => self.prompt_history.len() - 1,
+            Some((0, _)) => return,
+            Some((i, _)) => i - 1,
+        };
+        let draft = match self.recalling.take() {
+            Some((_, draft)) => draft,
+            None => self.input.lines().join("\n"),
+        };
+        self.recalling = Some((index, draft));
+        self.set_input(&self.prompt_history.entries()[index].clone());
+    }
+
+    fn recall_newer(&mut self) {
+        let Some((i, draft)) = self.recalling.take() else {
+            return;
+        };
+        if i + 1 < self.prompt_history.len() {
+            self.recalling = Some((i + 1, draft));
+            self.set_input(&self.prompt_history.entries()[i + 1].clone());
+        } else {
+            self.set_input(&draft);
+        }
+    }
+
+    /// replaces the prompt with `text`, the cursor at its end
+    fn set_input(&mut self, text: &str) {
+        self.input = new_input();
+        self.input.insert_str(text);
+    }
+
+    fn on_popup_key(&mut self, key: KeyEvent) {
+        let len = match &self.popup {
+            Some(Popup::Roles(_)) => self.settings.current_profile().role_list.len(),
+            Some(Popup::Conversations(list, _)) => list.len(),
+            None => return,
+        };
+        let state = match self.popup.as_mut() {
+            Some(Popup::Roles(state)) | Some(Popup::Conversations(_, state)) => state,
+            None => return,
+        };
+        match key.code {
+            KeyCode::Esc => self.popup = None,
+            KeyCode::Up => state.select(Some(state.selected().unwrap_or(0).saturating_sub(1))),
+            KeyCode::Down if len > 0 => {
+                state.select(Some((state.selected().unwrap_or(0) + 1).min(len - 1)))
+            }
+            KeyCode::Enter => {
+                let selected = state.selected();
+                match (self.popup.take(), selected) {
+                    (Some(Popup::Roles(_)), Some(i)) => {
+                        let role = self.settings.current_profile().role_list[i].clone();
+                        if role != self.current_role {
+                            self.current_role = role;
+                            self.reset_session();
+                        }
+                    }
+                    (Some(Popup::Conversations(mut list, _)), Some(i)) if i < list.len() => {
+                        self.open_conversation(list.swap_remove(i));
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn reset_session(&mut self) {
+        self.session = None;
+        self.scroll = 0;
+    }
+
+    /// continue a saved conversation with the profile and role it was created with
+    fn open_conversation(&mut self, conversation: StoredConversation) {
+        if self.settings.profile(&conversation.profile).is_some() {
+            self.settings.current_profile = conversation.profile.clone();
+        }
+        let profile = self.settings.current_profile();
+        self.current_role = profile
+            .role_list
+            .iter()
+            .find(|r| r.name == conversation.role)
+            .unwrap_or(&profile.role_list[0])
+            .clone();
+        self.reset_session();
+        match ChatSession::open(&self.settings, conversation) {
+            Ok(session) => self.session = Some(self.equip(session)),
+            Err(err) => self.status = Some(err.to_string()),
+        }
+    }
+
+    /// saves the session's conversation and lets it answer from the role's documents
+    fn equip(&self, session: ChatSession) -> ChatSession {
+        session
+            .with_store(self.store.clone())
+            .with_documents(self.library.clone())
+    }
+
+    fn submit(&mut self) {
+        let pmt = self.input.lines().join("\n");
+        if pmt.trim().is_empty() {
+            return;
+        }
+        if self.session.is_none() {
+            match ChatSession::new(&self.settings, &self.current_role) {
+                Ok(session) => self.session = Some(self.equip(session)),
+                Err(err) => {
+                    self.status = Some(err.to_string());
+                    return;
+                }
+            }
+        }
+        let session = self.session.as_ref().unwrap();
+        if session.is_busy() {
+            return;
+        }
+        session.send(pmt.trim().to_owned(), || {});
+        self.recalling = None;
+        if let Err(err) = self.prompt_history.push(&pmt) {
+            self.status = Some(format!("failed to save the prompt history: {err}"));
+        }
+        self.input = new_input();
+        self.scroll = 0;
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let input_height = (self.input.lines().len() as u16).clamp(1, 8) + 2;
+        let [history_area, input_area, help_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(input_height),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_history(frame, history_area);
+
+        self.input.set_block(Block::default().borders(Borders::ALL));
+        frame.render_widget(&self.input, input_area);
+
+        let help = match &self.status {
+            Some(status) => Line::from(status.clone()).red(),
+            None => Line::from(HELP).dark_gray(),
+        };
+        frame.render_widget(help, help_area);
+
+        match &mut self.popup {
+            Some(Popup::Roles(state)) => {
+                let items: Vec<String> = self
+                    .settings
+                    .current_profile()
+                    .role_list
+                    .iter()
+                    .map(|r| r.name.clone())
+                    .collect();
+                draw_popup(frame, " Role ", items, state);
+            }
+            Some(Popup::Conversations(list, state)) => {
+                let items: Vec<String> = list
+                    .iter()
+                    .map(|c| format!("{}  ({} / {})", c.title, c.profile, c.role))
+                    .collect();
+                draw_popup(frame, " Conversations ", items, state);
+            }
+            None => {}
+        }
+    }
+
+    fn draw_history(&mut self, frame: &mut Frame, area: Rect) {
+        let (messages, title, busy) = match &self.session {
+            Some(session) => {
+                let conversation = session.conversation();
+                (session.history(), conversation.title, session.is_busy())
+            }
+            None => (Vec::new(), String::new(), false),
+        };
+
+        let mut text = Text::default();
+        // the first message is the role setting message
+        for msg in messages.iter().skip(1) {
+            let (name, color) = match msg.role {
+                Role::User => ("You".to_owned(), Color::Green),
+                Role::Assistant => (self.current_role.name.clone(), Color::Cyan),
+                Role::System => ("System".to_owned(), Color::Red),
+            };
+            text.push_line(
+                Line::from(name).style(Style::default().fg(color).add_modifier(Modifier::BOLD)),
+            );
+            for part in attachment::split(&msg.content) {
+                match part {
+                    Part::Text(content) => {
+                        for line in content.lines() {
+                            text.push_line(Line::from(line.to_owned()));
+                        }
+                    }
+                    Part::Attachment { name, content } => text.push_line(
+                        Line::from(format!("📄 {name} ({} lines)", content.lines().count()))
+                            .dark_gray(),
+                    ),
+                }
+            }
+            for _ in msg.images.iter() {
+                text.push_line(Line::from("🖼 image").dark_gray());
+            }
+            // tool calls of conversations continued from the window, collapsed to one line
+            for call in msg.tool_calls.iter() {
+                let result = call.result.as_deref().unwrap_or("…");
+                let result = result.lines().next().unwrap_or_default();
+                text.push_line(
+                    Line::from(format!("🛠 {}({}) → {result}", call.name, call.arguments))
+                        .dark_gray(),
+                );
+            }
+            for (i, citation) in msg.citations.iter().enumerate() {
+                text.push_line(
+                    Line::from(format!("📚 [{}] {}", i + 1, citation.label())).dark_gray(),
+                );
+            }
+            text.push_line(Line::default());
+        }
+
+        let mut block_title = format!(
+            " {} · {} ",
+            self.settings.current_profile, self.current_role.name
+        );
+        if !title.is_empty() {
+            block_title.push_str(&format!("· {title} "));
+        }
+        if busy {
+            block_title.push_str("· … ");
+        }
+        let block = Block::default().borders(Borders::ALL).title(block_title);
+
+        let paragraph = Paragraph::new(text).wrap(Wrap { trim: false });
+        let height = area.height.saturating_sub(2);
+        let total = paragraph.line_count(area.width.saturating_sub(2)) as u16;
+        let max_scroll = total.saturating_sub(height);
+        self.scroll = self.scroll.min(max_scroll);
+        let paragraph = paragraph.block(block).scroll((max_scroll - self.scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+}
+
+fn new_input() -> TextArea<'static> {
+    let mut input = TextArea::default();
+    input.set_cursor_line_style(Style::default());
+    input.set_placeholder_text("Enter to send");
+    input
+}
+
+fn draw_popup(frame: &mut Frame, title: &str, items: Vec<String>, state: &mut ListState) {
+    let height = (items.len() as u16 + 2).min(frame.area().height);
+    let [area] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(frame.area());
+    let [area] = Layout::horizontal([Constraint::Percentage(60)])
+        .flex(Flex::Center)
+        .areas(area);
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title.to_owned()),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(list, area, state);
+}